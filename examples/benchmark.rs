@@ -0,0 +1,153 @@
+//! 在几种工作负载下测试所有行的组合。
+//!
+//! ```shell
+//! cargo run --release --example benchmark --features std -- -w power_law -n 200000
+//! ```
+//!
+//! 每种组合执行同一串操作，报告吞吐量、分配和回收延迟的分位数、失败次数和执行过程中的外部碎片率。
+//! 碎片率在执行过程中均匀采样，用试探分配测量，不计入耗时。
+
+mod common;
+
+use common::{Arena, Op, Visit, Workload, for_each_combination, fragmentation};
+use customizable_buddy::{BuddyCollection, OligarchyCollection, TakePolicy};
+use std::{fmt, process::exit, ptr::NonNull, time::Instant};
+
+const USAGE: &str = "\
+usage: benchmark [-w <workload>]... [-n <ops>] [-s <seed>] [-p <policy>] [-m <MiB>]
+
+workload: random | power_law | aligned | prod_cons (default: all)
+policy:   lowest | highest | lifo (default: lowest)";
+
+/// 碎片率的采样次数。
+const SAMPLES: usize = 10;
+
+fn main() {
+    let mut workloads = Vec::new();
+    let mut ops = 100_000;
+    let mut seed = 1;
+    let mut policy = TakePolicy::Lowest;
+    let mut mib = 64;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "-w" => workloads.push(Workload::parse(&value()).unwrap_or_else(|| usage())),
+            "-n" => ops = value().parse().unwrap_or_else(|_| usage()),
+            "-s" => seed = value().parse().unwrap_or_else(|_| usage()),
+            "-m" => mib = value().parse().unwrap_or_else(|_| usage()),
+            "-p" => {
+                policy = match value().as_str() {
+                    "lowest" => TakePolicy::Lowest,
+                    "highest" => TakePolicy::Highest,
+                    "lifo" => TakePolicy::Lifo,
+                    _ => usage(),
+                }
+            }
+            _ => usage(),
+        }
+    }
+    if workloads.is_empty() {
+        workloads.extend(Workload::ALL);
+    }
+
+    let arena = Arena::new(mib << 20);
+    println!("{mib} MiB, {ops} ops, seed {seed}, {policy:?}");
+    for workload in workloads {
+        // 存活的字节数保持在容量的 3/4 以下
+        let ops = workload.generate(seed, ops, arena.len() / 4 * 3);
+        println!();
+        println!("{} ({} ops)", workload.name(), ops.len());
+        println!(
+            "{:<24}{:>10}  {:>22}  {:>22}{:>8}  fragmentation over time",
+            "combination", "Mops/s", "alloc p50/p99/p99.9", "free p50/p99/p99.9", "fail"
+        );
+        for_each_combination(&mut Bench {
+            arena: &arena,
+            ops: &ops,
+            policy,
+        });
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    exit(2)
+}
+
+/// 在一种组合上执行操作序列。
+struct Bench<'a> {
+    arena: &'a Arena,
+    ops: &'a [Op],
+    policy: TakePolicy,
+}
+
+impl Visit for Bench<'_> {
+    fn visit<O, B>(&mut self, oligarchy: &'static str, line: &'static str)
+    where
+        O: OligarchyCollection + fmt::Debug,
+        B: BuddyCollection + fmt::Debug,
+    {
+        let mut allocator = self.arena.allocator::<O, B>(self.policy);
+        let mut blocks = vec![None::<(NonNull<u8>, usize)>; self.ops.len()];
+        let mut alloc_ns = Vec::with_capacity(self.ops.len());
+        let mut free_ns = Vec::with_capacity(self.ops.len());
+        let mut failed = 0;
+        let mut frag = Vec::with_capacity(SAMPLES);
+        let interval = (self.ops.len() / SAMPLES).max(1);
+
+        for (i, op) in self.ops.iter().enumerate() {
+            match *op {
+                Op::Allocate {
+                    id,
+                    align_order,
+                    size,
+                } => {
+                    let t = Instant::now();
+                    let ans = allocator.allocate::<u8>(align_order, size);
+                    alloc_ns.push(t.elapsed().as_nanos() as u64);
+                    match ans {
+                        Ok(block) => blocks[id] = Some(block),
+                        Err(_) => failed += 1,
+                    }
+                }
+                Op::Deallocate { id } => {
+                    // 分配失败的块不回收
+                    if let Some((ptr, size)) = blocks[id].take() {
+                        let t = Instant::now();
+                        allocator.deallocate(ptr, size);
+                        free_ns.push(t.elapsed().as_nanos() as u64);
+                    }
+                }
+            }
+            if frag.len() < SAMPLES && (i + 1) % interval == 0 {
+                frag.push(fragmentation(&mut allocator));
+            }
+        }
+        assert_eq!(allocator.free(), allocator.capacity());
+
+        let total = alloc_ns.iter().chain(&free_ns).sum::<u64>();
+        let throughput = (alloc_ns.len() + free_ns.len()) as f64 * 1e3 / total.max(1) as f64;
+        let frag = frag
+            .iter()
+            .map(|f| format!("{:>3.0}%", f * 100.0))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{:<24}{throughput:>10.2}  {:>22}  {:>22}{failed:>8}  {frag}",
+            format!("{oligarchy}/{line}"),
+            percentiles(&mut alloc_ns),
+            percentiles(&mut free_ns),
+        );
+    }
+}
+
+/// 格式化延迟的 50%、99% 和 99.9% 分位数，单位纳秒。
+fn percentiles(ns: &mut [u64]) -> String {
+    if ns.is_empty() {
+        return "-".into();
+    }
+    ns.sort_unstable();
+    let at = |p: f64| ns[((ns.len() - 1) as f64 * p) as usize];
+    format!("{}/{}/{}", at(0.5), at(0.99), at(0.999))
+}
@@ -0,0 +1,357 @@
+//! 示例程序的公共部分。
+//!
+//! 包括宿主内存、工作负载生成器和各种行的组合。
+//! 性能测试和碎片模拟共用这些代码。
+
+#![allow(dead_code)]
+
+use customizable_buddy::{
+    AvlBuddy, BTreeBuddy, BuddyAllocator, BuddyCollection, ExtentOligarchy, LinkedListBuddy,
+    OligarchyCollection, UsizeBuddy,
+};
+use std::{
+    alloc::{Layout, alloc_zeroed, dealloc},
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    fmt,
+    num::NonZeroUsize,
+    ptr::NonNull,
+};
+
+/// 最小阶数。
+pub const MIN_ORDER: usize = 12;
+/// 伙伴行的层数。寡头是 16 MiB。
+pub const LAYERS: usize = 12;
+
+/// 示例使用的分配器。
+pub type Allocator<O, B> = BuddyAllocator<LAYERS, O, B>;
+
+/// 从宿主取得的一块内存，对齐到寡头。
+pub struct Arena {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl Arena {
+    /// 取得 `size` 字节的内存。
+    #[inline]
+    pub fn new(size: usize) -> Self {
+        Self::aligned(size, MIN_ORDER + LAYERS)
+    }
+
+    /// 取得 `size` 字节的内存，对齐到 `align_order`。
+    pub fn aligned(size: usize, align_order: usize) -> Self {
+        let layout = Layout::from_size_align(size, 1 << align_order).unwrap();
+        let ptr = NonNull::new(unsafe { alloc_zeroed(layout) }).expect("out of host memory");
+        Self { ptr, layout }
+    }
+
+    /// 内存的起始地址。
+    #[inline]
+    pub fn ptr(&self) -> NonNull<u8> {
+        self.ptr
+    }
+
+    /// 内存的长度。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.layout.size()
+    }
+
+    /// 构造一个管理这块内存的分配器。
+    pub fn allocator<O: OligarchyCollection, B: BuddyCollection>(
+        &self,
+        policy: customizable_buddy::TakePolicy,
+    ) -> Allocator<O, B> {
+        let mut allocator = Allocator::<O, B>::new();
+        allocator.set_policy(policy);
+        allocator.init(MIN_ORDER, self.ptr);
+        unsafe { allocator.transfer(self.ptr, self.len()) };
+        allocator
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+/// xorshift64* 伪随机数发生器。
+pub struct Rng(u64);
+
+impl Rng {
+    /// 以 `seed` 为种子。
+    #[inline]
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// 下一个 64 位随机数。
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// `[0, n)` 中的随机数。
+    #[inline]
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// `[0, 1)` 中的随机数。
+    #[inline]
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// `[lo, hi]` 中对数均匀分布的随机数。
+    pub fn log_uniform(&mut self, lo: usize, hi: usize) -> usize {
+        let (lo, hi) = ((lo as f64).ln(), (hi as f64).ln());
+        (lo + (hi - lo) * self.unit()).exp() as usize
+    }
+}
+
+/// 工作负载中的一次操作。
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// 分配第 `id` 个块。
+    Allocate {
+        id: usize,
+        align_order: usize,
+        size: NonZeroUsize,
+    },
+    /// 回收第 `id` 个块。
+    Deallocate { id: usize },
+}
+
+/// 工作负载。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Workload {
+    /// 长度对数均匀分布，随机回收。
+    Random,
+    /// 寿命服从幂律分布，大多数块很快回收，少数块长期存活。
+    PowerLaw,
+    /// 整页的块，对齐要求混杂。
+    Aligned,
+    /// 生产者成批分配，消费者按先进先出的次序回收。
+    ProducerConsumer,
+}
+
+impl Workload {
+    /// 所有工作负载。
+    pub const ALL: [Self; 4] = [
+        Self::Random,
+        Self::PowerLaw,
+        Self::Aligned,
+        Self::ProducerConsumer,
+    ];
+
+    /// 名字。
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Random => "random",
+            Self::PowerLaw => "power_law",
+            Self::Aligned => "aligned",
+            Self::ProducerConsumer => "prod_cons",
+        }
+    }
+
+    /// 从名字解析。
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|w| w.name() == name)
+    }
+
+    /// 生成 `ops` 次操作，存活的字节数大致保持在 `target` 以下。
+    ///
+    /// 最后回收所有存活的块，所以返回的操作比 `ops` 多。
+    pub fn generate(self, seed: u64, ops: usize, target: usize) -> Vec<Op> {
+        let mut state = Generator {
+            rng: Rng::new(seed),
+            ops: Vec::with_capacity(ops * 2),
+            sizes: Vec::new(),
+            live: 0,
+        };
+        match self {
+            Self::Random => {
+                let mut held = Vec::new();
+                while state.ops.len() < ops {
+                    if state.live < target && (held.is_empty() || state.rng.unit() < 0.55) {
+                        let size = state.rng.log_uniform(1, 256 << 10);
+                        held.push(state.allocate(0, size));
+                    } else {
+                        let i = state.rng.below(held.len());
+                        state.deallocate(held.swap_remove(i));
+                    }
+                }
+            }
+            Self::PowerLaw => {
+                // 帕累托分布的寿命，以操作次数计
+                const ALPHA: f64 = 1.2;
+                let mut expiry = BinaryHeap::new();
+                let mut tick = 0usize;
+                while state.ops.len() < ops {
+                    tick += 1;
+                    while let Some(&Reverse((t, id))) = expiry.peek() {
+                        if t > tick && state.live < target {
+                            break;
+                        }
+                        expiry.pop();
+                        state.deallocate(id);
+                    }
+                    let size = state.rng.log_uniform(16, 64 << 10);
+                    let life = (4.0 / (1.0 - state.rng.unit()).powf(1.0 / ALPHA)) as usize;
+                    let id = state.allocate(0, size);
+                    expiry.push(Reverse((tick.saturating_add(life), id)));
+                }
+            }
+            Self::Aligned => {
+                const ALIGNS: [usize; 6] = [0, 12, 14, 16, 18, 21];
+                let mut held = Vec::new();
+                while state.ops.len() < ops {
+                    if state.live < target && (held.is_empty() || state.rng.unit() < 0.55) {
+                        let pages = state.rng.log_uniform(1, 512);
+                        let align_order = ALIGNS[state.rng.below(ALIGNS.len())];
+                        held.push(state.allocate(align_order, pages << MIN_ORDER));
+                    } else {
+                        let i = state.rng.below(held.len());
+                        state.deallocate(held.swap_remove(i));
+                    }
+                }
+            }
+            Self::ProducerConsumer => {
+                let mut queue = VecDeque::new();
+                while state.ops.len() < ops {
+                    // 生产一批
+                    for _ in 0..state.rng.below(32) + 1 {
+                        if state.live >= target {
+                            break;
+                        }
+                        let size = state.rng.log_uniform(4 << 10, 64 << 10);
+                        queue.push_back(state.allocate(0, size));
+                    }
+                    // 消费一批
+                    for _ in 0..state.rng.below(32) + 1 {
+                        match queue.pop_front() {
+                            Some(id) => state.deallocate(id),
+                            None => break,
+                        }
+                    }
+                }
+            }
+        }
+        // 回收所有存活的块
+        let live = (0..state.sizes.len())
+            .filter(|&id| state.sizes[id] != 0)
+            .collect::<Vec<_>>();
+        live.into_iter().for_each(|id| state.deallocate(id));
+        state.ops
+    }
+}
+
+/// 生成操作序列时的状态。
+struct Generator {
+    rng: Rng,
+    ops: Vec<Op>,
+    /// 每个块按页取整的长度，回收后置 0。
+    sizes: Vec<usize>,
+    /// 存活的字节数。
+    live: usize,
+}
+
+impl Generator {
+    fn allocate(&mut self, align_order: usize, size: usize) -> usize {
+        let id = self.sizes.len();
+        let size = NonZeroUsize::new(size.max(1)).unwrap();
+        let mask = (1 << MIN_ORDER) - 1;
+        let pages = (size.get() + mask) & !mask;
+        self.sizes.push(pages);
+        self.live += pages;
+        self.ops.push(Op::Allocate {
+            id,
+            align_order,
+            size,
+        });
+        id
+    }
+
+    fn deallocate(&mut self, id: usize) {
+        self.live -= core::mem::take(&mut self.sizes[id]);
+        self.ops.push(Op::Deallocate { id });
+    }
+}
+
+/// 访问一种行的组合。
+pub trait Visit {
+    /// 以 `O` 为寡头行、`B` 为伙伴行。
+    fn visit<O, B>(&mut self, oligarchy: &'static str, line: &'static str)
+    where
+        O: OligarchyCollection + fmt::Debug,
+        B: BuddyCollection + fmt::Debug;
+}
+
+/// 访问所有组合。
+///
+/// 位图行每行只能容纳 64 个块，容纳不下示例使用的内存，只作为寡头行。
+pub fn for_each_combination(v: &mut impl Visit) {
+    with_lines::<UsizeBuddy>("bitmap", v);
+    with_lines::<LinkedListBuddy>("linked_list", v);
+    with_lines::<AvlBuddy>("avl", v);
+    with_lines::<ExtentOligarchy>("extent", v);
+    with_lines::<BTreeBuddy>("btree", v);
+}
+
+fn with_lines<O: OligarchyCollection + fmt::Debug>(name: &'static str, v: &mut impl Visit) {
+    v.visit::<O, LinkedListBuddy>(name, "linked_list");
+    v.visit::<O, AvlBuddy>(name, "avl");
+    v.visit::<O, BTreeBuddy>(name, "btree");
+}
+
+/// 当前能分配到的最大的块的长度。
+///
+/// 逐阶试探分配并立即回收。大于寡头的块按寡头数量二分查找。
+pub fn largest_free<O: OligarchyCollection, B: BuddyCollection>(
+    allocator: &mut Allocator<O, B>,
+) -> usize {
+    let max_order = MIN_ORDER + LAYERS;
+    let oligarchs = allocator.free() >> max_order;
+    let mut fits =
+        |size: usize| match NonZeroUsize::new(size).map(|size| allocator.allocate::<u8>(0, size)) {
+            Some(Ok((ptr, size))) => {
+                allocator.deallocate(ptr, size);
+                true
+            }
+            _ => false,
+        };
+    if fits(1 << max_order) {
+        // 寡头数量
+        let (mut lo, mut hi) = (1, oligarchs + 1);
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            if fits(mid << max_order) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        lo << max_order
+    } else {
+        (MIN_ORDER..max_order)
+            .rev()
+            .map(|order| 1 << order)
+            .find(|&size| fits(size))
+            .unwrap_or(0)
+    }
+}
+
+/// 外部碎片率：不能作为一个块分配出去的空闲容量的比例。
+pub fn fragmentation<O: OligarchyCollection, B: BuddyCollection>(
+    allocator: &mut Allocator<O, B>,
+) -> f64 {
+    match allocator.free() {
+        0 => 0.0,
+        free => 1.0 - largest_free(allocator) as f64 / free as f64,
+    }
+}
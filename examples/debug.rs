@@ -1,10 +1,9 @@
-use customizable_buddy::{
-    BuddyAllocator, BuddyCollection, BuddyLine, LinkedListBuddy, OligarchyCollection,
-};
-use std::{collections::BTreeSet, fmt, mem::MaybeUninit, ptr::NonNull};
+use customizable_buddy::{BTreeBuddy, BuddyAllocator};
+use std::ptr::NonNull;
 
+// 两种行都是非侵入式的，所以可以直接管理一段假想的地址空间
 fn main() {
-    let mut allocator = BuddyAllocator::<16, BuddySet, LinkedListBuddy>::new();
+    let mut allocator = BuddyAllocator::<16, BTreeBuddy, BTreeBuddy>::new();
     allocator.init(12, non_null(0x1000));
     println!();
     assert!(allocator.allocate_type::<usize>().is_err());
@@ -38,78 +37,3 @@ fn main() {
 fn non_null(addr: usize) -> NonNull<u8> {
     NonNull::new(addr as *mut _).unwrap()
 }
-
-struct BuddySet {
-    set: MaybeUninit<BTreeSet<usize>>,
-    order: usize,
-}
-
-impl BuddyLine for BuddySet {
-    const EMPTY: Self = Self {
-        set: MaybeUninit::uninit(),
-        order: 0,
-    };
-
-    fn init(&mut self, order: usize, base: usize) {
-        self.order = order;
-        println!("Buddies[{order}] init as base = {base} order = {order}");
-    }
-
-    fn take(&mut self, idx: usize) -> bool {
-        println!("Buddies[{}] take at {idx}", self.order);
-        unsafe { self.set.assume_init_mut() }.remove(&idx)
-    }
-}
-
-impl OligarchyCollection for BuddySet {
-    fn take_any(&mut self, align_order: usize, count: usize) -> Option<usize> {
-        println!(
-            "Buddies[{}] take {count} with align = {align_order}",
-            self.order
-        );
-        assert!(count == 1);
-        let set = unsafe { self.set.assume_init_mut() };
-        if let Some(&i) = set.iter().next() {
-            set.remove(&i);
-            Some(i)
-        } else {
-            None
-        }
-    }
-
-    fn put(&mut self, idx: usize) {
-        println!("Buddies[{}] put oligarchy at {idx}", self.order);
-
-        unsafe { self.set.assume_init_mut() }.insert(idx);
-    }
-}
-
-impl BuddyCollection for BuddySet {
-    fn take_any(&mut self, align_order: usize) -> Option<usize> {
-        println!("Buddies[{}] take 1 with align = {align_order}", self.order);
-        let set = unsafe { self.set.assume_init_mut() };
-        if let Some(&i) = set.iter().next() {
-            set.remove(&i);
-            Some(i)
-        } else {
-            None
-        }
-    }
-
-    fn put(&mut self, idx: usize) -> Option<usize> {
-        println!("Buddies[{}] put buddy at = {idx}", self.order);
-        let set = unsafe { self.set.assume_init_mut() };
-        if set.remove(&(idx ^ 1)) {
-            Some(idx >> 1)
-        } else {
-            set.insert(idx);
-            None
-        }
-    }
-}
-
-impl fmt::Debug for BuddySet {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", unsafe { self.set.assume_init_ref() })
-    }
-}
@@ -0,0 +1,435 @@
+//! 在主机上重放分配器的操作记录。
+//!
+//! ```shell
+//! # 生成一份示例记录
+//! cargo run --example replay --features std -- --demo trace.bin
+//! # 用位图寡头和 AVL 伙伴行重放
+//! cargo run --example replay --features std -- trace.bin -o bitmap -b avl
+//! ```
+//!
+//! 记录由 [`TraceRecorder`] 产生。重放时把记录中的地址平移到一块主机内存上，保持对齐不变；
+//! 如果重放的分配结果与记录不同，之后对这个块的回收会跟随重放的结果。
+//!
+//! 位图行每行只能容纳 64 个块，记录覆盖的范围超出时会在重放中途 panic。
+
+use customizable_buddy::{
+    AvlBuddy, BTreeBuddy, BuddyAllocator, BuddyCollection, ExtentOligarchy, LinkedListBuddy,
+    OligarchyCollection, SliceBuddyAllocator, TraceReader, TraceRecord, TraceRecorder, UsizeBuddy,
+};
+use std::{
+    alloc::{Layout, alloc_zeroed, dealloc},
+    collections::BTreeMap,
+    fmt,
+    num::NonZeroUsize,
+    process::exit,
+    ptr::NonNull,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "\
+usage: replay <trace> [-o <oligarchy>] [-b <line>] [--dump]
+       replay --demo <trace>
+
+oligarchy: bitmap | linked_list | avl | extent | btree (default: linked_list)
+line:      bitmap | linked_list | avl | btree (default: linked_list)";
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut trace = None;
+    let mut oligarchy = "linked_list".to_string();
+    let mut line = "linked_list".to_string();
+    let mut dump = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => oligarchy = args.next().unwrap_or_else(|| usage()),
+            "-b" => line = args.next().unwrap_or_else(|| usage()),
+            "--dump" => dump = true,
+            "--demo" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                std::fs::write(&path, demo()).unwrap();
+                println!("demo trace written to {path}");
+                return;
+            }
+            _ if trace.is_none() => trace = Some(arg),
+            _ => usage(),
+        }
+    }
+    let path = trace.unwrap_or_else(|| usage());
+
+    let bytes = std::fs::read(&path).unwrap_or_else(|e| {
+        eprintln!("failed to read {path}: {e}");
+        exit(1)
+    });
+    let records = TraceReader::new(&bytes)
+        .and_then(|reader| reader.collect::<Result<Vec<_>, _>>())
+        .unwrap_or_else(|e| {
+            eprintln!("{path}: {e}");
+            exit(1)
+        });
+
+    let report = match oligarchy.as_str() {
+        "bitmap" => with_line::<UsizeBuddy>(&line, &records, dump),
+        "linked_list" => with_line::<LinkedListBuddy>(&line, &records, dump),
+        "avl" => with_line::<AvlBuddy>(&line, &records, dump),
+        "extent" => with_line::<ExtentOligarchy>(&line, &records, dump),
+        "btree" => with_line::<BTreeBuddy>(&line, &records, dump),
+        _ => usage(),
+    };
+    println!("replayed {path} with {oligarchy}/{line}");
+    report.print();
+}
+
+fn with_line<O: OligarchyCollection + fmt::Debug>(
+    line: &str,
+    records: &[TraceRecord],
+    dump: bool,
+) -> Report {
+    match line {
+        "bitmap" => replay::<O, UsizeBuddy>(records, dump),
+        "linked_list" => replay::<O, LinkedListBuddy>(records, dump),
+        "avl" => replay::<O, AvlBuddy>(records, dump),
+        "btree" => replay::<O, BTreeBuddy>(records, dump),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    exit(2)
+}
+
+/// 一类操作的统计。
+#[derive(Default)]
+struct Stat {
+    count: usize,
+    failed: usize,
+    diverged: usize,
+    time: Duration,
+}
+
+#[derive(Default)]
+struct Report {
+    transfer: Stat,
+    allocate: Stat,
+    snatch: Stat,
+    deallocate: Stat,
+    reclaim: Stat,
+    /// 因为对应的分配在重放中失败而跳过的回收和转移。
+    skipped: usize,
+    free: usize,
+    capacity: usize,
+}
+
+impl Report {
+    fn print(&self) {
+        println!(
+            "{:<12}{:>10}{:>10}{:>10}{:>14}{:>12}",
+            "op", "count", "failed", "diverged", "total", "avg"
+        );
+        for (name, stat) in [
+            ("transfer", &self.transfer),
+            ("allocate", &self.allocate),
+            ("snatch", &self.snatch),
+            ("deallocate", &self.deallocate),
+            ("reclaim", &self.reclaim),
+        ] {
+            let avg = stat.time.checked_div(stat.count as u32).unwrap_or_default();
+            println!(
+                "{name:<12}{:>10}{:>10}{:>10}{:>14?}{:>12?}",
+                stat.count, stat.failed, stat.diverged, stat.time, avg
+            );
+        }
+        println!("skipped:  {}", self.skipped);
+        println!("free:     {:#x} / {:#x}", self.free, self.capacity);
+    }
+}
+
+/// 承载重放的主机内存，与记录中的地址模 `1 << align_order` 同余。
+struct Host {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    /// 记录地址加上它得到主机地址。
+    delta: usize,
+}
+
+impl Host {
+    fn new(lo: usize, hi: usize, align_order: usize) -> Self {
+        let align = 1usize << align_order;
+        let skew = lo & (align - 1);
+        let layout = Layout::from_size_align(hi - lo + skew, align).unwrap();
+        let ptr = NonNull::new(unsafe { alloc_zeroed(layout) }).unwrap_or_else(|| {
+            eprintln!(
+                "failed to allocate {:#x} bytes of host memory",
+                layout.size()
+            );
+            exit(1)
+        });
+        let delta = (ptr.as_ptr() as usize + skew).wrapping_sub(lo);
+        Self { ptr, layout, delta }
+    }
+
+    fn map(&self, addr: usize) -> NonNull<u8> {
+        NonNull::new(addr.wrapping_add(self.delta) as *mut u8).unwrap()
+    }
+}
+
+impl Drop for Host {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+fn replay<O: OligarchyCollection + fmt::Debug, B: BuddyCollection + fmt::Debug>(
+    records: &[TraceRecord],
+    dump: bool,
+) -> Report {
+    let Some(&TraceRecord::Init {
+        min_order,
+        base,
+        layers,
+    }) = records.first()
+    else {
+        eprintln!("trace does not start with an init record");
+        exit(1)
+    };
+
+    // 记录涉及的地址范围和最大对齐
+    let mut lo = base;
+    let mut hi = base;
+    let mut align_order = min_order + layers;
+    for record in records {
+        let (ptr, size) = match *record {
+            TraceRecord::Transfer { ptr, size }
+            | TraceRecord::Deallocate { ptr, size }
+            | TraceRecord::Reclaim { ptr, size, .. } => (ptr, size),
+            TraceRecord::Allocate {
+                align_order: a,
+                result,
+                ..
+            }
+            | TraceRecord::Snatch {
+                align_order: a,
+                result,
+                ..
+            } => {
+                align_order = align_order.max(a);
+                result.unwrap_or((base, 0))
+            }
+            TraceRecord::Init { .. } => continue,
+        };
+        lo = lo.min(ptr);
+        hi = hi.max(ptr + size);
+    }
+    let host = Host::new(lo, hi.max(lo + 1), align_order.min(30));
+
+    let mut lines = (0..layers).map(|_| B::EMPTY).collect::<Vec<_>>();
+    let mut allocator = SliceBuddyAllocator::<O, B>::new();
+    allocator.init_with_lines(min_order, host.map(base), &mut lines);
+
+    // 记录中分配到的块到重放中分配到的块的映射
+    let mut blocks = Blocks::new();
+    // 把记录中的地址换算成重放中的地址；对应的分配失败时返回 None
+    let translate = |blocks: &Blocks, ptr: usize| match blocks.range(..=ptr).next_back() {
+        Some((&start, &(len, mapped))) if ptr < start + len => {
+            mapped.map(|p| unsafe { p.add(ptr - start) })
+        }
+        _ => Some(host.map(ptr)),
+    };
+
+    let mut report = Report::default();
+    for record in &records[1..] {
+        match *record {
+            TraceRecord::Init { .. } => {
+                eprintln!("unexpected init record");
+                exit(1)
+            }
+            TraceRecord::Transfer { ptr, size } => {
+                // 转移回来的块不再是分配到的块
+                let mapped = translate(&blocks, ptr);
+                forget(&mut blocks, ptr, size);
+                let Some(ptr) = mapped else {
+                    report.skipped += 1;
+                    continue;
+                };
+                let t = Instant::now();
+                unsafe { allocator.transfer(ptr, size) };
+                report.transfer.time += t.elapsed();
+                report.transfer.count += 1;
+            }
+            TraceRecord::Reclaim {
+                ptr,
+                size,
+                reclaimed,
+            } => {
+                let Some(ptr) = translate(&blocks, ptr) else {
+                    report.skipped += 1;
+                    continue;
+                };
+                let t = Instant::now();
+                let ans = allocator.reclaim_range(ptr, size, |_, _| {});
+                report.reclaim.time += t.elapsed();
+                report.reclaim.count += 1;
+                if ans != reclaimed {
+                    report.reclaim.diverged += 1;
+                }
+            }
+            TraceRecord::Deallocate { ptr, size } => {
+                let mapped = translate(&blocks, ptr);
+                forget(&mut blocks, ptr, size);
+                let Some(ptr) = mapped else {
+                    report.skipped += 1;
+                    continue;
+                };
+                let t = Instant::now();
+                allocator.deallocate(ptr, size);
+                report.deallocate.time += t.elapsed();
+                report.deallocate.count += 1;
+            }
+            TraceRecord::Allocate {
+                align_order,
+                size,
+                result,
+            }
+            | TraceRecord::Snatch {
+                align_order,
+                size,
+                result,
+            } => {
+                let snatch = matches!(record, TraceRecord::Snatch { .. });
+                let size = NonZeroUsize::new(size).unwrap();
+                let t = Instant::now();
+                let ans = if snatch {
+                    allocator.snatch::<u8>(align_order, size)
+                } else {
+                    allocator.allocate::<u8>(align_order, size)
+                };
+                let time = t.elapsed();
+                let stat = if snatch {
+                    &mut report.snatch
+                } else {
+                    &mut report.allocate
+                };
+                stat.time += time;
+                stat.count += 1;
+                let ans = ans.ok();
+                if ans.is_none() {
+                    stat.failed += 1;
+                }
+                let expect = result.map(|(ptr, len)| (host.map(ptr), len));
+                if ans != expect {
+                    stat.diverged += 1;
+                }
+                if let Some((ptr, len)) = result {
+                    blocks.insert(ptr, (len, ans.map(|(p, _)| p)));
+                }
+            }
+        }
+    }
+    report.free = allocator.free();
+    report.capacity = allocator.capacity();
+    if dump {
+        println!("{allocator:#x?}");
+    }
+    report
+}
+
+/// 记录中分配到的块的 `起始 -> (长度, 重放中分配到的块)`。
+type Blocks = BTreeMap<usize, (usize, Option<NonNull<u8>>)>;
+
+/// 移除记录中从 `[ptr, ptr + size)` 开始的块的映射。
+fn forget(blocks: &mut Blocks, ptr: usize, size: usize) {
+    let starts = blocks
+        .range(ptr..ptr.saturating_add(size))
+        .map(|(&start, _)| start)
+        .collect::<Vec<_>>();
+    for start in starts {
+        blocks.remove(&start);
+    }
+}
+
+/// 生成一份示例记录：在假想的 64 MiB 地址空间上随机分配和回收。
+fn demo() -> Vec<u8> {
+    let mut allocator =
+        BuddyAllocator::<12, BTreeBuddy, BTreeBuddy, [BTreeBuddy; 12], _>::with_observer(
+            TraceRecorder::new(Vec::new()),
+        );
+    let base = 0x8000_0000usize;
+    let ptr = NonNull::new(base as *mut u8).unwrap();
+    allocator.init(12, ptr);
+    unsafe { allocator.transfer(ptr, 64 << 20) };
+
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut held = Vec::new();
+    for _ in 0..20000 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        if seed % 5 < 3 || held.is_empty() {
+            let size = NonZeroUsize::new(((seed >> 8) % (64 << 10)) as usize + 1).unwrap();
+            let align = [0, 12, 16][(seed >> 32) as usize % 3];
+            if let Ok(block) = allocator.allocate::<u8>(align, size) {
+                held.push(block);
+            }
+        } else {
+            let (ptr, size) = held.swap_remove((seed >> 16) as usize % held.len());
+            allocator.deallocate(ptr, size);
+        }
+    }
+    allocator.observer().sink().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demo_matches() {
+        // 用记录时的行重放，结果与记录完全相同
+        let records = TraceReader::new(&demo())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let report = replay::<BTreeBuddy, BTreeBuddy>(&records, false);
+        assert_eq!(report.skipped, 0);
+        for stat in [&report.allocate, &report.deallocate, &report.reclaim] {
+            assert_eq!(stat.diverged, 0);
+        }
+        assert!(report.allocate.count > 0 && report.deallocate.count > 0);
+    }
+
+    #[test]
+    fn test_forget_freed_block() {
+        let base = 0x8000_0000;
+        let records = [
+            TraceRecord::Init {
+                min_order: 12,
+                base,
+                layers: 2,
+            },
+            TraceRecord::Transfer {
+                ptr: base,
+                size: 0x4000,
+            },
+            // 记录中成功、重放中失败的分配
+            TraceRecord::Allocate {
+                align_order: 0,
+                size: 0x8000,
+                result: Some((base, 0x8000)),
+            },
+            TraceRecord::Deallocate {
+                ptr: base,
+                size: 0x8000,
+            },
+            // 落在已回收的块中的转移不再跟随失败的分配
+            TraceRecord::Transfer {
+                ptr: base + 0x4000,
+                size: 0x4000,
+            },
+        ];
+        let report = replay::<BTreeBuddy, BTreeBuddy>(&records, false);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.transfer.count, 2);
+        assert_eq!(report.capacity, 0x8000);
+    }
+}
@@ -0,0 +1,446 @@
+//! 碎片模拟器。
+//!
+//! ```shell
+//! # 合成的工作负载，要求始终能分配 1 MiB 的块
+//! cargo run --release --example simulate --features std -- -w aligned -t 1048576 > frag.csv
+//! # 记录的工作负载，参见 TraceRecorder
+//! cargo run --release --example simulate --features std -- -r trace.bin -o extent -b avl
+//! ```
+//!
+//! 在 `SliceBuddyAllocator` 上执行工作负载，最小阶数、层数和行的类型都可以配置。
+//! 定期把空闲容量、最大空闲块和各阶空闲块的数量以 CSV 格式输出；
+//! 配置的分配目标不能满足时停止并报告当时的状态。
+//!
+//! 分配器总是尽量合并伙伴，空闲块就是空闲页按对齐分解出的最大块。
+//! 因此各阶的块数由模拟器记录的页的状态计算，不干扰分配器。
+
+mod common;
+
+use common::{Arena, Op, Workload};
+use customizable_buddy::{
+    AvlBuddy, BTreeBuddy, BuddyCollection, ExtentOligarchy, LinkedListBuddy, OligarchyCollection,
+    SliceBuddyAllocator, TakePolicy, TraceReader, TraceRecord, UsizeBuddy,
+};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    num::NonZeroUsize,
+    process::exit,
+    ptr::NonNull,
+};
+
+const USAGE: &str = "\
+usage: simulate [-w <workload> | -r <trace>] [options]
+
+  -w <workload>   random | power_law | aligned | prod_cons (default: random)
+  -r <trace>      replay the allocations recorded by TraceRecorder
+  -n <ops>        number of synthetic operations (default: 100000)
+  -s <seed>       seed of the synthetic workload (default: 1)
+  -m <MiB>        memory size (default: 64, or the memory transferred in the trace)
+  -u <percent>    live bytes the synthetic workload aims at (default: 75)
+  -k <order>      minimum order (default: 12, or the one in the trace)
+  -l <layers>     number of buddy lines (default: 12, or the one in the trace)
+  -o <oligarchy>  bitmap | linked_list | avl | extent | btree (default: linked_list)
+  -b <line>       bitmap | linked_list | avl | btree (default: linked_list)
+  -p <policy>     lowest | highest | lifo (default: lowest)
+  -t <bytes>      allocation target that must always be met
+  -a <order>      alignment of the allocation target (default: 0)
+  -e <ops>        sampling interval (default: 1/100 of the operations)
+  -c <file>       write the CSV to a file instead of stdout";
+
+/// 模拟的配置。
+struct Config {
+    min_order: usize,
+    layers: usize,
+    policy: TakePolicy,
+    size: usize,
+    target: Option<(NonZeroUsize, usize)>,
+    every: usize,
+}
+
+fn main() {
+    let mut workload = Workload::Random;
+    let mut trace = None;
+    let mut ops = 100_000;
+    let mut seed = 1;
+    let mut mib = None;
+    let mut usage_percent = 75;
+    let mut min_order = None;
+    let mut layers = None;
+    let mut oligarchy = "linked_list".to_string();
+    let mut line = "linked_list".to_string();
+    let mut policy = TakePolicy::Lowest;
+    let mut target = None;
+    let mut align_order = 0;
+    let mut every = None;
+    let mut csv = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        fn num<T: std::str::FromStr>(s: String) -> T {
+            s.parse().unwrap_or_else(|_| usage())
+        }
+        match arg.as_str() {
+            "-w" => workload = Workload::parse(&value()).unwrap_or_else(|| usage()),
+            "-r" => trace = Some(value()),
+            "-n" => ops = num(value()),
+            "-s" => seed = num(value()),
+            "-m" => mib = Some(num::<usize>(value())),
+            "-u" => usage_percent = num(value()),
+            "-k" => min_order = Some(num(value())),
+            "-l" => layers = Some(num(value())),
+            "-o" => oligarchy = value(),
+            "-b" => line = value(),
+            "-p" => {
+                policy = match value().as_str() {
+                    "lowest" => TakePolicy::Lowest,
+                    "highest" => TakePolicy::Highest,
+                    "lifo" => TakePolicy::Lifo,
+                    _ => usage(),
+                }
+            }
+            "-t" => target = Some(num::<NonZeroUsize>(value())),
+            "-a" => align_order = num(value()),
+            "-e" => every = Some(num::<usize>(value())),
+            "-c" => csv = Some(value()),
+            _ => usage(),
+        }
+    }
+
+    let (ops, recorded) = match trace {
+        Some(path) => {
+            let bytes = std::fs::read(&path).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+            let records = TraceReader::new(&bytes)
+                .and_then(|reader| reader.collect::<Result<Vec<_>, _>>())
+                .unwrap_or_else(|e| fail(format!("{path}: {e}")));
+            let (ops, recorded) =
+                from_trace(&records).unwrap_or_else(|e| fail(format!("{path}: {e}")));
+            (ops, Some(recorded))
+        }
+        None => {
+            let size = mib.unwrap_or(64) << 20;
+            (
+                workload.generate(seed, ops, size / 100 * usage_percent),
+                None,
+            )
+        }
+    };
+    let min_order = min_order.or(recorded.map(|r| r.min_order)).unwrap_or(12);
+    let layers = layers.or(recorded.map(|r| r.layers)).unwrap_or(12);
+    let size = mib
+        .map(|mib| mib << 20)
+        .or(recorded.map(|r| r.transferred))
+        .unwrap_or(64 << 20);
+    let config = Config {
+        min_order,
+        layers,
+        policy,
+        size: size.next_multiple_of(1 << min_order),
+        target: target.map(|size| (size, align_order)),
+        every: every.unwrap_or(ops.len() / 100).max(1),
+    };
+
+    let out: Box<dyn Write> = match csv {
+        Some(path) => {
+            Box::new(File::create(&path).unwrap_or_else(|e| fail(format!("{path}: {e}"))))
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    let mut out = BufWriter::new(out);
+
+    macro_rules! lines {
+        ($o:ty) => {
+            match line.as_str() {
+                "bitmap" => simulate::<$o, UsizeBuddy>(&config, &ops, &mut out),
+                "linked_list" => simulate::<$o, LinkedListBuddy>(&config, &ops, &mut out),
+                "avl" => simulate::<$o, AvlBuddy>(&config, &ops, &mut out),
+                "btree" => simulate::<$o, BTreeBuddy>(&config, &ops, &mut out),
+                _ => usage(),
+            }
+        };
+    }
+    let ok = match oligarchy.as_str() {
+        "bitmap" => lines!(UsizeBuddy),
+        "linked_list" => lines!(LinkedListBuddy),
+        "avl" => lines!(AvlBuddy),
+        "extent" => lines!(ExtentOligarchy),
+        "btree" => lines!(BTreeBuddy),
+        _ => usage(),
+    };
+    out.flush().unwrap();
+    if !ok {
+        exit(1)
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    exit(2)
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("{msg}");
+    exit(1)
+}
+
+/// 从记录中得到的参数。
+#[derive(Clone, Copy)]
+struct Recorded {
+    min_order: usize,
+    layers: usize,
+    /// 转移给分配器的总容量。
+    transferred: usize,
+}
+
+/// 把记录转换为操作序列。
+///
+/// 夺走的块转移回分配器时视为回收；其他转移只计入容量。
+fn from_trace(records: &[TraceRecord]) -> Result<(Vec<Op>, Recorded), String> {
+    let Some(&TraceRecord::Init {
+        min_order, layers, ..
+    }) = records.first()
+    else {
+        return Err("trace does not start with an init record".into());
+    };
+    let mut recorded = Recorded {
+        min_order,
+        layers,
+        transferred: 0,
+    };
+    let mut ops = Vec::new();
+    let mut blocks = BTreeMap::new();
+    let mut next = 0;
+    for record in &records[1..] {
+        match *record {
+            TraceRecord::Init { .. } => return Err("unexpected init record".into()),
+            TraceRecord::Reclaim { ptr, .. } => {
+                return Err(format!("reclaiming at {ptr:#x} is not supported"));
+            }
+            TraceRecord::Allocate {
+                align_order,
+                size,
+                result,
+            }
+            | TraceRecord::Snatch {
+                align_order,
+                size,
+                result,
+            } => {
+                let id = next;
+                next += 1;
+                let size = NonZeroUsize::new(size).ok_or("zero-sized allocation")?;
+                ops.push(Op::Allocate {
+                    id,
+                    align_order,
+                    size,
+                });
+                if let Some((ptr, len)) = result {
+                    blocks.insert(ptr, (id, len));
+                }
+            }
+            TraceRecord::Deallocate { ptr, size } | TraceRecord::Transfer { ptr, size } => {
+                match blocks.remove(&ptr) {
+                    Some((id, len)) if len == size => ops.push(Op::Deallocate { id }),
+                    Some(_) => return Err(format!("partial deallocation at {ptr:#x}")),
+                    None if matches!(record, TraceRecord::Transfer { .. }) => {
+                        recorded.transferred += size
+                    }
+                    None => return Err(format!("deallocation of unknown block at {ptr:#x}")),
+                }
+            }
+        }
+    }
+    Ok((ops, recorded))
+}
+
+/// 逐页记录的状态，用于计算各阶空闲块。
+struct Pages {
+    /// 第一页的页号。
+    base: usize,
+    /// 每页是否空闲。
+    free: Vec<bool>,
+    min_order: usize,
+    layers: usize,
+}
+
+/// 某一时刻的空闲块统计。
+struct Snapshot {
+    free: usize,
+    largest: usize,
+    /// 各阶空闲块的数量，最后一项是寡头。
+    counts: Vec<usize>,
+}
+
+impl Pages {
+    fn mark(&mut self, ptr: usize, size: usize, free: bool) {
+        let start = (ptr >> self.min_order) - self.base;
+        let end = start + (size >> self.min_order);
+        self.free[start..end].iter_mut().for_each(|p| *p = free);
+    }
+
+    /// 把每段连续的空闲页按对齐分解成块，与分配器回收时的分解相同。
+    fn snapshot(&self) -> Snapshot {
+        let mut ans = Snapshot {
+            free: 0,
+            largest: 0,
+            counts: vec![0; self.layers + 1],
+        };
+        let mut i = 0;
+        while i < self.free.len() {
+            if !self.free[i] {
+                i += 1;
+                continue;
+            }
+            let end = i + self.free[i..].iter().take_while(|&&p| p).count();
+            ans.free += (end - i) << self.min_order;
+            let mut page = self.base + i;
+            let last = self.base + end;
+            while page < last {
+                let len = last - page;
+                let order = (page.trailing_zeros() as usize).min(len.ilog2() as usize);
+                if order >= self.layers {
+                    let count = len >> self.layers;
+                    ans.counts[self.layers] += count;
+                    ans.largest = ans.largest.max(count << (self.layers + self.min_order));
+                    page += count << self.layers;
+                } else {
+                    ans.counts[order] += 1;
+                    ans.largest = ans.largest.max(1 << (order + self.min_order));
+                    page += 1 << order;
+                }
+            }
+            i = end;
+        }
+        ans
+    }
+}
+
+/// 执行模拟。目标不能满足时返回 `false`。
+fn simulate<O: OligarchyCollection, B: BuddyCollection>(
+    config: &Config,
+    ops: &[Op],
+    out: &mut impl Write,
+) -> bool {
+    let Config {
+        min_order,
+        layers,
+        policy,
+        size,
+        target,
+        every,
+    } = *config;
+
+    let arena = Arena::aligned(size, (min_order + layers).min(30));
+    let mut lines = (0..layers).map(|_| B::EMPTY).collect::<Vec<_>>();
+    let mut allocator = SliceBuddyAllocator::<O, B>::new();
+    allocator.set_policy(policy);
+    allocator.init_with_lines(min_order, arena.ptr(), &mut lines);
+    unsafe { allocator.transfer(arena.ptr(), arena.len()) };
+
+    let mut pages = Pages {
+        base: arena.ptr().as_ptr() as usize >> min_order,
+        free: vec![true; arena.len() >> min_order],
+        min_order,
+        layers,
+    };
+    let mut blocks = vec![None::<(NonNull<u8>, usize)>; ops.len()];
+    let mut failed = 0;
+    let mut peak = 0.0f64;
+
+    write!(out, "step,free,largest").unwrap();
+    (min_order..min_order + layers).for_each(|o| write!(out, ",order_{o}").unwrap());
+    writeln!(out, ",oligarchs").unwrap();
+    let mut sample = |step: usize, s: &Snapshot, out: &mut dyn Write| {
+        write!(out, "{step},{},{}", s.free, s.largest).unwrap();
+        s.counts.iter().for_each(|c| write!(out, ",{c}").unwrap());
+        writeln!(out).unwrap();
+        if s.free > 0 {
+            peak = peak.max(1.0 - s.largest as f64 / s.free as f64);
+        }
+    };
+    sample(0, &pages.snapshot(), out);
+
+    for (step, op) in ops.iter().enumerate() {
+        let step = step + 1;
+        match *op {
+            Op::Allocate {
+                id,
+                align_order,
+                size,
+            } => match allocator.allocate::<u8>(align_order, size) {
+                Ok((ptr, len)) => {
+                    pages.mark(ptr.as_ptr() as usize, len, false);
+                    blocks[id] = Some((ptr, len));
+                }
+                Err(_) => failed += 1,
+            },
+            Op::Deallocate { id } => {
+                if let Some((ptr, len)) = blocks[id].take() {
+                    allocator.deallocate(ptr, len);
+                    pages.mark(ptr.as_ptr() as usize, len, true);
+                }
+            }
+        }
+        if step % every == 0 {
+            let snapshot = pages.snapshot();
+            assert_eq!(snapshot.free, allocator.free());
+            sample(step, &snapshot, out);
+        }
+        // 试探分配目标
+        if let Some((size, align_order)) = target {
+            match allocator.allocate::<u8>(align_order, size) {
+                Ok((ptr, len)) => allocator.deallocate(ptr, len),
+                Err(_) => {
+                    let snapshot = pages.snapshot();
+                    sample(step, &snapshot, out);
+                    report(config, step, op, &snapshot, (size, align_order));
+                    return false;
+                }
+            }
+        }
+    }
+
+    let snapshot = pages.snapshot();
+    if !ops.len().is_multiple_of(every) {
+        sample(ops.len(), &snapshot, out);
+    }
+    eprintln!(
+        "{} ops, {failed} failed allocations, peak fragmentation {:.1}%, free {:#x} / {:#x}",
+        ops.len(),
+        peak * 100.0,
+        allocator.free(),
+        allocator.capacity()
+    );
+    true
+}
+
+/// 报告分配目标不能满足时的状态。
+fn report(
+    config: &Config,
+    step: usize,
+    op: &Op,
+    snapshot: &Snapshot,
+    (size, align_order): (NonZeroUsize, usize),
+) {
+    eprintln!(
+        "allocation target of {:#x} bytes aligned to order {align_order} can no longer be met",
+        size.get()
+    );
+    eprintln!("  after step {step}: {op:?}");
+    eprintln!(
+        "  free {:#x} / {:#x} bytes, largest free block {:#x} bytes",
+        snapshot.free, config.size, snapshot.largest
+    );
+    eprintln!("  free blocks by order:");
+    for (i, count) in snapshot.counts.iter().enumerate() {
+        let order = config.min_order + i;
+        let kind = if i == config.layers {
+            " (oligarchs)"
+        } else {
+            ""
+        };
+        eprintln!("    {order:>2}: {count}{kind}");
+    }
+}
@@ -0,0 +1,29 @@
+//! 差分模糊测试。
+//!
+//! 把输入解码成操作序列，施加到所有组合上。运行：
+//!
+//! ```shell
+//! cargo +nightly fuzz run differential
+//! ```
+
+#![no_main]
+
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+use common::{Op, run};
+use customizable_buddy::TakePolicy;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // 第一个字节选择策略和区域的偏斜
+    let Some((&head, data)) = data.split_first() else {
+        return;
+    };
+    let policy = match head % 3 {
+        0 => TakePolicy::Lowest,
+        1 => TakePolicy::Highest,
+        _ => TakePolicy::Lifo,
+    };
+    run(policy, head as usize / 3, &Op::decode_all(data));
+});
@@ -0,0 +1,675 @@
+use crate::{
+    BuddyCollection, BuddyError, BuddyLine, OligarchyCollection, TakePolicy,
+    bitmap::{pick, pick_run, run_mask},
+    nonzero,
+};
+use core::{
+    alloc::Layout,
+    fmt,
+    num::NonZeroUsize,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// 用一个 [`AtomicUsize`] 作为位图保存占用情况的伙伴行。
+///
+/// 位图布局与 [`UsizeBuddy`](crate::UsizeBuddy) 相同，提取和放入都是对这一个字的 CAS，
+/// 因此可以通过共享引用并发操作，参见 [`ConcurrentBuddyAllocator`]。
+///
+/// - 非侵入式
+/// - 静态分配，容量有限（最多 64 或 128 个块，取决于平台）
+/// - 无锁，每个操作都在一次成功的 CAS 处生效
+/// - 位图不记录放入次序，[`TakePolicy::Lifo`] 按 [`TakePolicy::Lowest`] 处理
+pub struct AtomicBitmapBuddy {
+    /// 位图，1 表示空闲，0 表示已分配。
+    bits: AtomicUsize,
+    /// 基序号，用于将本地索引转换为全局索引。
+    base: usize,
+    /// 取块策略。
+    policy: TakePolicy,
+}
+
+impl AtomicBitmapBuddy {
+    const SIZE: usize = usize::BITS as usize;
+
+    /// 用 `f` 更新位图，直到 CAS 成功或 `f` 放弃。
+    ///
+    /// `f` 根据当前位图返回新位图和结果。
+    #[inline]
+    fn update<T>(&self, mut f: impl FnMut(usize) -> Option<(usize, T)>) -> Option<T> {
+        let mut bits = self.bits.load(Ordering::Acquire);
+        loop {
+            let (new, ans) = f(bits)?;
+            match self
+                .bits
+                .compare_exchange_weak(bits, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break Some(ans),
+                Err(current) => bits = current,
+            }
+        }
+    }
+
+    /// 提取指定全局序号的元素，返回是否提取到。
+    #[inline]
+    pub fn take_shared(&self, idx: usize) -> bool {
+        let Some(i) = idx.checked_sub(self.base).filter(|&i| i < Self::SIZE) else {
+            return false;
+        };
+        let bit = 1usize << i;
+        self.bits.fetch_and(!bit, Ordering::AcqRel) & bit == bit
+    }
+
+    /// 提取任何一个满足 `align_order` 的元素。
+    #[inline]
+    pub fn take_any_shared(&self, align_order: usize) -> Option<usize> {
+        self.update(|bits| {
+            let i = pick(bits, self.base, align_order, self.policy)?;
+            Some((bits & !(1 << i), self.base + i))
+        })
+    }
+
+    /// 提取任何 `count` 个连续的、满足 `align_order` 的元素。
+    #[inline]
+    pub fn take_run_shared(&self, align_order: usize, count: usize) -> Option<usize> {
+        match count {
+            0 => None,
+            1 => self.take_any_shared(align_order),
+            _ if count > Self::SIZE => None,
+            _ => self.update(|bits| {
+                let i = pick_run(bits, self.base, align_order, count, self.policy)?;
+                Some((bits & !run_mask(i, count), self.base + i))
+            }),
+        }
+    }
+
+    /// 放入一个元素 `idx`，不合并。
+    #[inline]
+    pub fn put_shared(&self, idx: usize) {
+        let i = idx - self.base;
+        debug_assert!(i < Self::SIZE, "index out of bound");
+        self.bits.fetch_or(1 << i, Ordering::AcqRel);
+    }
+
+    /// 放入从 `idx` 开始的 `count` 个连续的元素，不合并。
+    #[inline]
+    pub fn put_range_shared(&self, idx: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let i = idx - self.base;
+        debug_assert!(i + count <= Self::SIZE, "index out of bound");
+        self.bits.fetch_or(run_mask(i, count), Ordering::AcqRel);
+    }
+
+    /// 提取从 `idx` 开始的 `count` 个连续的元素，返回是否全部提取到。
+    ///
+    /// 有元素不在位图中时不提取任何元素。
+    #[inline]
+    pub fn take_range_shared(&self, idx: usize, count: usize) -> bool {
+        if count == 0 {
+            return true;
+        }
+        let Some(i) = idx
+            .checked_sub(self.base)
+            .filter(|&i| i + count <= Self::SIZE)
+        else {
+            return false;
+        };
+        let mask = run_mask(i, count);
+        self.update(|bits| (bits & mask == mask).then_some((bits & !mask, ())))
+            .is_some()
+    }
+
+    /// 放入一个元素 `idx`，伙伴存在时合并。
+    ///
+    /// 检查伙伴和放入 `idx` 在同一次 CAS 中完成：
+    /// 要么伙伴被提取，返回它们在上一层的序号；要么 `idx` 被放入，返回 [`None`]。
+    #[inline]
+    pub fn put_merge_shared(&self, idx: usize) -> Option<usize> {
+        let i = idx - self.base;
+        debug_assert!(i < Self::SIZE, "index out of bound");
+        // 伙伴按全局序号确定，基序号不一定是偶数
+        let buddy = (idx ^ 1)
+            .checked_sub(self.base)
+            .filter(|&b| b < Self::SIZE)
+            .map(|b| 1usize << b);
+        self.update(|bits| match buddy {
+            Some(b) if bits & b == b => Some((bits & !b, Some(idx >> 1))),
+            _ => Some((bits | 1 << i, None)),
+        })
+        .flatten()
+    }
+}
+
+impl BuddyLine for AtomicBitmapBuddy {
+    const EMPTY: Self = Self {
+        bits: AtomicUsize::new(0),
+        base: 0,
+        policy: TakePolicy::Lowest,
+    };
+
+    #[inline]
+    fn init(&mut self, _order: usize, base: usize) {
+        self.base = base;
+    }
+
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.policy = policy;
+    }
+
+    #[inline]
+    fn take(&mut self, idx: usize) -> bool {
+        self.take_shared(idx)
+    }
+
+    #[inline]
+    fn contains(&self, idx: usize) -> bool {
+        idx.checked_sub(self.base)
+            .is_some_and(|i| i < Self::SIZE && (self.bits.load(Ordering::Acquire) >> i) & 1 == 1)
+    }
+
+    #[inline]
+    fn addressable(&self, idx: usize) -> bool {
+        idx.checked_sub(self.base).is_some_and(|i| i < Self::SIZE)
+    }
+}
+
+impl OligarchyCollection for AtomicBitmapBuddy {
+    #[inline]
+    fn take_any(&mut self, align_order: usize, count: usize) -> Option<usize> {
+        self.take_run_shared(align_order, count)
+    }
+
+    #[inline]
+    fn put(&mut self, idx: usize) {
+        self.put_shared(idx)
+    }
+
+    #[inline]
+    fn put_range(&mut self, idx: usize, count: usize) {
+        self.put_range_shared(idx, count)
+    }
+
+    #[inline]
+    fn take_range(&mut self, idx: usize, count: usize) -> bool {
+        self.take_range_shared(idx, count)
+    }
+}
+
+impl BuddyCollection for AtomicBitmapBuddy {
+    #[inline]
+    fn take_any(&mut self, align_order: usize) -> Option<usize> {
+        self.take_any_shared(align_order)
+    }
+
+    #[inline]
+    fn put(&mut self, idx: usize) -> Option<usize> {
+        self.put_merge_shared(idx)
+    }
+}
+
+impl fmt::Debug for AtomicBitmapBuddy {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:b}", self.bits.load(Ordering::Relaxed))
+    }
+}
+
+/// 可并发使用的伙伴分配器。
+///
+/// 所有行都是 [`AtomicBitmapBuddy`]，分配和回收只需要共享引用，不加锁。
+/// 初始化和设置策略仍需要独占引用。
+///
+/// # 可线性化
+///
+/// 每个块在任何时刻至多属于一处：某一行的位图，或者某个正在执行的操作。
+/// 块从行中提取和放入行都是一次 CAS，所以块的归属变化是原子的：
+///
+/// - 分配在从某一行提取到块的那次 CAS 处生效，此后块只属于这次分配；
+/// - 拆分时多出的半块逐层放回低层，每次放回都是一次独立的回收，在它的 CAS 处生效；
+/// - 回收在每一层的 CAS 中检查伙伴：伙伴在行中就和伙伴一起被提取，继续向上放回父块，
+///   否则块被放入行中。合并的每一步都是原子的，同一对伙伴只会合并一次；
+/// - 为了对齐或取整多分配的部分在分配生效之后回收。
+///
+/// 因此同一个块不会同时分给两个调用者，回收的块也不会丢失。
+/// 但拆分和合并途中的块暂时不在任何行中，此时并发的分配可能看不到它们而失败。
+/// 分配失败只表示它生效时行中没有合适的块，不保证分配器中此刻没有足够的空闲内存。
+///
+/// 空闲容量的计数不与行一起原子地更新：回收先增加计数再把块放入行中，分配从行中提取到块之后才减少计数。
+/// 所以计数只会暂时偏大，不会少于行中空闲块的总长度，也不会超过总容量。
+///
+/// 行数和容量的限制与 [`UsizeBuddy`](crate::UsizeBuddy) 相同。
+pub struct ConcurrentBuddyAllocator<const N: usize> {
+    /// 寡头行。
+    oligarchy: AtomicBitmapBuddy,
+
+    /// `buddies[i]` 管理阶数为 `min_order + i` 的内存块。
+    buddies: [AtomicBitmapBuddy; N],
+
+    /// 最小阶数。
+    min_order: usize,
+
+    /// 空闲容量。
+    free: AtomicUsize,
+
+    /// 总容量。
+    capacity: AtomicUsize,
+}
+
+impl<const N: usize> ConcurrentBuddyAllocator<N> {
+    /// 构造分配器。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            oligarchy: AtomicBitmapBuddy::EMPTY,
+            buddies: [AtomicBitmapBuddy::EMPTY; N],
+            min_order: 0,
+            free: AtomicUsize::new(0),
+            capacity: AtomicUsize::new(0),
+        }
+    }
+
+    /// 返回分配器管理的总容量。
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// 返回分配器剩余的空间容量。
+    #[inline]
+    pub fn free(&self) -> usize {
+        self.free.load(Ordering::Relaxed)
+    }
+
+    /// 最大阶数。寡头块的阶数。
+    #[inline]
+    const fn max_order(&self) -> usize {
+        self.min_order + N
+    }
+
+    /// 运行时初始化。
+    ///
+    /// 设置分配器分配的最小阶数和基址。
+    #[inline]
+    pub fn init<T>(&mut self, min_order: usize, base: NonNull<T>) {
+        assert_eq!(
+            0,
+            self.capacity(),
+            "init is not allowed after any transfering"
+        );
+
+        self.min_order = min_order;
+        let max_order = self.max_order();
+        let base = base.as_ptr() as usize;
+        self.buddies.iter_mut().enumerate().for_each(|(i, c)| {
+            let o = min_order + i;
+            c.init(o, base >> o)
+        });
+        self.oligarchy.init(max_order, base >> max_order);
+    }
+
+    /// 设置取块策略。
+    ///
+    /// 策略只决定每一行取哪个块，拆分时总是保留低半。
+    #[inline]
+    pub fn set_policy(&mut self, policy: TakePolicy) {
+        assert_eq!(
+            0,
+            self.capacity(),
+            "set_policy is not allowed after any transfering"
+        );
+
+        self.buddies.iter_mut().for_each(|b| b.set_policy(policy));
+        self.oligarchy.set_policy(policy);
+    }
+
+    /// 将一个 `ptr` 指向的长度为 `usize` 的内存块转移给分配器。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证：
+    ///
+    /// - 这个内存块没有被其他任何对象引用；
+    /// - 这个内存块和已经托管的内存块不重叠。
+    #[inline]
+    pub unsafe fn transfer<T>(&self, ptr: NonNull<T>, size: usize) {
+        self.capacity.fetch_add(size, Ordering::Relaxed);
+        self.deallocate(ptr, size)
+    }
+
+    /// 从分配器夺走一个对齐到 `align_order` 阶，长度为 `size` 的内存块。
+    #[inline]
+    pub fn snatch<T>(
+        &self,
+        align_order: usize,
+        size: NonZeroUsize,
+    ) -> Result<(NonNull<T>, usize), BuddyError> {
+        let ans = self.allocate(align_order, size);
+        if let Ok((_, size)) = ans {
+            self.capacity.fetch_sub(size, Ordering::Relaxed);
+        }
+        ans
+    }
+
+    /// 分配可容纳 `T` 对象的内存块。
+    #[inline]
+    pub fn allocate_type<T>(&self) -> Result<(NonNull<T>, usize), BuddyError> {
+        self.allocate_layout(Layout::new::<T>())
+    }
+
+    /// 分配符合 `layout` 布局的内存块。
+    #[inline]
+    pub fn allocate_layout<T>(&self, layout: Layout) -> Result<(NonNull<T>, usize), BuddyError> {
+        if let Some(size) = NonZeroUsize::new(layout.size()) {
+            self.allocate(layout.align().trailing_zeros() as _, size)
+        } else {
+            Ok((NonNull::from(self).cast(), 0))
+        }
+    }
+
+    /// 分配。
+    ///
+    /// 如果分配成功，返回一个 `(指针, 长度)` 二元组。
+    pub fn allocate<T>(
+        &self,
+        align_order: usize,
+        size: NonZeroUsize,
+    ) -> Result<(NonNull<T>, usize), BuddyError> {
+        let max_order = self.max_order();
+
+        // 要分配的容量
+        let page_mask = (1usize << self.min_order) - 1;
+        let ans_size = (size.get() + page_mask) & !page_mask;
+        // 分配的阶数
+        let size_order = nonzero(ans_size.next_power_of_two()).trailing_zeros() as usize;
+        // 分配
+        let (ptr, alloc_size) = if size_order >= max_order {
+            // 连续分配寡头
+            let count = (ans_size + (1 << max_order) - 1) >> max_order;
+            let align_offset = align_order.saturating_sub(max_order);
+            match self.oligarchy.take_run_shared(align_offset, count) {
+                Some(idx) => (idx << max_order, count << max_order),
+                None => Err(BuddyError)?,
+            }
+        } else {
+            // 分配伙伴
+            let layer0 = size_order - self.min_order;
+            let mut layer = layer0;
+            let mut idx = loop {
+                // 从寡头借
+                if layer == N {
+                    let align_offset = align_order.saturating_sub(max_order);
+                    match self.oligarchy.take_any_shared(align_offset) {
+                        Some(idx) => break idx,
+                        None => Err(BuddyError)?,
+                    }
+                }
+                // 从伙伴借
+                let align_offset = align_order.saturating_sub(self.min_order + layer);
+                match self.buddies[layer].take_any_shared(align_offset) {
+                    Some(idx) => break idx,
+                    None => layer += 1,
+                }
+            };
+            // 存回多借用的高半，它们的伙伴属于这次分配，不会合并
+            for b in self.buddies[layer0..layer].iter().rev() {
+                idx <<= 1;
+                b.put_shared(idx | 1);
+            }
+            // 完成
+            (idx << size_order, 1 << size_order)
+        };
+        // 块已经从行中取出，减少计数
+        self.free.fetch_sub(alloc_size, Ordering::Relaxed);
+        // 存回为了取整而多分配的
+        if alloc_size > ans_size {
+            self.deallocate(
+                unsafe { NonNull::new_unchecked((ptr + ans_size) as *mut u8) },
+                alloc_size - ans_size,
+            );
+        }
+        Ok((unsafe { NonNull::new_unchecked(ptr as *mut T) }, ans_size))
+    }
+
+    /// 根据布局回收。
+    ///
+    /// # Safety
+    ///
+    /// 这个方法认为 `ptr` 是根据 `layout` 分配出来的，
+    /// 因此长度不小于 `layout.size()` 并且对齐到 `self.min_order`。
+    pub unsafe fn deallocate_layout<T>(&self, ptr: NonNull<T>, layout: Layout) {
+        debug_assert!((1 << (ptr.as_ptr() as usize).trailing_zeros()) >= layout.align());
+
+        let mask = (1 << self.min_order) - 1;
+        self.deallocate(ptr, (layout.size() + mask) & !mask)
+    }
+
+    /// 回收。
+    ///
+    /// # Notice
+    ///
+    /// 调用者需要保证 `size` 对齐了分配器的最小阶数。
+    pub fn deallocate<T>(&self, ptr: NonNull<T>, size: usize) {
+        debug_assert!(
+            size.trailing_zeros() as usize >= self.min_order,
+            "size must align to minium order"
+        );
+
+        let max_order = self.max_order();
+
+        // 先增加计数再放入块，并发的分配取到这些块时计数已经包含它们
+        let free = self.free.fetch_add(size, Ordering::Relaxed) + size;
+        debug_assert!(
+            free <= self.capacity(),
+            "something wrong with the free bytes, it is larger than the capacity: {} > {}",
+            free,
+            self.capacity()
+        );
+
+        let mut ptr = ptr.as_ptr() as usize;
+        let end = ptr + size;
+        while ptr < end {
+            // 剩余长度
+            let len = nonzero(end - ptr);
+            // 指针的对齐决定最大阶数
+            let order_ptr = nonzero(ptr).trailing_zeros();
+            // 长度向下取整也决定最大阶数
+            let order_len = usize::BITS - len.leading_zeros() - 1;
+            // 实际阶数是两个最大阶数中较小的那个
+            let order = order_ptr.min(order_len) as usize;
+            // 直接释放寡头
+            if order >= max_order {
+                let idx = ptr >> max_order;
+                let count = len.get() >> max_order;
+                ptr += count << max_order;
+                self.oligarchy.put_range_shared(idx, count);
+            } else {
+                let mut idx = ptr >> order;
+                ptr += 1 << order;
+                for layer in (order - self.min_order).. {
+                    if layer == N {
+                        self.oligarchy.put_shared(idx);
+                        break;
+                    }
+                    match self.buddies[layer].put_merge_shared(idx) {
+                        Some(parent) => idx = parent,
+                        None => break,
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for ConcurrentBuddyAllocator<N> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> fmt::Debug for ConcurrentBuddyAllocator<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "ConcurrentBuddyAllocator@{:#018x}",
+            self as *const _ as usize
+        )?;
+        writeln!(f, "---------------------------------")?;
+        for (i, line) in self.buddies.iter().enumerate() {
+            writeln!(f, "{:>2}> {line:?}", self.min_order + i)?;
+        }
+        writeln!(f, "{:>2}> {:?}", self.max_order(), self.oligarchy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::{sync::Barrier, thread, vec::Vec};
+
+    #[test]
+    fn test_same_as_usize_buddy() {
+        let mut buddy = AtomicBitmapBuddy::EMPTY;
+        buddy.init(0, 7);
+
+        assert_eq!(BuddyCollection::put(&mut buddy, 7), None);
+        assert_eq!(BuddyCollection::put(&mut buddy, 9), None);
+        // 全局索引 8 和 9 是伙伴
+        assert_eq!(BuddyCollection::put(&mut buddy, 8), Some(4));
+        assert_eq!(buddy.bits.load(Ordering::Relaxed), 0b0001);
+
+        // 对齐按全局索引计算
+        buddy.bits.store(0b1111, Ordering::Relaxed);
+        assert_eq!(BuddyCollection::take_any(&mut buddy, 1), Some(8));
+        assert_eq!(OligarchyCollection::take_any(&mut buddy, 1, 2), None);
+        assert_eq!(OligarchyCollection::take_any(&mut buddy, 0, 2), Some(9));
+        assert!(buddy.contains(7) && !buddy.contains(6) && !buddy.contains(7 + 64));
+        assert!(BuddyLine::take(&mut buddy, 7));
+        assert!(!buddy.contains(7));
+        assert!(!BuddyLine::take(&mut buddy, 6));
+        assert!(!BuddyLine::take(&mut buddy, 7 + 64));
+
+        buddy.bits.store(0b1110, Ordering::Relaxed);
+        buddy.set_policy(TakePolicy::Highest);
+        assert_eq!(BuddyCollection::take_any(&mut buddy, 0), Some(10));
+
+        buddy.put_range(12, 4);
+        assert_eq!(buddy.bits.load(Ordering::Relaxed), 0b1_1110_0110);
+        assert!(!buddy.take_range(9, 4));
+        assert!(buddy.take_range(12, 3));
+        assert_eq!(buddy.bits.load(Ordering::Relaxed), 0b1_0000_0110);
+    }
+
+    #[test]
+    fn test_concurrent_merge() {
+        // 两个线程分别放入每对伙伴中的一个，每对恰好合并一次
+        for _ in 0..64 {
+            let buddy = AtomicBitmapBuddy::EMPTY;
+            let barrier = Barrier::new(2);
+            let merged = thread::scope(|s| {
+                let workers = [0, 1].map(|half| {
+                    let (buddy, barrier) = (&buddy, &barrier);
+                    s.spawn(move || {
+                        barrier.wait();
+                        (0..32)
+                            .filter_map(|k| buddy.put_merge_shared(2 * k + half))
+                            .collect::<Vec<_>>()
+                    })
+                });
+                let mut merged = workers
+                    .into_iter()
+                    .flat_map(|w| w.join().unwrap())
+                    .collect::<Vec<_>>();
+                merged.sort_unstable();
+                merged
+            });
+            assert_eq!(merged, (0..32).collect::<Vec<_>>());
+            assert_eq!(buddy.bits.load(Ordering::Relaxed), 0);
+        }
+    }
+
+    #[test]
+    fn test_concurrent_take() {
+        // 并发提取，每个块恰好被取到一次
+        let buddy = AtomicBitmapBuddy::EMPTY;
+        buddy.bits.store(usize::MAX, Ordering::Relaxed);
+        let mut taken = thread::scope(|s| {
+            let workers = (0..4)
+                .map(|_| {
+                    s.spawn(|| core::iter::from_fn(|| buddy.take_any_shared(0)).collect::<Vec<_>>())
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        taken.sort_unstable();
+        assert_eq!(taken, (0..AtomicBitmapBuddy::SIZE).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_allocator_stress() {
+        const PAGES: usize = 64;
+        #[repr(C, align(262144))]
+        struct Block([usize; PAGES * 512]);
+        static mut BLOCK: Block = Block([0; PAGES * 512]);
+
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(BLOCK).cast::<u8>()).unwrap();
+        let len = PAGES << 12;
+        let mut allocator = ConcurrentBuddyAllocator::<4>::new();
+        allocator.init(12, ptr);
+        unsafe { allocator.transfer(ptr, len) };
+        assert_eq!(allocator.free(), len);
+
+        let allocator = &allocator;
+        thread::scope(|s| {
+            for tag in 1..=4usize {
+                s.spawn(move || {
+                    let mut seed = tag as u64;
+                    let mut held = Vec::new();
+                    for _ in 0..2000 {
+                        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                        let pages = (seed >> 33) as usize % 5 + 1;
+                        let align = (seed >> 40) as usize % 3 + 12;
+                        let size = NonZeroUsize::new(pages << 12).unwrap();
+                        // 分配可能因为并发的拆分合并而暂时失败
+                        if let Ok((p, s)) = allocator.allocate::<usize>(align, size) {
+                            assert_eq!(p.as_ptr() as usize % (1 << align), 0);
+                            let words = s / size_of::<usize>();
+                            unsafe { core::slice::from_raw_parts_mut(p.as_ptr(), words) }.fill(tag);
+                            held.push((p, s));
+                        }
+                        if held.len() > 3 || (seed >> 50) & 1 == 1 {
+                            let Some((p, s)) = held.pop() else { continue };
+                            let words = s / size_of::<usize>();
+                            // 持有期间没有被其他线程改写
+                            assert!(
+                                unsafe { core::slice::from_raw_parts(p.as_ptr(), words) }
+                                    .iter()
+                                    .all(|&w| w == tag)
+                            );
+                            allocator.deallocate(p, s);
+                        }
+                        // 空闲计数可以暂时偏大，但不会回绕
+                        assert!(allocator.free() <= len);
+                    }
+                    for (p, s) in held {
+                        allocator.deallocate(p, s);
+                    }
+                });
+            }
+        });
+
+        // 所有内存都回到分配器，并且重新合并成寡头
+        assert_eq!(allocator.free(), len);
+        let size = NonZeroUsize::new(len).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!((p, s), (ptr, len));
+    }
+}
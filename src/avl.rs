@@ -38,8 +38,8 @@
 //!     IF 当前节点为空：
 //!         返回None
 //!     ELSE:
-//!         根据高度以及当前节点是否存在左右子树的情况，分成四种情况
-//!             找到距离根节点最近的叶子节点进行删除[根据节点的高度信息]
+//!         按取块策略沿最左（或最右）路径找到地址最小（或最大）的节点进行删除
+//!         回溯时更新高度并旋转，保持树的平衡
 
 // AVL 算法
 // # 静止的 AVL 树左右子树的高度差的绝对值最大为 1
@@ -72,12 +72,16 @@
 //
 // B 的高度不变但整棵树的高度降低 1。
 
-use crate::{BuddyCollection, BuddyLine, OligarchyCollection, Order};
+use crate::{BuddyCollection, BuddyLine, OligarchyCollection, Order, TakePolicy};
 use core::{fmt, ptr::NonNull};
 /// 基于平衡二叉查找树的侵入式伙伴行。
+///
+/// 树按地址排序，总是提取满足对齐要求的地址最小（或最大）的块。
+/// 树不记录放入次序，[`TakePolicy::Lifo`] 按 [`TakePolicy::Lowest`] 处理。
 pub struct AvlBuddy {
     tree: Tree,
     order: Order,
+    policy: TakePolicy,
 }
 
 /// 必须实现 [`Send`] 才能加锁。
@@ -89,6 +93,7 @@ impl BuddyLine for AvlBuddy {
     const EMPTY: Self = Self {
         tree: Tree(None),
         order: Order::new(0),
+        policy: TakePolicy::Lowest,
     };
 
     #[inline]
@@ -96,34 +101,113 @@ impl BuddyLine for AvlBuddy {
         self.order = Order::new(order);
     }
 
-    fn take(&mut self, _idx: usize) -> bool {
-        todo!()
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.policy = policy;
+    }
+
+    fn take(&mut self, idx: usize) -> bool {
+        self.order
+            .idx_to_ptr(idx)
+            .is_some_and(|ptr| self.tree.remove(ptr))
+    }
+
+    fn contains(&self, idx: usize) -> bool {
+        self.order
+            .idx_to_ptr(idx)
+            .is_some_and(|ptr| self.tree.contains(ptr))
+    }
+
+    #[inline]
+    fn addressable(&self, idx: usize) -> bool {
+        self.order.idx_to_ptr::<Node>(idx).is_some()
     }
 }
 
 impl OligarchyCollection for AvlBuddy {
-    fn take_any(&mut self, _align_order: usize, _count: usize) -> Option<usize> {
-        // 个人感觉基于寡头行的数量而言，实现这个地方没有什么效率
-        todo!()
+    fn take_any(&mut self, align_order: usize, count: usize) -> Option<usize> {
+        match count {
+            0 => None,
+            1 => BuddyCollection::take_any(self, align_order),
+            _ => {
+                let start = self.find_run(align_order, count)?;
+                for idx in start..start + count {
+                    assert!(BuddyLine::take(self, idx));
+                }
+                Some(start)
+            }
+        }
     }
 
     #[inline]
-    fn put(&mut self, _idx: usize) {
-        // 个人感觉基于寡头行的数量而言，实现这个没有效率
-        todo!()
+    fn put(&mut self, idx: usize) {
+        // 寡头不合并
+        self.tree.insert_no_merge(idx, &self.order);
+    }
+}
+
+impl AvlBuddy {
+    /// 按策略中序查找 `count` 个连续的块，第一个块的序号对齐到 `align_order`。
+    fn find_run(&self, align_order: usize, count: usize) -> Option<usize> {
+        use core::cell::Cell;
+
+        let mask = (1usize << align_order) - 1;
+        let highest = self.policy == TakePolicy::Highest;
+        // 当前连续段的另一端和上一个访问的序号
+        let edge = Cell::new(0);
+        let prev = Cell::new(None::<usize>);
+        let ans = Cell::new(0);
+        let found = |ptr| {
+            let idx = self.order.ptr_to_idx(ptr);
+            let adjacent = if highest {
+                prev.get() == Some(idx + 1)
+            } else {
+                prev.get().is_some_and(|p| p + 1 == idx)
+            };
+            if !adjacent {
+                edge.set(idx);
+            }
+            prev.set(Some(idx));
+            let start = if highest {
+                match (edge.get() + 1).checked_sub(count) {
+                    Some(start) => start & !mask,
+                    None => return false,
+                }
+            } else {
+                (edge.get() + mask) & !mask
+            };
+            ans.set(start);
+            if highest {
+                start >= idx
+            } else {
+                start + count - 1 <= idx
+            }
+        };
+        self.tree.find_in_order(!highest, &found)?;
+        Some(ans.get())
     }
 }
 
 impl BuddyCollection for AvlBuddy {
     // 从 avl_buddy 行内分配器中获取获取一个节点
-    fn take_any(&mut self, _align_order: usize) -> Option<usize> {
+    fn take_any(&mut self, align_order: usize) -> Option<usize> {
         // 默认以相同大小进行分配我感觉是比较好的，但是不排除后面修改了想法
-        // TODO 需要考虑是否进行边界判断
-        if _align_order != 0 {
-            None
+        let highest = self.policy == TakePolicy::Highest;
+        let ptr = if align_order == 0 {
+            if highest {
+                self.tree.take_max()
+            } else {
+                self.tree.take_min()
+            }
         } else {
-            self.tree.delete(&self.order)
-        }
+            // 中序查找低位有足够多 0 的序号，再从树中移除
+            let mask = (1usize << align_order) - 1;
+            let aligned = |ptr| self.order.ptr_to_idx(ptr) & mask == 0;
+            let ptr = self.tree.find_in_order(!highest, &aligned)?;
+            assert!(self.tree.remove(ptr));
+            Some(ptr)
+        };
+        ptr.map(|ptr| self.order.ptr_to_idx(ptr))
     }
 
     /// insert node into avl_buddy
@@ -223,457 +307,20 @@ struct Node {
     h: usize,
 }
 
-/// 以递归方式找到距离传入子树最大节点最近的子树节点
-///
-/// 注意：调用者需要考虑到传出节点可能存在反向子树的情况
-///     这个地方没有考虑到开始递归节点处可能在一开始就没有右子树的情况
-fn find_max(node: &NonNull<Node>) -> NonNull<Node> {
-    // 需要考虑到有左子树的情况
-    //  [A]
-    //    \
-    //     [B] (node.r)
-    //    /
-    //   [?]
-    let right = unsafe { node.as_ref().r.0 };
-    if let Some(right_node) = right {
-        if unsafe { right_node.as_ref().r.0.is_some() } {
-            // if have right and it's right
-            find_max(&right_node)
-        } else {
-            *node
-        }
-    } else {
-        *node
-        // NonNull::new(node.as_ptr()).unwrap()
-    }
-}
-
-/// 以递归方式找到并返回距离最小子树最近的子树
-///
-/// 注意：调用者需要考虑到可能传出节点存在反向子树的情况
-///     在这个地方没有考虑到开始递归节点可能一开始就没有左子树的情况
-fn find_min(node: &NonNull<Node>) -> NonNull<Node> {
-    // 需要考虑有右子树的情况
-    //    [a]
-    //    /
-    //   [b]            (min point)
-    //    \
-    //     [c]
-    let left = unsafe { node.as_ref().l.0 };
-    if let Some(left_node) = left {
-        if unsafe { left_node.as_ref().l.0.is_some() } {
-            find_min(&left_node)
-        } else {
-            *node
-        }
-    } else {
-        *node
-        // NonNull::new(node.as_ptr()).unwrap()
-    }
-}
-
 impl Tree {
     /// 向当前节点处插入一个节点
     ///
     /// 在碰到节点与伙伴节点同时存在的情况下，会删除伙伴节点并且返回false [插入失败]
     fn insert(&mut self, idx: usize, order: &Order) -> bool {
-        // 这个地方我认为目前的速度瓶颈主要出现在大量使用递归所带来的影响，但是考虑到本lab主要完成的是分配器，因此可能没有办法实现动态的内存分配，进而使用栈或者队列来实现对应操作
-
-        // 版本二：在进行插入的时候直接完成对应伙伴节点的删除工作
-        let ptr: NonNull<Node> = order.idx_to_ptr(idx).expect("block address is null");
-        match self.0 {
-            // if this node is not empty
-            Some(mut root_ptr) => {
-                let root = unsafe { root_ptr.as_mut() };
-                let buddy = order
-                    .idx_to_ptr::<Node>(idx ^ 1)
-                    .expect("buddy address is null");
-                use core::cmp::Ordering::*;
-                // use core::mem::replace;
-
-                // 找到我们需要去的方向
-                let ret = match root_ptr.cmp(&buddy) {
-                    Less => {
-                        // 向右前方前进， 并且右边子树节点为伙伴节点 => 此时做可能出现的所有删除情形的判断
-                        if root.r.0.is_some() && order.ptr_to_idx(root.r.0.unwrap()) == idx ^ 1 {
-                            // 要删除的节点
-                            let node = unsafe { root.r.0.unwrap().as_mut() };
-                            // 测试两个子树的状态
-                            match (node.l.0.is_some(), node.r.0.is_some()) {
-                                (true, true) => {
-                                    // have both left and right subtree
-                                    if node.l.height() < node.r.height() {
-                                        // right tree higher than left tree
-                                        // root:    the point you using the function
-                                        // node:    the point you want to delete
-                                        // byond:   the point byond the leaf
-                                        //  [A]   (root)    |   [A]
-                                        //  / \             |   / \
-                                        // .. [B] (node)    |  ..  [E]
-                                        //    /       \     |      /  \
-                                        //  [C](byond)[D]   |    [C]  [D]
-                                        //    \             |     \
-                                        //    [E] (leaf)    |     [F]
-                                        //     \
-                                        //      [F]
-                                        if node.r.height() == 1 {
-                                            // right subtree is leaf => delete node
-                                            let leaf = unsafe { node.r.0.unwrap().as_mut() };
-                                            leaf.l = Tree(node.l.0);
-                                            root.r = Tree(NonNull::new(leaf));
-                                            leaf.update();
-                                            root.update();
-                                        } else {
-                                            // right subtree has subtree => delete link and relink this link to it's parent then delete node
-                                            let beyond =
-                                                unsafe { find_min(&node.r.0.unwrap()).as_mut() };
-
-                                            if let Some(mut leaf) = beyond.l.0 {
-                                                // 如果 beyond 存在左节点
-                                                let leaf = unsafe { leaf.as_mut() };
-
-                                                // 如果 leaf 存在反方向节点
-                                                if leaf.r.0.is_some() {
-                                                    beyond.l = Tree(leaf.l.0);
-                                                    leaf.l = Tree(None);
-                                                } else {
-                                                    beyond.l = Tree(None);
-                                                }
-                                                leaf.l = Tree(node.l.0);
-                                                leaf.r = Tree(node.r.0);
-                                                root.r = Tree(NonNull::new(leaf));
-                                                leaf.update();
-                                                root.update();
-                                            } else {
-                                                // 将 beyond 作为 leaf 替换 node 的位置
-                                                beyond.l = Tree(node.l.0);
-                                                root.r = Tree(NonNull::new(beyond));
-                                                beyond.update();
-                                                root.update();
-                                            }
-                                        }
-                                    } else {
-                                        // left tree higher than right subtree
-                                        // root:    the point you using the function
-                                        // node:    the point you want to delete
-                                        // byond:   the point byond the leaf
-                                        // [A]   (root)     | [A]
-                                        //   \              |  \
-                                        //   [B] (node)     |   [E]
-                                        //   / \            |   / \
-                                        //  [C] [D](byond)  | [C] [D]
-                                        //      /           |       \
-                                        // (E:leaf)         |        [F]
-                                        //     \
-                                        //     [F]
-                                        if node.l.height() == 1 {
-                                            // left subtree is leaf => delete node
-                                            let leaf = unsafe { node.l.0.unwrap().as_mut() };
-                                            leaf.r = Tree(node.r.0);
-                                            root.r = Tree(NonNull::new(leaf));
-                                            leaf.update();
-                                            root.update();
-                                        } else {
-                                            #[allow(unused_variables, unused_mut, dead_code)]
-                                            // left subtree has subtree => delete link and relink this link to it's parent then delete node
-                                            let mut beyond =
-                                                unsafe { find_max(&node.l.0.unwrap()).as_mut() };
-
-                                            if let Some(mut leaf) = beyond.r.0 {
-                                                // 如果 beyond 存在右子树【这个实际上是为了弥补 find_max 中无法获取这种情况下距离最大节点最近的节点设计的】
-                                                let leaf = unsafe { leaf.as_mut() };
-
-                                                // 如果 leaf 存在反方向子树
-                                                if leaf.l.0.is_some() {
-                                                    beyond.r = Tree(leaf.l.0);
-                                                    leaf.l = Tree(None);
-                                                } else {
-                                                    beyond.r = Tree(None);
-                                                }
-                                                leaf.l = Tree(node.l.0);
-                                                leaf.r = Tree(node.r.0);
-                                                root.r = Tree(NonNull::new(leaf));
-                                                leaf.update();
-                                                root.update();
-                                            } else {
-                                                // 将 beyond 作为 leaf 替换 node 的位置
-                                                beyond.r = Tree(node.r.0);
-                                                root.r = Tree(NonNull::new(beyond));
-                                                beyond.update();
-                                                root.update();
-                                            }
-                                        }
-                                    }
-                                    node.update();
-                                    root.r.rotate();
-                                }
-                                (true, false) => {
-                                    // have left but not right
-                                    // [A] <- root   | [A]
-                                    //   \           |   \
-                                    //    [B] <- node|  [C]
-                                    //    /          |
-                                    //  [C]          |
-                                    root.r = Tree(Some(node.l.0.unwrap()));
-                                    node.l = Tree(None); // 可有可无？
-                                    root.update();
-                                }
-                                (false, true) => {
-                                    // have left but not right
-                                    // [A] <- root   | [A]
-                                    //   \           |   \
-                                    //    [B] <- node|  [C]
-                                    //      \        |
-                                    //      [C]      | 这个地方不能直接上旋转，将节点转到叶子的原因是太麻烦了
-                                    root.r = Tree(Some(node.r.0.unwrap()));
-                                    node.r = Tree(None);
-                                    root.update();
-                                }
-                                (false, false) => {
-                                    root.r = Tree(None);
-                                    root.update();
-                                }
-                            }
-                            return false;
-                        } else {
-                            // 如果前进的方向不是 buddy 节点，则以递归方式继续进行插入，一直到达对应的位置(插入于空节点)
-                            root.r.insert(idx, order)
-                        }
-                    }
-                    Equal => {
-                        // 个人感觉这个地方只可能出现在根节点处
-                        match (root.l.0.is_some(), root.r.0.is_some()) {
-                            (true, true) => {
-                                match (
-                                    root.l.height() < root.r.height(),
-                                    core::cmp::max(root.l.height(), root.r.height()),
-                                ) {
-                                    (_, 1) => {
-                                        // if both of left and right is leaf => choice left as root
-                                        let left = unsafe { root.l.0.unwrap().as_mut() };
-                                        // left.l = Tree(None);
-                                        left.r = Tree(root.r.0);
-                                        self.0 = NonNull::new(left);
-                                        left.update();
-                                    }
-                                    (true, _) => {
-                                        // right higher that left
-                                        let beyond =
-                                            unsafe { find_min(&root.r.0.unwrap()).as_mut() };
-
-                                        if let Some(mut leaf) = beyond.l.0 {
-                                            let leaf = unsafe { leaf.as_mut() };
-
-                                            if leaf.r.0.is_some() {
-                                                beyond.l = Tree(leaf.r.0);
-                                                leaf.r = Tree(None);
-                                            } else {
-                                                beyond.l = Tree(None);
-                                            }
-                                            leaf.l = Tree(root.l.0);
-                                            leaf.r = Tree(root.r.0);
-                                            self.0 = NonNull::new(leaf);
-                                            leaf.update();
-                                        } else {
-                                            // 取右边最高节点出来作为root
-                                            let right = unsafe { root.r.0.unwrap().as_mut() };
-                                            right.l = Tree(root.l.0);
-                                            self.0 = NonNull::new(right);
-                                            right.update();
-                                        }
-                                    }
-                                    (false, _) => {
-                                        // left higher than right
-                                        let beyond =
-                                            unsafe { find_max(&root.l.0.unwrap()).as_mut() };
-                                        if let Some(mut leaf) = beyond.r.0 {
-                                            let leaf = unsafe { leaf.as_mut() };
-
-                                            if leaf.l.0.is_some() {
-                                                beyond.r = Tree(leaf.l.0);
-                                                leaf.l = Tree(None);
-                                            } else {
-                                                beyond.r = Tree(None);
-                                            }
-                                            leaf.l = Tree(root.l.0);
-                                            leaf.r = Tree(root.r.0);
-                                            self.0 = NonNull::new(leaf);
-                                            leaf.update();
-                                        } else {
-                                            let left = unsafe { root.l.0.unwrap().as_mut() };
-                                            left.r = Tree(root.r.0);
-                                            self.0 = NonNull::new(left);
-                                            left.update()
-                                        }
-                                    }
-                                }
-                            }
-                            (true, false) => {
-                                self.0 = Some(root.l.0.unwrap());
-                            }
-                            (false, true) => {
-                                self.0 = Some(root.r.0.unwrap());
-                            }
-                            (false, false) => {
-                                self.0 = None;
-                                return false;
-                            }
-                        };
-                        false
-                    }
-                    Greater => {
-                        // 向左方前进，前进前确认对应节点是否存在，以及节点是否是buddy
-                        if root.l.0.is_some() && order.ptr_to_idx(root.l.0.unwrap()) == idx ^ 1 {
-                            // if delete node is not leaf => delete link
-                            let node = unsafe { root.l.0.unwrap().as_mut() };
-                            match (node.l.0.is_some(), node.r.0.is_some()) {
-                                (true, true) => {
-                                    // have both left and right subtree
-                                    if node.l.height() < node.r.height() {
-                                        // right tree higher than left tree
-                                        // node:    the point you want to delete
-                                        // byond:   the point byond the leaf
-                                        //      [A] (root)  |      [A]
-                                        //     /            |       /
-                                        // . [B] (node)     |      [E]
-                                        //    /       \     |      /  \
-                                        //  [C](byond)[D]   |    [C]  [D]
-                                        //    \             |     \
-                                        //    [E] (leaf)    |     [F]
-                                        //     \
-                                        //      [F]// 找到距离左子树最大节点最近的节点
-                                        if node.r.height() == 1 {
-                                            let leaf = unsafe { node.r.0.unwrap().as_mut() };
-                                            leaf.l = Tree(node.l.0);
-                                            root.l = Tree(NonNull::new(leaf));
-                                            leaf.update();
-                                            root.update();
-                                        } else {
-                                            let beyond =
-                                                unsafe { find_min(&node.r.0.unwrap()).as_mut() };
-                                            if let Some(mut leaf) = beyond.l.0 {
-                                                // 如果 beyond 存在左子树
-                                                let leaf = unsafe { leaf.as_mut() };
-
-                                                // 如果存在反方向子树
-                                                if leaf.r.0.is_some() {
-                                                    beyond.l = Tree(leaf.r.0);
-                                                    leaf.r = Tree(None);
-                                                } else {
-                                                    beyond.l = Tree(None);
-                                                }
-
-                                                leaf.l = Tree(node.l.0);
-                                                leaf.r = Tree(node.r.0);
-                                                root.l = Tree(NonNull::new(leaf));
-
-                                                leaf.update();
-                                                root.update();
-                                            } else {
-                                                // 将 beyond 作为 leaf 替换 node
-                                                beyond.l = Tree(node.l.0);
-                                                root.l = Tree(NonNull::new(beyond));
-                                                beyond.update();
-                                                root.update();
-                                            }
-                                        }
-                                    } else {
-                                        // left tree higher than right subtree
-                                        // node:    the point you want to delete
-                                        // byond:   the point byond the leaf
-                                        //    [A]   (root)  |     [A]
-                                        //    /             |    /
-                                        //   [B] (node)     |   [E]
-                                        //   / \            |   / \
-                                        //  [C] [D](byond)  | [C] [D]
-                                        //      /           |       \
-                                        // (E:leaf)         |        [F]
-                                        //     \
-                                        //     [F]
-                                        if node.l.height() == 1 {
-                                            // left subtree is leaf => delete node
-                                            let leaf = unsafe { node.l.0.unwrap().as_mut() };
-                                            leaf.r = Tree(node.r.0);
-                                            root.l = Tree(NonNull::new(leaf));
-                                            leaf.update();
-                                            root.update();
-                                        } else {
-                                            // left subtree has subtree => delete link and relink this link to it's parent then delete node
-                                            let beyond =
-                                                unsafe { find_max(&node.l.0.unwrap()).as_mut() };
-                                            if let Some(mut leaf) = beyond.r.0 {
-                                                let leaf = unsafe { leaf.as_mut() };
-
-                                                if leaf.l.0.is_some() {
-                                                    beyond.r = Tree(leaf.l.0);
-                                                    leaf.l = Tree(None);
-                                                } else {
-                                                    beyond.r = Tree(None);
-                                                }
-                                                leaf.l = Tree(node.l.0);
-                                                leaf.r = Tree(node.r.0);
-                                                root.l = Tree(NonNull::new(leaf));
-                                                leaf.update();
-                                                root.update();
-                                            } else {
-                                                beyond.r = Tree(node.r.0);
-                                                root.l = Tree(NonNull::new(beyond));
-                                                beyond.update();
-                                                root.update();
-                                            }
-                                        }
-                                    }
-                                }
-                                (true, false) => {
-                                    // have left but not right
-                                    //      [A] <- root |   [A]
-                                    //     /            |   /
-                                    //    [B] <- node   |  [C]
-                                    //    /             |
-                                    //  [C]             |
-                                    root.l = Tree(Some(node.l.0.unwrap()));
-                                    node.l = Tree(None);
-                                    node.update();
-                                }
-                                (false, true) => {
-                                    // have left but not right
-                                    //      [A] <- root |    [A]
-                                    //      /           |   /
-                                    //    [B] <- node   |  [C]
-                                    //      \           |
-                                    //      [C]         |
-                                    root.l = Tree(Some(node.r.0.unwrap()));
-                                    node.l = Tree(None);
-                                    node.update();
-                                }
-                                (false, false) => {
-                                    // is leaf node
-                                    root.l = Tree(None);
-                                    root.update();
-                                }
-                            }
-                            return false;
-                        } else {
-                            // 如果前进的方向不是 buddy 节点，则以递归方式继续进行插入，一直到达对应的位置(插入于空节点)
-                            root.l.insert(idx, order)
-                        }
-                    }
-                };
-                root.update();
-                unsafe { self.0.unwrap().as_mut() }.update();
-                self.rotate();
-                ret
-            }
-            // if this node is empty => insert in this point
-            None => {
-                self.0 = Some(ptr);
-                *unsafe { order.idx_to_ptr(idx).unwrap().as_mut() } = Node {
-                    l: Tree(None),
-                    r: Tree(None),
-                    h: 1,
-                };
-                true
-            }
+        // 伙伴可能在树的任何位置，用通用的删除保持平衡
+        let buddy = order
+            .idx_to_ptr::<Node>(idx ^ 1)
+            .expect("buddy address is null");
+        if self.remove(buddy) {
+            false
+        } else {
+            self.insert_no_merge(idx, order);
+            true
         }
     }
 
@@ -704,79 +351,118 @@ impl Tree {
         }
     }
 
-    /// 从地址池中获取一个单位的地址, 并且返回这个地址
-    fn delete(&mut self, order: &Order) -> Option<usize> {
-        /*
-        根据需求，此处需要实现的子模块包括，通过 左右子树中的最小高度导航到 到最近的叶子结点，然后再进行 删除节点操作
-        删除节点操作的时候，由于当前操作在叶子节点处产生，因此不需要考虑额外信息，直接将其删除即可
-        */
-        match self.0 {
-            None => None,
-            Some(mut root_ptr) => {
-                let root = unsafe { root_ptr.as_mut() };
-                let ret = match (root.l.0.is_some(), root.r.0.is_some()) {
-                    (true, true) => {
-                        // have both left and right subtree
-                        // 如果某个方向只剩下一个节点，则直接删除这个节点，并且返回对应信息
-                        // 否则以递归方式继续进行
-                        // 这个地方实际上主要目的在于减少代码量...但是反而带来了可读性的降低
-                        match (
-                            root.l.height() < root.r.height(),
-                            core::cmp::min(root.l.height(), root.r.height()),
-                        ) {
-                            (true, 1) => {
-                                let node = order.ptr_to_idx(root.l.0.unwrap());
-                                root.l = Tree(None);
-                                root.update();
-                                return Some(node);
-                            }
-                            (true, _) => root.l.delete(order),
-                            (false, 1) => {
-                                let node = order.ptr_to_idx(root.r.0.unwrap());
-                                root.r = Tree(None);
-                                root.update();
-                                return Some(node);
-                            }
-                            (false, _) => root.r.delete(order),
-                        }
-                    }
-                    (true, false) => {
-                        // only have left subtree
-                        match root.l.height() {
-                            1 => {
-                                let node = order.ptr_to_idx(root.l.0.unwrap());
-                                root.l = Tree(None);
-                                root.update();
-                                return Some(node);
-                            }
-                            _ => root.l.delete(order),
-                        }
-                    }
-                    (false, true) => {
-                        // only have right subtree
-                        match root.r.height() {
-                            1 => {
-                                let node = order.ptr_to_idx(root.r.0.unwrap());
-                                root.r = Tree(None);
-                                root.update();
-                                return Some(node);
-                            }
-                            _ => root.r.delete(order),
-                        }
+    /// 取下地址最小的结点。
+    ///
+    /// 沿最左路径递归，返回时逐层更新高度并旋转以保持平衡。
+    fn take_min(&mut self) -> Option<NonNull<Node>> {
+        let mut root_ptr = self.0?;
+        let root = unsafe { root_ptr.as_mut() };
+        if root.l.0.is_some() {
+            let ans = root.l.take_min();
+            root.update();
+            self.rotate();
+            ans
+        } else {
+            // 最小结点没有左子树，用右子树替代它
+            self.0 = root.r.0;
+            Some(root_ptr)
+        }
+    }
+
+    /// 取下地址最大的结点。
+    ///
+    /// 沿最右路径递归，返回时逐层更新高度并旋转以保持平衡。
+    fn take_max(&mut self) -> Option<NonNull<Node>> {
+        let mut root_ptr = self.0?;
+        let root = unsafe { root_ptr.as_mut() };
+        if root.r.0.is_some() {
+            let ans = root.r.take_max();
+            root.update();
+            self.rotate();
+            ans
+        } else {
+            // 最大结点没有右子树，用左子树替代它
+            self.0 = root.l.0;
+            Some(root_ptr)
+        }
+    }
+
+    /// 按中序找到第一个满足条件的结点。
+    ///
+    /// `ascending` 为 `false` 时按逆中序查找，即找到最后一个满足条件的结点。
+    fn find_in_order(
+        &self,
+        ascending: bool,
+        f: &impl Fn(NonNull<Node>) -> bool,
+    ) -> Option<NonNull<Node>> {
+        let root_ptr = self.0?;
+        let root = unsafe { root_ptr.as_ref() };
+        let (first, last) = if ascending {
+            (&root.l, &root.r)
+        } else {
+            (&root.r, &root.l)
+        };
+        first
+            .find_in_order(ascending, f)
+            .or_else(|| f(root_ptr).then_some(root_ptr))
+            .or_else(|| last.find_in_order(ascending, f))
+    }
+
+    /// 判断目标结点是否在树中。
+    fn contains(&self, target: NonNull<Node>) -> bool {
+        let mut cursor = *self;
+        while let Some(root_ptr) = cursor.0 {
+            let root = unsafe { root_ptr.as_ref() };
+            use core::cmp::Ordering::*;
+            cursor = match target.cmp(&root_ptr) {
+                Less => root.l,
+                Greater => root.r,
+                Equal => return true,
+            };
+        }
+        false
+    }
+
+    /// 移除目标结点，并重新平衡。
+    ///
+    /// 如果目标结点存在返回 `true`。
+    fn remove(&mut self, target: NonNull<Node>) -> bool {
+        let Some(mut root_ptr) = self.0 else {
+            return false;
+        };
+        let root = unsafe { root_ptr.as_mut() };
+        use core::cmp::Ordering::*;
+        let found = match target.cmp(&root_ptr) {
+            Less => root.l.remove(target),
+            Greater => root.r.remove(target),
+            Equal => {
+                // 用较高的子树中最靠近的结点替代被移除的结点
+                let next = if root.l.height() < root.r.height() {
+                    root.r.take_min()
+                } else {
+                    root.l.take_max()
+                };
+                match next {
+                    Some(mut next) => {
+                        let next = unsafe { next.as_mut() };
+                        next.l = root.l;
+                        next.r = root.r;
+                        self.0 = NonNull::new(next);
                     }
-                    (false, false) => {
-                        // this is the root node (and it's leaf) => clean itself
-                        // let node = self.0.unwrap().as_ptr() as usize;
-                        let node = order.ptr_to_idx(self.0.unwrap());
+                    None => {
+                        // 叶子结点
                         self.0 = None;
-                        return Some(node);
+                        return true;
                     }
-                };
-                root.update();
-                self.rotate();
-                ret
+                }
+                true
             }
+        };
+        if found {
+            unsafe { self.0.unwrap().as_mut() }.update();
+            self.rotate();
         }
+        found
     }
 
     /// 树高。
@@ -1298,4 +984,238 @@ mod test {
 
         assert_eq!(avl_buddy.tree.0, None);
     }
+
+    /// 检查树有序且平衡，返回树高。
+    fn check_balanced(tree: &Tree) -> usize {
+        match tree.0 {
+            None => 0,
+            Some(node) => {
+                let node = unsafe { node.as_ref() };
+                let l = check_balanced(&node.l);
+                let r = check_balanced(&node.r);
+                assert!(l.abs_diff(r) <= 1);
+                assert_eq!(node.h, l.max(r) + 1);
+                node.h
+            }
+        }
+    }
+
+    #[test]
+    fn test_for_take_aligned() {
+        let vec = create_nonnull_list();
+        for policy in [TakePolicy::Lowest, TakePolicy::Highest] {
+            let mut avl_buddy = AvlBuddy::EMPTY;
+            avl_buddy.init(ORDER_LEVEL, vec[0].as_ptr() as usize);
+            avl_buddy.set_policy(policy);
+            // 序号的奇偶性交错，使对齐的块分散在树中
+            let mut put = [0; 12];
+            for (i, p) in put.iter_mut().enumerate() {
+                *p = ((vec[i].as_ptr() as usize) >> ORDER_LEVEL) + i % 2;
+                <AvlBuddy as BuddyCollection>::put(&mut avl_buddy, *p);
+            }
+            check_balanced(&avl_buddy.tree);
+
+            // 对齐到 2 的块按地址顺序取出
+            put.sort_unstable();
+            if policy == TakePolicy::Highest {
+                put.reverse();
+            }
+            for &p in put.iter().filter(|&&p| p & 1 == 0) {
+                assert_eq!(
+                    <AvlBuddy as BuddyCollection>::take_any(&mut avl_buddy, 1),
+                    Some(p)
+                );
+                check_balanced(&avl_buddy.tree);
+            }
+            assert_eq!(
+                <AvlBuddy as BuddyCollection>::take_any(&mut avl_buddy, 1),
+                None
+            );
+            // 剩下的块不对齐地取出
+            for &p in put.iter().filter(|&&p| p & 1 == 1) {
+                assert_eq!(
+                    <AvlBuddy as BuddyCollection>::take_any(&mut avl_buddy, 0),
+                    Some(p)
+                );
+                check_balanced(&avl_buddy.tree);
+            }
+            assert_eq!(avl_buddy.tree.0, None);
+        }
+    }
+
+    #[test]
+    fn test_for_take_in_address_order() {
+        let vec = create_nonnull_list();
+        for policy in [TakePolicy::Lowest, TakePolicy::Highest] {
+            let mut avl_buddy = AvlBuddy::EMPTY;
+            avl_buddy.init(ORDER_LEVEL, vec[0].as_ptr() as usize);
+            avl_buddy.set_policy(policy);
+            // 乱序放入互不为伙伴的块
+            for i in [9, 3, 14, 1, 7, 12, 5, 10, 0, 15, 4] {
+                <AvlBuddy as BuddyCollection>::put(
+                    &mut avl_buddy,
+                    (vec[i].as_ptr() as usize) >> ORDER_LEVEL,
+                );
+            }
+            check_balanced(&avl_buddy.tree);
+
+            let mut expect = [0, 1, 3, 4, 5, 7, 9, 10, 12, 14, 15];
+            if policy == TakePolicy::Highest {
+                expect.reverse();
+            }
+            for i in expect {
+                assert_eq!(
+                    <AvlBuddy as BuddyCollection>::take_any(&mut avl_buddy, 0),
+                    Some((vec[i].as_ptr() as usize) >> ORDER_LEVEL)
+                );
+                check_balanced(&avl_buddy.tree);
+            }
+            assert_eq!(
+                <AvlBuddy as BuddyCollection>::take_any(&mut avl_buddy, 0),
+                None
+            );
+        }
+    }
+
+    #[test]
+    fn test_for_oligarchy() {
+        // 使用连续的页，避开其他测试使用的页
+        let page = |k: usize| unsafe { core::ptr::addr_of_mut!(MEMORY[1 + k]) } as usize;
+        let b = page(0) >> ORDER_LEVEL;
+        let offsets = [7, 0, 4, 11, 2, 8, 5, 1, 6];
+        for policy in [TakePolicy::Lowest, TakePolicy::Highest] {
+            let mut avl_buddy = AvlBuddy::EMPTY;
+            avl_buddy.init(ORDER_LEVEL, 0);
+            avl_buddy.set_policy(policy);
+            for k in offsets {
+                <AvlBuddy as OligarchyCollection>::put(&mut avl_buddy, b + k);
+            }
+            check_balanced(&avl_buddy.tree);
+
+            let mut take = |count| {
+                let ans = <AvlBuddy as OligarchyCollection>::take_any(&mut avl_buddy, 0, count);
+                check_balanced(&avl_buddy.tree);
+                ans.map(|idx| idx - b)
+            };
+            if policy == TakePolicy::Highest {
+                assert_eq!(take(3), Some(6));
+                assert_eq!(take(2), Some(4));
+                assert_eq!(take(3), Some(0));
+            } else {
+                assert_eq!(take(3), Some(0));
+                assert_eq!(take(3), Some(4));
+                assert_eq!(take(2), Some(7));
+            }
+            assert_eq!(take(2), None);
+            assert!(avl_buddy.contains(b + 11) && !avl_buddy.contains(b + 10));
+            assert!(avl_buddy.take(b + 11));
+            assert!(!avl_buddy.contains(b + 11));
+            assert!(!avl_buddy.take(b + 11));
+            assert_eq!(avl_buddy.tree.0, None);
+        }
+
+        // 连续段的起点按全局序号对齐
+        let mut avl_buddy = AvlBuddy::EMPTY;
+        avl_buddy.init(ORDER_LEVEL, 0);
+        for k in offsets {
+            <AvlBuddy as OligarchyCollection>::put(&mut avl_buddy, b + k);
+        }
+        let expect = (0..12)
+            .find(|k| {
+                (b + k).is_multiple_of(2) && offsets.contains(k) && offsets.contains(&(k + 1))
+            })
+            .map(|k| b + k);
+        assert_eq!(
+            <AvlBuddy as OligarchyCollection>::take_any(&mut avl_buddy, 1, 2),
+            expect
+        );
+
+        // 默认的批量放入和提取
+        let mut avl_buddy = AvlBuddy::EMPTY;
+        avl_buddy.init(ORDER_LEVEL, 0);
+        avl_buddy.put_range(b, 6);
+        check_balanced(&avl_buddy.tree);
+        assert!(avl_buddy.take_range(b + 1, 3));
+        // 部分不在集合中时放回已经提取的元素
+        assert!(!avl_buddy.take_range(b + 4, 3));
+        assert!(avl_buddy.contains(b + 4) && avl_buddy.contains(b + 5));
+        assert!(avl_buddy.take_range(b + 4, 2));
+        assert!(avl_buddy.take_range(b, 1));
+        assert!(avl_buddy.take_range(b, 0));
+        assert_eq!(avl_buddy.tree.0, None);
+    }
+
+    #[test]
+    fn test_for_random_put_take() {
+        // 乱序放入和取出大量块，与逐页的模型对照
+        static mut POOL: [Page; 256] = [Page::ZERO; 256];
+        let start = core::ptr::addr_of_mut!(POOL) as usize >> ORDER_LEVEL;
+        let mut avl_buddy = AvlBuddy::EMPTY;
+        avl_buddy.init(ORDER_LEVEL, 0);
+        let mut model = [false; 256];
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        for _ in 0..20000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            if seed.is_multiple_of(2) {
+                let i = (seed >> 8) as usize % 256;
+                let buddy = ((start + i) ^ 1).wrapping_sub(start);
+                if model[i] || buddy >= 256 {
+                    continue;
+                }
+                let expect = if model[buddy] {
+                    model[buddy] = false;
+                    Some((start + i) >> 1)
+                } else {
+                    model[i] = true;
+                    None
+                };
+                assert_eq!(
+                    <AvlBuddy as BuddyCollection>::put(&mut avl_buddy, start + i),
+                    expect
+                );
+            } else {
+                let expect = model.iter().position(|&free| free);
+                if let Some(i) = expect {
+                    model[i] = false;
+                }
+                assert_eq!(
+                    <AvlBuddy as BuddyCollection>::take_any(&mut avl_buddy, 0),
+                    expect.map(|i| start + i)
+                );
+            }
+            check_balanced(&avl_buddy.tree);
+        }
+    }
+
+    #[test]
+    fn test_for_deep_buddy_merge() {
+        // 伙伴不在插入路径的下一层时，合并后其他块不能丢失，树仍然平衡
+        static mut POOL: [Page; 34] = [Page::ZERO; 34];
+        let start = core::ptr::addr_of_mut!(POOL) as usize >> ORDER_LEVEL;
+        let b = (start + 1) & !1;
+        for (n, k) in [(4, 1), (8, 7), (9, 9), (16, 25)] {
+            let mut avl_buddy = AvlBuddy::EMPTY;
+            avl_buddy.init(ORDER_LEVEL, 0);
+            for j in 0..n {
+                assert_eq!(
+                    <AvlBuddy as BuddyCollection>::put(&mut avl_buddy, b + 2 * j),
+                    None
+                );
+            }
+            assert_eq!(
+                <AvlBuddy as BuddyCollection>::put(&mut avl_buddy, b + k),
+                Some((b + k) >> 1)
+            );
+            check_balanced(&avl_buddy.tree);
+            for j in (0..n).filter(|&j| 2 * j != k - 1) {
+                assert_eq!(
+                    <AvlBuddy as BuddyCollection>::take_any(&mut avl_buddy, 0),
+                    Some(b + 2 * j)
+                );
+            }
+            assert_eq!(avl_buddy.tree.0, None);
+        }
+    }
 }
@@ -0,0 +1,270 @@
+use core::ptr::NonNull;
+
+/// 分配器的后备内存。
+///
+/// [`allocate_backed`](crate::BuddyAllocator::allocate_backed) 在空闲内存不足时向后备内存申请更多内存，
+/// [`trim_backed`](crate::BuddyAllocator::trim_backed) 把完全空闲的寡头还给后备内存，
+/// 类似 `sbrk` 或者 `mmap` 之上的堆。
+///
+/// # Safety
+///
+/// [`grow`](Self::grow) 提供的内存会直接转移给分配器，实现需要保证它没有被其他任何对象引用，
+/// 并且与之前提供的、尚未归还的内存不重叠。
+pub unsafe trait Backing {
+    /// 提供一块至少 `2^order` 字节、地址对齐到 `2^order` 的内存，返回 `(指针, 长度)`。
+    ///
+    /// 长度需要是 `2^order` 的倍数。无法提供时返回 [`None`]。
+    fn grow(&mut self, order: usize) -> Option<(NonNull<u8>, usize)>;
+
+    /// 收回 `[ptr, ptr + size)`。
+    ///
+    /// 归还的范围是若干连续的寡头，可能只是某一次 [`grow`](Self::grow) 提供的内存的一部分，
+    /// 也可能跨越多次提供的内存。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证这段内存由 [`grow`](Self::grow) 提供，并且不再被引用。
+    unsafe fn release(&mut self, ptr: NonNull<u8>, size: usize);
+}
+
+/// 以匿名映射提供内存的后备内存。
+///
+/// 每次至少映射 `2^chunk_order` 字节，多映射一些以便对齐，再解除头尾多余的部分。
+/// 归还时直接解除映射，因此分配器的最大阶数不能小于页的阶数。
+/// 解除映射失败的内存仍然映射着，继续计入 [`mapped`](Self::mapped)。
+#[cfg(all(feature = "std", target_os = "linux"))]
+#[derive(Debug)]
+pub struct MmapBacking {
+    chunk_order: usize,
+    mapped: usize,
+}
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+mod sys {
+    use core::ffi::{c_int, c_long, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const PROT_WRITE: c_int = 2;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_ANONYMOUS: c_int = 0x20;
+    pub const MAP_FAILED: *mut c_void = !0 as _;
+
+    unsafe extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+impl MmapBacking {
+    /// 每次至少映射 `2^chunk_order` 字节的后备内存。
+    #[inline]
+    pub const fn new(chunk_order: usize) -> Self {
+        Self {
+            chunk_order,
+            mapped: 0,
+        }
+    }
+
+    /// 返回已经映射、尚未归还的字节数。
+    #[inline]
+    pub fn mapped(&self) -> usize {
+        self.mapped
+    }
+}
+
+#[cfg(all(feature = "std", target_os = "linux"))]
+unsafe impl Backing for MmapBacking {
+    fn grow(&mut self, order: usize) -> Option<(NonNull<u8>, usize)> {
+        use sys::*;
+
+        let size = 1usize.checked_shl(order.max(self.chunk_order) as _)?;
+        // 多映射一倍，从中截取对齐的部分
+        let len = size.checked_mul(2)?;
+        let addr = unsafe {
+            mmap(
+                core::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        if addr == MAP_FAILED {
+            return None;
+        }
+        let addr = addr as usize;
+        let start = (addr + size - 1) & !(size - 1);
+        let end = start + size;
+        unsafe {
+            if start > addr {
+                munmap(addr as _, start - addr);
+            }
+            if addr + len > end {
+                munmap(end as _, addr + len - end);
+            }
+        }
+        self.mapped += size;
+        Some((NonNull::new(start as *mut u8)?, size))
+    }
+
+    unsafe fn release(&mut self, ptr: NonNull<u8>, size: usize) {
+        if unsafe { sys::munmap(ptr.as_ptr().cast(), size) } == 0 {
+            self.mapped -= size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        NoopObserver,
+        tests::{FAKE_BASE as BASE, fake_allocator},
+    };
+    use core::num::NonZeroUsize;
+
+    /// 从一段假想的地址依次提供内存的后备内存。
+    struct Bump {
+        next: usize,
+        end: usize,
+        released: [(usize, usize); 4],
+        n: usize,
+    }
+
+    unsafe impl Backing for Bump {
+        fn grow(&mut self, order: usize) -> Option<(NonNull<u8>, usize)> {
+            let size = 1 << order;
+            let start = self.next.next_multiple_of(size);
+            (start + size <= self.end).then(|| {
+                self.next = start + size;
+                (NonNull::new(start as *mut u8).unwrap(), size)
+            })
+        }
+
+        unsafe fn release(&mut self, ptr: NonNull<u8>, size: usize) {
+            self.released[self.n] = (ptr.as_ptr() as usize, size);
+            self.n += 1;
+        }
+    }
+
+    #[test]
+    fn test_allocate_backed_trim() {
+        let mut backing = Bump {
+            next: BASE,
+            end: BASE + 0x10_0000,
+            released: [(0, 0); 4],
+            n: 0,
+        };
+        // 最小阶数 12，寡头 64 KiB
+        let mut allocator = fake_allocator::<4, _>(BASE, 12, NoopObserver);
+
+        // 没有内存时申请恰好满足要求的块
+        let page = NonZeroUsize::new(0x1000).unwrap();
+        let (a, _) = allocator
+            .allocate_backed::<u8>(0, page, &mut backing)
+            .unwrap();
+        assert_eq!(a.as_ptr() as usize, BASE);
+        assert_eq!(allocator.capacity(), 0x1000);
+        // 对齐要求决定申请的阶数
+        let (b, _) = allocator
+            .allocate_backed::<u8>(16, page, &mut backing)
+            .unwrap();
+        assert_eq!(b.as_ptr() as usize, BASE + 0x1_0000);
+        assert_eq!(allocator.capacity(), 0x1_1000);
+        // 三个寡头
+        let size = NonZeroUsize::new(0x3_0000).unwrap();
+        let (c, _) = allocator
+            .allocate_backed::<u8>(0, size, &mut backing)
+            .unwrap();
+        assert_eq!(c.as_ptr() as usize, BASE + 0x4_0000);
+        assert_eq!(allocator.capacity(), 0x5_1000);
+        // 后备内存耗尽
+        let size = NonZeroUsize::new(0x10_0000).unwrap();
+        assert!(
+            allocator
+                .allocate_backed::<u8>(0, size, &mut backing)
+                .is_err()
+        );
+
+        // 只有完整的空闲寡头可以归还，并且保留 `keep_bytes` 字节空闲内存
+        allocator.deallocate(b, 0x1000);
+        allocator.deallocate(c, 0x3_0000);
+        assert_eq!(allocator.free(), 0x5_0000);
+        assert_eq!(
+            unsafe { allocator.trim_backed(0x2_0000, &mut backing) },
+            0x3_0000
+        );
+        assert_eq!(backing.released[..backing.n], [(BASE + 0x5_0000, 0x3_0000)]);
+        // 从高地址开始归还，不相邻的寡头分别归还
+        assert_eq!(unsafe { allocator.trim_backed(0, &mut backing) }, 0x2_0000);
+        assert_eq!(
+            backing.released[..backing.n],
+            [
+                (BASE + 0x5_0000, 0x3_0000),
+                (BASE + 0x4_0000, 0x1_0000),
+                (BASE + 0x1_0000, 0x1_0000)
+            ]
+        );
+        assert_eq!(allocator.capacity(), 0x1000);
+        assert_eq!(allocator.free(), 0);
+        allocator.deallocate(a, 0x1000);
+    }
+
+    #[cfg(all(feature = "std", target_os = "linux"))]
+    #[test]
+    fn test_mmap() {
+        use crate::{BuddyAllocator, LinkedListBuddy};
+
+        let mut backing = MmapBacking::new(21);
+        // 最小阶数 12，寡头 1 MiB
+        let mut allocator = BuddyAllocator::<8, LinkedListBuddy, LinkedListBuddy>::new();
+        allocator.init(12, NonNull::<u8>::dangling());
+
+        let page = NonZeroUsize::new(0x1000).unwrap();
+        let (a, _) = allocator
+            .allocate_backed::<u8>(0, page, &mut backing)
+            .unwrap();
+        assert_eq!(backing.mapped(), 2 << 20);
+        let size = NonZeroUsize::new(3 << 20).unwrap();
+        let (b, _) = allocator
+            .allocate_backed::<u8>(0, size, &mut backing)
+            .unwrap();
+        assert_eq!(backing.mapped(), 6 << 20);
+        assert_eq!(allocator.capacity(), 6 << 20);
+        // 映射的内存可以读写
+        unsafe {
+            a.as_ptr().write_bytes(0xa5, 0x1000);
+            b.as_ptr().write_bytes(0x5a, 3 << 20);
+        }
+
+        allocator.deallocate(b, 3 << 20);
+        // 第一块中有一个寡头还在使用
+        assert_eq!(unsafe { allocator.trim_backed(0, &mut backing) }, 5 << 20);
+        assert_eq!(backing.mapped(), 1 << 20);
+        allocator.deallocate(a, 0x1000);
+        assert_eq!(unsafe { allocator.trim_backed(0, &mut backing) }, 1 << 20);
+        assert_eq!(backing.mapped(), 0);
+        assert_eq!(allocator.capacity(), 0);
+    }
+
+    #[cfg(all(feature = "std", target_os = "linux"))]
+    #[test]
+    fn test_mmap_release_failed() {
+        let mut backing = MmapBacking::new(12);
+        let (ptr, size) = backing.grow(12).unwrap();
+        // 没有对齐到页的地址无法解除映射，仍然计入已映射的字节数
+        unsafe { backing.release(ptr.byte_add(1), size) };
+        assert_eq!(backing.mapped(), size);
+        unsafe { backing.release(ptr, size) };
+        assert_eq!(backing.mapped(), 0);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::{AllocObserver, BuddyAllocator, BuddyCollection, LineStorage, OligarchyCollection};
+use core::{fmt, num::NonZeroUsize, ptr::NonNull};
+
+/// 内存气球。
+///
+/// 充气时用 [`snatch`](BuddyAllocator::snatch) 从分配器夺走内存块交给宿主，
+/// 放气时用 [`transfer`](BuddyAllocator::transfer) 把内存块还给分配器。
+/// 夺走的块以 `(地址, 长度)` 记录在调用者提供的存储中，地址相邻的块合并成一项。
+///
+/// 分配器的容量随充气减少、随放气增加，`capacity() + size()` 保持不变。
+///
+/// 每次充气或放气最多移动速率限制的字节数，直到气球的大小达到目标。
+/// 充气时按偏好的阶数依次尝试；没有设置偏好时从最大阶数开始向下尝试，优先夺走大块以减少碎片。
+pub struct Balloon<'a> {
+    /// 夺走的块，按夺走的顺序排列。
+    blocks: &'a mut [(usize, usize)],
+    len: usize,
+    /// 气球中的字节数。
+    size: usize,
+    /// 目标字节数。
+    target: usize,
+    /// 每次充气或放气最多移动的字节数。
+    rate: usize,
+    /// 充气时依次尝试的阶数。
+    orders: &'a [usize],
+}
+
+impl<'a> Balloon<'a> {
+    /// 以 `storage` 记录夺走的块的空气球。
+    #[inline]
+    pub fn new(storage: &'a mut [(usize, usize)]) -> Self {
+        Self {
+            blocks: storage,
+            len: 0,
+            size: 0,
+            target: 0,
+            rate: usize::MAX,
+            orders: &[],
+        }
+    }
+
+    /// 返回气球中的字节数。
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 返回目标字节数。
+    #[inline]
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    /// 设置目标字节数。
+    #[inline]
+    pub fn set_target(&mut self, bytes: usize) {
+        self.target = bytes;
+    }
+
+    /// 设置每次充气或放气最多移动的字节数。
+    #[inline]
+    pub fn set_rate(&mut self, bytes: usize) {
+        self.rate = bytes;
+    }
+
+    /// 设置充气时依次尝试的阶数。为空时从分配器的最大阶数向下尝试到最小阶数。
+    ///
+    /// 小于分配器最小阶数的阶数被忽略。
+    #[inline]
+    pub fn set_orders(&mut self, orders: &'a [usize]) {
+        self.orders = orders;
+    }
+
+    /// 以 `(地址, 长度)` 迭代气球中的块。
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (NonNull<u8>, usize)> + '_ {
+        self.blocks[..self.len]
+            .iter()
+            .map(|&(addr, size)| (unsafe { NonNull::new_unchecked(addr as *mut u8) }, size))
+    }
+
+    /// 迭代气球中的 `page_order` 阶的页的页号，用于报告给宿主。
+    #[inline]
+    pub fn pages(&self, page_order: usize) -> impl Iterator<Item = usize> + '_ {
+        self.blocks[..self.len]
+            .iter()
+            .flat_map(move |&(addr, size)| (addr >> page_order)..((addr + size) >> page_order))
+    }
+
+    /// 充气，返回这一次夺走的字节数。
+    ///
+    /// 每夺走一个块就以 `(地址, 长度)` 传给 `inflated`。
+    /// 分配器没有足够的空闲块或者存储已满时提前停止。
+    pub fn inflate<
+        const N: usize,
+        O: OligarchyCollection,
+        B: BuddyCollection,
+        S: LineStorage<B>,
+        W: AllocObserver,
+    >(
+        &mut self,
+        allocator: &mut BuddyAllocator<N, O, B, S, W>,
+        mut inflated: impl FnMut(NonNull<u8>, usize),
+    ) -> usize {
+        let min_order = allocator.min_order;
+        let mask = (1usize << min_order) - 1;
+        let mut budget = self.target.saturating_sub(self.size).min(self.rate) & !mask;
+
+        let mut total = 0;
+        while budget > 0 && self.len < self.blocks.len() {
+            // 没有偏好时从最大阶数向下尝试
+            let fallback = (min_order..=allocator.max_order())
+                .rev()
+                .filter(|_| self.orders.is_empty());
+            let Some((ptr, size)) = self
+                .orders
+                .iter()
+                .copied()
+                .chain(fallback)
+                .filter(|&order| order >= min_order && order < usize::BITS as usize)
+                .filter(|&order| 1 << order <= budget)
+                .find_map(|order| {
+                    allocator
+                        .snatch::<u8>(order, NonZeroUsize::new(1 << order).unwrap())
+                        .ok()
+                })
+            else {
+                break;
+            };
+            self.push(ptr.as_ptr() as usize, size);
+            inflated(ptr, size);
+            budget -= size;
+            total += size;
+        }
+        self.size += total;
+        total
+    }
+
+    /// 放气，返回这一次还给分配器的字节数。
+    ///
+    /// 从最后夺走的块开始归还，块比剩余的速率限制大时只归还它的尾部。
+    /// 每归还一段就在转移给分配器之前以 `(地址, 长度)` 传给 `deflated`。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证：
+    ///
+    /// - `allocator` 是充气时的分配器，或者可以管理气球中的块；
+    /// - 宿主不再使用要归还的内存。
+    pub unsafe fn deflate<
+        const N: usize,
+        O: OligarchyCollection,
+        B: BuddyCollection,
+        S: LineStorage<B>,
+        W: AllocObserver,
+    >(
+        &mut self,
+        allocator: &mut BuddyAllocator<N, O, B, S, W>,
+        mut deflated: impl FnMut(NonNull<u8>, usize),
+    ) -> usize {
+        let mask = (1usize << allocator.min_order) - 1;
+        let mut budget = self.size.saturating_sub(self.target).min(self.rate) & !mask;
+
+        let mut total = 0;
+        while budget > 0 && self.len > 0 {
+            let (addr, size) = &mut self.blocks[self.len - 1];
+            let len = (*size).min(budget);
+            *size -= len;
+            let ptr = unsafe { NonNull::new_unchecked((*addr + *size) as *mut u8) };
+            if *size == 0 {
+                self.len -= 1;
+            }
+            deflated(ptr, len);
+            unsafe { allocator.transfer(ptr, len) };
+            budget -= len;
+            total += len;
+        }
+        self.size -= total;
+        total
+    }
+
+    /// 记录一个夺走的块，与上一项相邻时合并。
+    fn push(&mut self, addr: usize, size: usize) {
+        if let Some(last) = self.blocks[..self.len].last_mut() {
+            if last.0 + last.1 == addr {
+                last.1 += size;
+                return;
+            }
+            if addr + size == last.0 {
+                *last = (addr, last.1 + size);
+                return;
+            }
+        }
+        self.blocks[self.len] = (addr, size);
+        self.len += 1;
+    }
+}
+
+impl fmt::Debug for Balloon<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Balloon")
+            .field("size", &self.size)
+            .field("target", &self.target)
+            .field("blocks", &format_args!("{:x?}", &self.blocks[..self.len]))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        UsizeBuddy,
+        tests::{FAKE_BASE as BASE, fake_allocator},
+    };
+
+    fn allocator() -> BuddyAllocator<4, UsizeBuddy, UsizeBuddy> {
+        let mut allocator = fake_allocator(BASE, 12, crate::NoopObserver);
+        let ptr = NonNull::new(BASE as *mut u8).unwrap();
+        unsafe { allocator.transfer(ptr, 0x4_0000) };
+        allocator
+    }
+
+    #[test]
+    fn test_inflate_deflate() {
+        let mut allocator = allocator();
+        let mut storage = [(0, 0); 4];
+        let mut balloon = Balloon::new(&mut storage);
+
+        // 先夺走大块，再用小块凑齐
+        balloon.set_target(0x2_3000);
+        let mut sizes = [0; 4];
+        let mut n = 0;
+        let inflated = balloon.inflate(&mut allocator, |_, size| {
+            sizes[n] = size;
+            n += 1;
+        });
+        assert_eq!(inflated, 0x2_3000);
+        assert_eq!(sizes, [0x1_0000, 0x1_0000, 0x2000, 0x1000]);
+        assert_eq!(allocator.capacity(), 0x4_0000 - 0x2_3000);
+        // 相邻的块合并成一项
+        assert!(
+            balloon
+                .iter()
+                .map(|(ptr, size)| (ptr.as_ptr() as usize, size))
+                .eq([(BASE, 0x2_3000)])
+        );
+        assert!(balloon.pages(12).eq((BASE >> 12)..(BASE >> 12) + 0x23));
+        // 已经达到目标
+        assert_eq!(balloon.inflate(&mut allocator, |_, _| {}), 0);
+
+        // 放气受速率限制，从尾部归还
+        balloon.set_target(0);
+        balloon.set_rate(0x5800);
+        let mut returned = (0, 0);
+        let deflated = unsafe {
+            balloon.deflate(&mut allocator, |ptr, size| {
+                returned = (ptr.as_ptr() as usize, size)
+            })
+        };
+        assert_eq!(deflated, 0x5000);
+        assert_eq!(returned, (BASE + 0x1_e000, 0x5000));
+        assert_eq!(balloon.size(), 0x1_e000);
+        assert_eq!(allocator.capacity() + balloon.size(), 0x4_0000);
+
+        balloon.set_rate(usize::MAX);
+        assert_eq!(
+            unsafe { balloon.deflate(&mut allocator, |_, _| {}) },
+            0x1_e000
+        );
+        assert_eq!(balloon.size(), 0);
+        assert_eq!(balloon.iter().count(), 0);
+        assert_eq!(allocator.capacity(), 0x4_0000);
+        assert_eq!(allocator.free(), 0x4_0000);
+    }
+
+    #[test]
+    fn test_orders_and_storage() {
+        let mut allocator = allocator();
+        let page = NonZeroUsize::new(0x1000).unwrap();
+        // 在 BASE + 0x1000 留下一个空闲页
+        let (a, _) = allocator.allocate::<u8>(0, page).unwrap();
+        let (b, _) = allocator.allocate::<u8>(0, page).unwrap();
+        let (c, _) = allocator.allocate::<u8>(0, page).unwrap();
+        allocator.deallocate(b, 0x1000);
+
+        let mut storage = [(0, 0); 1];
+        let mut balloon = Balloon::new(&mut storage);
+        balloon.set_target(0x10_0000);
+        balloon.set_rate(0x2000);
+        // 小于最小阶数的偏好被忽略
+        balloon.set_orders(&[3, 12]);
+        assert_eq!(balloon.inflate(&mut allocator, |_, _| {}), 0x1000);
+        // 下一页与气球中的块不相邻，存储已满
+        assert!(balloon.pages(12).eq([(BASE >> 12) + 1]));
+        assert_eq!(balloon.inflate(&mut allocator, |_, _| {}), 0);
+
+        balloon.set_target(0);
+        unsafe { balloon.deflate(&mut allocator, |_, _| {}) };
+        assert_eq!(balloon.size(), 0);
+        allocator.deallocate(a, 0x1000);
+        allocator.deallocate(c, 0x1000);
+        assert_eq!(allocator.free(), 0x4_0000);
+    }
+}
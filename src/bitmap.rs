@@ -1,4 +1,4 @@
-use crate::{BuddyCollection, BuddyLine, OligarchyCollection};
+use crate::{BuddyCollection, BuddyLine, OligarchyCollection, TakePolicy};
 use core::fmt;
 
 /// 用一个 usize 作为位图保存占用情况的伙伴行。
@@ -6,11 +6,14 @@ use core::fmt;
 /// - 非侵入式
 /// - 静态分配，容量有限（最多 64 或 128 个块，取决于平台）
 /// - 查找和插入时间复杂度为 O(1)
+/// - 位图不记录放入次序，[`TakePolicy::Lifo`] 按 [`TakePolicy::Lowest`] 处理
 pub struct UsizeBuddy {
     /// 位图，1 表示空闲，0 表示已分配。
     bits: usize,
     /// 基序号，用于将本地索引转换为全局索引。
     base: usize,
+    /// 取块策略。
+    policy: TakePolicy,
 }
 
 impl UsizeBuddy {
@@ -18,6 +21,9 @@ impl UsizeBuddy {
 
     #[inline]
     fn take(&mut self, idx: usize) -> bool {
+        if idx >= Self::SIZE {
+            return false;
+        }
         let bit = 1usize << idx;
         let bits = self.bits;
         self.bits &= !bit;
@@ -25,17 +31,100 @@ impl UsizeBuddy {
     }
 }
 
+/// 位图中第一个全局序号对齐到 `align` 的本地索引。
+#[inline]
+const fn first_aligned(base: usize, align: usize) -> usize {
+    base.wrapping_neg() & (align - 1)
+}
+
+/// 按策略从位图 `bits` 中选出一个全局序号对齐到 `align_order` 的空闲位。
+///
+/// 位图不记录放入次序，[`TakePolicy::Lifo`] 按 [`TakePolicy::Lowest`] 处理。
+pub(crate) fn pick(
+    bits: usize,
+    base: usize,
+    align_order: usize,
+    policy: TakePolicy,
+) -> Option<usize> {
+    // 只保留全局序号对齐的位，即从第一个对齐的位开始每 2^align_order 个位中的一个
+    let candidates = if align_order == 0 {
+        bits
+    } else {
+        let align = 1usize << align_order;
+        let mut mask = 0usize;
+        for i in (first_aligned(base, align)..usize::BITS as usize).step_by(align) {
+            mask |= 1 << i;
+        }
+        bits & mask
+    };
+    if candidates == 0 {
+        None
+    } else if policy == TakePolicy::Highest {
+        Some(usize::BITS as usize - 1 - candidates.leading_zeros() as usize)
+    } else {
+        Some(candidates.trailing_zeros() as usize)
+    }
+}
+
+/// 按策略从位图 `bits` 中找出 `count` 个连续的空闲位，起点的全局序号对齐到 `align_order`。
+///
+/// 返回起点的本地索引。
+pub(crate) fn pick_run(
+    bits: usize,
+    base: usize,
+    align_order: usize,
+    count: usize,
+    policy: TakePolicy,
+) -> Option<usize> {
+    let mask = run_mask(0, count);
+    let align = 1usize << align_order;
+    let found = |&i: &usize| bits & (mask << i) == mask << i;
+    let first = first_aligned(base, align);
+    let mut starts = (first..(usize::BITS as usize).checked_sub(count)? + 1).step_by(align);
+    if policy == TakePolicy::Highest {
+        starts.rev().find(found)
+    } else {
+        starts.find(found)
+    }
+}
+
+/// `count` 个连续的 1，从第 `i` 位开始。
+#[inline]
+pub(crate) const fn run_mask(i: usize, count: usize) -> usize {
+    (usize::MAX >> (usize::BITS as usize - count)) << i
+}
+
 impl BuddyLine for UsizeBuddy {
-    const EMPTY: Self = Self { bits: 0, base: 0 };
+    const EMPTY: Self = Self {
+        bits: 0,
+        base: 0,
+        policy: TakePolicy::Lowest,
+    };
 
     #[inline]
     fn init(&mut self, _order: usize, base: usize) {
         self.base = base;
     }
 
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.policy = policy;
+    }
+
     #[inline]
     fn take(&mut self, idx: usize) -> bool {
-        self.take(idx - self.base)
+        idx >= self.base && self.take(idx - self.base)
+    }
+
+    #[inline]
+    fn contains(&self, idx: usize) -> bool {
+        idx.checked_sub(self.base)
+            .is_some_and(|i| i < Self::SIZE && (self.bits >> i) & 1 == 1)
+    }
+
+    #[inline]
+    fn addressable(&self, idx: usize) -> bool {
+        idx.checked_sub(self.base).is_some_and(|i| i < Self::SIZE)
     }
 }
 
@@ -51,68 +140,67 @@ impl OligarchyCollection for UsizeBuddy {
         }
 
         // 需要找到连续的 count 个位
-        // mask 是 count 个连续的 1
-        let mask = (1usize << count) - 1;
-        let align = 1usize << align_order;
-        let mut i = 0;
-        while i + count <= usize::BITS as usize {
-            let bits_mask = mask << i;
-            if self.bits & bits_mask == bits_mask {
-                self.bits &= !bits_mask;
-                return Some(self.base + i);
-            }
-            i += align;
+        if count > Self::SIZE {
+            return None;
         }
-        None
+        let i = pick_run(self.bits, self.base, align_order, count, self.policy)?;
+        self.bits &= !run_mask(i, count);
+        Some(self.base + i)
     }
 
     #[inline]
     fn put(&mut self, idx: usize) {
         self.bits |= 1 << (idx - self.base);
     }
+
+    #[inline]
+    fn put_range(&mut self, idx: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let i = idx - self.base;
+        debug_assert!(i + count <= Self::SIZE, "index out of bound");
+        self.bits |= run_mask(i, count);
+    }
+
+    #[inline]
+    fn take_range(&mut self, idx: usize, count: usize) -> bool {
+        if count == 0 {
+            return true;
+        }
+        let Some(i) = idx
+            .checked_sub(self.base)
+            .filter(|&i| i + count <= Self::SIZE)
+        else {
+            return false;
+        };
+        let mask = run_mask(i, count);
+        let found = self.bits & mask == mask;
+        if found {
+            self.bits &= !mask;
+        }
+        found
+    }
 }
 
 impl BuddyCollection for UsizeBuddy {
     #[inline]
     fn take_any(&mut self, align_order: usize) -> Option<usize> {
-        // 将位图对齐到指定对齐阶数
-        // align_order=1 要求索引是 2 的倍数（0, 2, 4...）
-        // 需要清除 bit 0, 1, ..., align_order-1 中不满足对齐的位
-        // 正确做法：保留每 2^align_order 个位中的第一个
-        if align_order == 0 {
-            // 不对齐，直接找第一个空闲位
-            if self.bits != 0 {
-                let i = self.bits.trailing_zeros() as usize;
-                self.bits &= !(1 << i);
-                return Some(self.base + i);
-            }
-        } else {
-            // 对齐：只保留 bit 0, 2^align_order, 2*2^align_order, ...
-            let align = 1usize << align_order;
-            // 创建掩码，只保留对齐的位
-            let mut mask = 0usize;
-            for i in (0..usize::BITS as usize).step_by(align) {
-                mask |= 1 << i;
-            }
-            let aligned_bits = self.bits & mask;
-            if aligned_bits != 0 {
-                let i = aligned_bits.trailing_zeros() as usize;
-                self.bits &= !(1 << i);
-                return Some(self.base + i);
-            }
-        }
-        None
+        let i = pick(self.bits, self.base, align_order, self.policy)?;
+        self.bits &= !(1 << i);
+        Some(self.base + i)
     }
 
     #[inline]
     fn put(&mut self, idx: usize) -> Option<usize> {
-        let idx = idx - self.base;
-        debug_assert!(idx < Self::SIZE, "index out of bound");
+        let i = idx - self.base;
+        debug_assert!(i < Self::SIZE, "index out of bound");
+        // 伙伴按全局序号确定，基序号不一定是偶数
         let buddy = idx ^ 1;
-        if self.take(buddy) {
-            Some(idx << 1)
+        if buddy >= self.base && self.take(buddy - self.base) {
+            Some(idx >> 1)
         } else {
-            self.bits |= 1 << idx;
+            self.bits |= 1 << i;
             None
         }
     }
@@ -141,6 +229,7 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b1010, // 位 1 和 3 是空闲的
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // 应该返回最小的空闲位（1）
@@ -156,6 +245,7 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b1111, // 位 0,1,2,3 都是空闲的
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // align_order=1 要求索引对齐到 2（即索引必须是 0,2,4...）
@@ -172,6 +262,7 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b0000,
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // 放入索引 0
@@ -188,11 +279,12 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b0001, // 本地索引 0 是空闲的
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // 放入全局索引 1（本地索引 1），伙伴本地索引 0 存在，触发合并
-        // 返回父节点的本地索引（idx << 1 格式，调用者需要 idx >> 1 得到实际父节点）
-        assert_eq!(BuddyCollection::put(&mut buddy, 1), Some(2));
+        // 返回父节点在上一层的全局索引
+        assert_eq!(BuddyCollection::put(&mut buddy, 1), Some(0));
         assert_eq!(buddy.bits, 0b0000);
     }
 
@@ -202,6 +294,7 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b0000, // 初始为空
             base: 10,
+            ..UsizeBuddy::EMPTY
         };
 
         // 放入全局索引 10（本地索引 0），伙伴不存在
@@ -209,15 +302,43 @@ mod tests {
         assert_eq!(buddy.bits, 0b0001);
 
         // 放入全局索引 11（本地索引 1），伙伴存在，触发合并
-        assert_eq!(BuddyCollection::put(&mut buddy, 11), Some(2));
+        assert_eq!(BuddyCollection::put(&mut buddy, 11), Some(5));
         assert_eq!(buddy.bits, 0b0000);
     }
 
+    #[test]
+    fn test_odd_base() {
+        // base 为奇数时，本地索引 0 的伙伴不在位图中
+        let mut buddy = UsizeBuddy {
+            bits: 0b0000,
+            base: 7,
+            ..UsizeBuddy::EMPTY
+        };
+
+        assert_eq!(BuddyCollection::put(&mut buddy, 7), None);
+        assert_eq!(BuddyCollection::put(&mut buddy, 9), None);
+        // 全局索引 8 和 9 是伙伴
+        assert_eq!(BuddyCollection::put(&mut buddy, 8), Some(4));
+        assert_eq!(buddy.bits, 0b0001);
+
+        // 对齐按全局索引计算
+        buddy.bits = 0b1111;
+        assert_eq!(BuddyCollection::take_any(&mut buddy, 1), Some(8));
+        assert_eq!(OligarchyCollection::take_any(&mut buddy, 1, 2), None);
+        assert_eq!(OligarchyCollection::take_any(&mut buddy, 0, 2), Some(9));
+        assert!(buddy.contains(7) && !buddy.contains(6) && !buddy.contains(7 + 64));
+        assert!(BuddyLine::take(&mut buddy, 7));
+        assert!(!buddy.contains(7));
+        assert!(!BuddyLine::take(&mut buddy, 6));
+        assert!(!BuddyLine::take(&mut buddy, 7 + 64));
+    }
+
     #[test]
     fn test_take_by_index() {
         let mut buddy = UsizeBuddy {
             bits: 0b1010,
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // 提取索引 3
@@ -237,6 +358,7 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b1010,
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // count=1 应该使用 BuddyCollection 的逻辑
@@ -250,6 +372,7 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b0111, // 位 0,1,2 是空闲的
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // 取 2 个连续的位
@@ -261,11 +384,36 @@ mod tests {
         assert_eq!(OligarchyCollection::take_any(&mut buddy, 0, 2), None);
     }
 
+    #[test]
+    fn test_oligarchy_range() {
+        let mut buddy = UsizeBuddy::EMPTY;
+        buddy.init(0, 3);
+
+        buddy.put_range(4, 5);
+        buddy.put_range(20, 0);
+        assert_eq!(buddy.bits, 0b11111 << 1);
+        // 部分不在位图中时不提取
+        assert!(!buddy.take_range(7, 3));
+        assert!(!buddy.take_range(2, 2));
+        assert!(!buddy.take_range(3 + 60, 5));
+        assert_eq!(buddy.bits, 0b11111 << 1);
+        assert!(buddy.take_range(5, 3));
+        assert!(buddy.take_range(5, 0));
+        assert_eq!(buddy.bits, 0b10001 << 1);
+
+        // 整个位图
+        buddy.put_range(3, 64);
+        assert_eq!(buddy.bits, usize::MAX);
+        assert!(buddy.take_range(3, 64));
+        assert_eq!(buddy.bits, 0);
+    }
+
     #[test]
     fn test_oligarchy_take_any_count_zero() {
         let mut buddy = UsizeBuddy {
             bits: 0b1111,
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // count=0 应该返回 None
@@ -278,6 +426,7 @@ mod tests {
         let mut buddy = UsizeBuddy {
             bits: 0b111111, // 位 0-5 是空闲的
             base: 0,
+            ..UsizeBuddy::EMPTY
         };
 
         // 取 2 个连续的位，align_order=1（对齐到 2）
@@ -294,6 +443,40 @@ mod tests {
         assert_eq!(buddy.bits, 0b110000);
     }
 
+    #[test]
+    fn test_take_any_highest() {
+        let mut buddy = UsizeBuddy {
+            bits: 0b1110, // 位 1,2,3 是空闲的
+            base: 4,
+            ..UsizeBuddy::EMPTY
+        };
+        buddy.set_policy(TakePolicy::Highest);
+
+        // 应该返回最大的空闲位
+        assert_eq!(BuddyCollection::take_any(&mut buddy, 0), Some(7));
+        // 对齐到 2 的只剩位 2
+        assert_eq!(BuddyCollection::take_any(&mut buddy, 1), Some(6));
+        assert_eq!(BuddyCollection::take_any(&mut buddy, 0), Some(5));
+        assert_eq!(BuddyCollection::take_any(&mut buddy, 0), None);
+    }
+
+    #[test]
+    fn test_oligarchy_take_any_highest() {
+        let mut buddy = UsizeBuddy {
+            bits: 0b0111_0111,
+            base: 0,
+            ..UsizeBuddy::EMPTY
+        };
+        buddy.set_policy(TakePolicy::Highest);
+
+        // 从高处找 2 个连续的位
+        assert_eq!(OligarchyCollection::take_any(&mut buddy, 0, 2), Some(5));
+        assert_eq!(buddy.bits, 0b0001_0111);
+        // 对齐到 2 时只能取位 0,1
+        assert_eq!(OligarchyCollection::take_any(&mut buddy, 1, 2), Some(0));
+        assert_eq!(buddy.bits, 0b0001_0100);
+    }
+
     #[test]
     fn test_empty() {
         let buddy = UsizeBuddy::EMPTY;
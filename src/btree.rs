@@ -0,0 +1,248 @@
+use crate::{BuddyCollection, BuddyLine, OligarchyCollection, TakePolicy};
+use alloc::collections::BTreeSet;
+use core::fmt;
+
+/// 用 [`BTreeSet`] 保存空闲块序号的伙伴行。
+///
+/// - 非侵入式，元数据在堆上，容量不限
+/// - 查找和插入时间复杂度为 O(log n)，对齐和连续的提取需要扫描
+/// - 集合不记录放入次序，[`TakePolicy::Lifo`] 按 [`TakePolicy::Lowest`] 处理
+///
+/// 实现简单直接，适合在主机侧的工具中使用，或作为其他行的参照实现。
+pub struct BTreeBuddy {
+    /// 空闲块的序号。
+    set: BTreeSet<usize>,
+    /// 取块策略。
+    policy: TakePolicy,
+}
+
+impl BTreeBuddy {
+    /// 空闲块的数量。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    /// 是否没有空闲块。
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty()
+    }
+
+    /// 按序号升序迭代空闲块。
+    #[inline]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = usize> + '_ {
+        self.set.iter().copied()
+    }
+
+    /// 按策略找到 `count` 个连续的空闲块，第一个块的序号对齐到 `align_order`。
+    fn find_run(&self, align_order: usize, count: usize) -> Option<usize> {
+        let mask = (1usize << align_order) - 1;
+        if self.policy == TakePolicy::Highest {
+            // 从高处扫描，当前连续段是 [idx, end]
+            let mut end = None;
+            let mut prev = None;
+            for idx in self.set.iter().rev().copied() {
+                if prev != Some(idx + 1) {
+                    end = Some(idx);
+                }
+                prev = Some(idx);
+                let start = (end? + 1).checked_sub(count)? & !mask;
+                if start >= idx {
+                    return Some(start);
+                }
+            }
+            None
+        } else {
+            // 从低处扫描，当前连续段是 [start, idx]
+            let mut start = 0;
+            let mut prev = None;
+            for idx in self.set.iter().copied() {
+                if prev.is_none_or(|p: usize| p + 1 != idx) {
+                    start = idx;
+                }
+                prev = Some(idx);
+                let first = (start + mask) & !mask;
+                if first + count - 1 <= idx {
+                    return Some(first);
+                }
+            }
+            None
+        }
+    }
+}
+
+impl BuddyLine for BTreeBuddy {
+    const EMPTY: Self = Self {
+        set: BTreeSet::new(),
+        policy: TakePolicy::Lowest,
+    };
+
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.policy = policy;
+    }
+
+    #[inline]
+    fn take(&mut self, idx: usize) -> bool {
+        self.set.remove(&idx)
+    }
+
+    #[inline]
+    fn contains(&self, idx: usize) -> bool {
+        self.set.contains(&idx)
+    }
+}
+
+impl OligarchyCollection for BTreeBuddy {
+    fn take_any(&mut self, align_order: usize, count: usize) -> Option<usize> {
+        if count == 0 {
+            return None;
+        }
+        let start = self.find_run(align_order, count)?;
+        (start..start + count).for_each(|idx| assert!(self.set.remove(&idx)));
+        Some(start)
+    }
+
+    #[inline]
+    fn put(&mut self, idx: usize) {
+        self.set.insert(idx);
+    }
+
+    #[inline]
+    fn put_range(&mut self, idx: usize, count: usize) {
+        self.set.extend(idx..idx + count);
+    }
+
+    fn take_range(&mut self, idx: usize, count: usize) -> bool {
+        let found = self.set.range(idx..idx + count).count() == count;
+        if found {
+            (idx..idx + count).for_each(|idx| assert!(self.set.remove(&idx)));
+        }
+        found
+    }
+}
+
+impl BuddyCollection for BTreeBuddy {
+    #[inline]
+    fn take_any(&mut self, align_order: usize) -> Option<usize> {
+        OligarchyCollection::take_any(self, align_order, 1)
+    }
+
+    #[inline]
+    fn put(&mut self, idx: usize) -> Option<usize> {
+        if self.set.remove(&(idx ^ 1)) {
+            Some(idx >> 1)
+        } else {
+            self.set.insert(idx);
+            None
+        }
+    }
+}
+
+impl fmt::Debug for BTreeBuddy {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.set)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BuddyAllocator;
+    use core::{num::NonZeroUsize, ptr::NonNull};
+
+    fn line(idx: impl IntoIterator<Item = usize>) -> BTreeBuddy {
+        let mut line = BTreeBuddy::EMPTY;
+        idx.into_iter()
+            .for_each(|i| OligarchyCollection::put(&mut line, i));
+        line
+    }
+
+    #[test]
+    fn test_put_merge() {
+        let mut line = BTreeBuddy::EMPTY;
+        assert_eq!(BuddyCollection::put(&mut line, 7), None);
+        assert_eq!(BuddyCollection::put(&mut line, 9), None);
+        assert_eq!(BuddyCollection::put(&mut line, 8), Some(4));
+        assert_eq!(line.iter().collect::<alloc::vec::Vec<_>>(), [7]);
+        assert!(line.contains(7) && !line.contains(8));
+        assert!(line.take(7));
+        assert!(!line.contains(7));
+        assert!(!line.take(7));
+        assert!(line.is_empty());
+    }
+
+    #[test]
+    fn test_take_aligned() {
+        let mut line = line([3, 5, 6, 9, 12]);
+        assert_eq!(BuddyCollection::take_any(&mut line, 0), Some(3));
+        assert_eq!(BuddyCollection::take_any(&mut line, 1), Some(6));
+        assert_eq!(BuddyCollection::take_any(&mut line, 2), Some(12));
+        assert_eq!(BuddyCollection::take_any(&mut line, 2), None);
+
+        let mut line = self::line([3, 5, 6, 9, 12]);
+        line.set_policy(TakePolicy::Highest);
+        assert_eq!(BuddyCollection::take_any(&mut line, 0), Some(12));
+        assert_eq!(BuddyCollection::take_any(&mut line, 1), Some(6));
+        assert_eq!(BuddyCollection::take_any(&mut line, 0), Some(9));
+        assert_eq!(line.len(), 2);
+    }
+
+    #[test]
+    fn test_take_run() {
+        let mut line = line([1, 2, 3, 5, 6, 7, 8, 9, 12]);
+        // 1..4 长度够但起点不对齐到 4
+        assert_eq!(OligarchyCollection::take_any(&mut line, 2, 3), None);
+        assert_eq!(OligarchyCollection::take_any(&mut line, 1, 3), Some(6));
+        assert_eq!(OligarchyCollection::take_any(&mut line, 0, 3), Some(1));
+        assert_eq!(OligarchyCollection::take_any(&mut line, 0, 2), None);
+        assert_eq!(OligarchyCollection::take_any(&mut line, 0, 0), None);
+        assert_eq!(line.iter().collect::<alloc::vec::Vec<_>>(), [5, 9, 12]);
+
+        let mut line = self::line([1, 2, 3, 5, 6, 7, 8, 9, 12]);
+        line.set_policy(TakePolicy::Highest);
+        assert_eq!(OligarchyCollection::take_any(&mut line, 0, 3), Some(7));
+        assert_eq!(OligarchyCollection::take_any(&mut line, 1, 2), Some(2));
+        assert_eq!(OligarchyCollection::take_any(&mut line, 0, 2), Some(5));
+        assert_eq!(line.iter().collect::<alloc::vec::Vec<_>>(), [1, 12]);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut line = line([1, 2]);
+        line.put_range(5, 4);
+        assert_eq!(
+            line.iter().collect::<alloc::vec::Vec<_>>(),
+            [1, 2, 5, 6, 7, 8]
+        );
+        assert!(!line.take_range(2, 4));
+        assert!(!line.take_range(7, 3));
+        assert_eq!(line.len(), 6);
+        assert!(line.take_range(6, 3));
+        assert!(line.take_range(1, 2));
+        assert_eq!(line.iter().collect::<alloc::vec::Vec<_>>(), [5]);
+    }
+
+    #[test]
+    fn test_allocator() {
+        // 非侵入式，不需要真实的内存
+        let mut allocator = BuddyAllocator::<8, BTreeBuddy, BTreeBuddy>::new();
+        let base = NonNull::new(0x1000 as *mut u8).unwrap();
+        allocator.init(12, base);
+        unsafe { allocator.transfer(base, 0x7fff_f000) };
+
+        let size = NonZeroUsize::new(4096 * 3 - 100).unwrap();
+        let (p0, s0) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!((p0.as_ptr() as usize, s0), (0x4000, 4096 * 3));
+        // 连续的寡头
+        let size = NonZeroUsize::new(3 << 20).unwrap();
+        let (p1, s1) = allocator.allocate::<u8>(21, size).unwrap();
+        assert_eq!((p1.as_ptr() as usize, s1), (0x20_0000, 3 << 20));
+
+        allocator.deallocate(p0, s0);
+        allocator.deallocate(p1, s1);
+        assert_eq!(allocator.free(), 0x7fff_f000);
+    }
+}
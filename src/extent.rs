@@ -0,0 +1,676 @@
+use crate::{BuddyLine, OligarchyCollection, Order, TakePolicy};
+use core::{cmp::Ordering, fmt, ptr::NonNull};
+
+/// 以区间形式保存连续空闲寡头的侵入式寡头行。
+///
+/// - 每段连续的空闲寡头是一个区间，元数据保存在区间的第一个块中
+/// - 区间同时挂在按地址排序和按长度排序的两棵平衡树上
+/// - 放入时与相邻区间合并，时间复杂度为 O(log n)
+/// - 提取时选择放得下的最短区间（最佳适配），通常为 O(log n)
+/// - [`TakePolicy::Highest`] 时改为选择地址最高的放得下的区间，最坏需要遍历所有区间
+///
+/// 适合服务大于最大阶数的连续分配。
+/// 树不记录放入次序，[`TakePolicy::Lifo`] 按 [`TakePolicy::Lowest`] 处理。
+pub struct ExtentOligarchy {
+    /// 按地址排序的区间树。
+    by_addr: Link,
+    /// 按长度排序的区间树。
+    by_size: Link,
+    /// 寡头的阶数，用于指针和索引的转换。
+    order: Order,
+    /// 取块策略。
+    policy: TakePolicy,
+}
+
+/// 必须实现 [`Send`] 才能加锁。
+unsafe impl Send for ExtentOligarchy {}
+
+impl BuddyLine for ExtentOligarchy {
+    const INTRUSIVE_META_SIZE: usize = core::mem::size_of::<Extent>();
+
+    const EMPTY: Self = Self {
+        by_addr: None,
+        by_size: None,
+        order: Order::new(0),
+        policy: TakePolicy::Lowest,
+    };
+
+    #[inline]
+    fn init(&mut self, order: usize, _base: usize) {
+        self.order = Order::new(order);
+    }
+
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.policy = policy;
+    }
+
+    fn take(&mut self, idx: usize) -> bool {
+        match floor(self.by_addr, idx, &self.order) {
+            Some(ext) if idx < self.start(ext) + len(ext) => {
+                self.carve(ext, idx, 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn contains(&self, idx: usize) -> bool {
+        matches!(
+            floor(self.by_addr, idx, &self.order),
+            Some(ext) if idx < self.start(ext) + len(ext)
+        )
+    }
+
+    #[inline]
+    fn addressable(&self, idx: usize) -> bool {
+        self.order.idx_to_ptr::<Extent>(idx).is_some()
+    }
+}
+
+impl OligarchyCollection for ExtentOligarchy {
+    fn take_any(&mut self, align_order: usize, count: usize) -> Option<usize> {
+        if count == 0 {
+            return None;
+        }
+        let align = 1usize << align_order;
+        let highest = self.policy == TakePolicy::Highest;
+        let order = &self.order;
+        // 区间 `ext` 中放得下 `count` 个对齐块的位置
+        let fit = |ext: NonNull<Extent>| {
+            let start = order.ptr_to_idx(ext);
+            let end = start + len(ext);
+            let lowest = start.checked_next_multiple_of(align)?;
+            if highest {
+                let top = (end.checked_sub(count)? / align) * align;
+                (top >= lowest).then_some(top)
+            } else {
+                (lowest.checked_add(count)? <= end).then_some(lowest)
+            }
+        };
+        let (ext, idx) = if highest {
+            find_last(self.by_addr, &fit)?
+        } else {
+            find_fit(self.by_size, count, &fit)?
+        };
+        self.carve(ext, idx, count);
+        Some(idx)
+    }
+
+    #[inline]
+    fn put(&mut self, idx: usize) {
+        self.insert_range(idx, 1);
+    }
+
+    /// 整段作为一个区间放入，时间复杂度为 O(log n)。
+    #[inline]
+    fn put_range(&mut self, idx: usize, count: usize) {
+        if count > 0 {
+            self.insert_range(idx, count);
+        }
+    }
+
+    fn take_range(&mut self, idx: usize, count: usize) -> bool {
+        if count == 0 {
+            return true;
+        }
+        match floor(self.by_addr, idx, &self.order) {
+            Some(ext) if idx + count <= self.start(ext) + len(ext) => {
+                self.carve(ext, idx, count);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl ExtentOligarchy {
+    /// 区间首个块的序号。
+    #[inline]
+    fn start(&self, ext: NonNull<Extent>) -> usize {
+        self.order.ptr_to_idx(ext)
+    }
+
+    /// 放入从 `start` 开始的 `count` 个块，并与相邻区间合并。
+    fn insert_range(&mut self, start: usize, count: usize) {
+        let end = start + count;
+        // 前一个区间
+        let prev = floor(self.by_addr, start, &self.order);
+        let prev = prev.filter(|&ext| {
+            let prev_end = self.start(ext) + len(ext);
+            debug_assert!(prev_end <= start, "extent overlaps free blocks");
+            prev_end == start
+        });
+        // 后一个区间
+        let next = self
+            .order
+            .idx_to_ptr::<Extent>(end)
+            .and_then(|ptr| floor(self.by_addr, end, &self.order).filter(|&ext| ext == ptr));
+        debug_assert!(
+            floor(self.by_addr, end - 1, &self.order)
+                .is_none_or(|ext| self.start(ext) + len(ext) <= start),
+            "extent overlaps free blocks"
+        );
+
+        let mut count = count;
+        if let Some(next) = next {
+            remove::<ByAddr>(&mut self.by_addr, next);
+            remove::<BySize>(&mut self.by_size, next);
+            count += len(next);
+        }
+        match prev {
+            Some(mut prev) => {
+                remove::<BySize>(&mut self.by_size, prev);
+                unsafe { prev.as_mut() }.len += count;
+                insert::<BySize>(&mut self.by_size, prev);
+            }
+            None => {
+                let ptr: NonNull<Extent> =
+                    self.order.idx_to_ptr(start).expect("block address is null");
+                unsafe { ptr.as_ptr().write(Extent::new(count)) };
+                insert::<ByAddr>(&mut self.by_addr, ptr);
+                insert::<BySize>(&mut self.by_size, ptr);
+            }
+        }
+    }
+
+    /// 从区间 `ext` 中取出从 `idx` 开始的 `count` 个块。
+    ///
+    /// 区间剩余的头部和尾部仍然是空闲的区间。
+    fn carve(&mut self, mut ext: NonNull<Extent>, idx: usize, count: usize) {
+        let start = self.start(ext);
+        let end = start + len(ext);
+        debug_assert!(start <= idx && idx + count <= end);
+
+        remove::<BySize>(&mut self.by_size, ext);
+        // 头部复用原来的结点
+        if idx > start {
+            unsafe { ext.as_mut() }.len = idx - start;
+            insert::<BySize>(&mut self.by_size, ext);
+        } else {
+            remove::<ByAddr>(&mut self.by_addr, ext);
+        }
+        // 尾部需要新的结点
+        if idx + count < end {
+            let tail: NonNull<Extent> = self.order.idx_to_ptr(idx + count).unwrap();
+            unsafe { tail.as_ptr().write(Extent::new(end - idx - count)) };
+            insert::<ByAddr>(&mut self.by_addr, tail);
+            insert::<BySize>(&mut self.by_size, tail);
+        }
+    }
+}
+
+impl fmt::Debug for ExtentOligarchy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn dfs(root: Link, order: &Order, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if let Some(ext) = root {
+                let links = ByAddr::links(ext);
+                dfs(links.l, order, f)?;
+                write!(f, "{:#x}+{}, ", order.ptr_to_idx(ext), len(ext))?;
+                dfs(links.r, order, f)?;
+            }
+            Ok(())
+        }
+
+        write!(f, "[")?;
+        dfs(self.by_addr, &self.order, f)?;
+        write!(f, "]")
+    }
+}
+
+/// 树中的链接。
+type Link = Option<NonNull<Extent>>;
+
+/// 区间结点。
+///
+/// 侵入式结点，直接存储在区间的第一个块中。
+#[repr(C)]
+struct Extent {
+    /// 区间中块的数量。
+    len: usize,
+    /// 地址树中的链接。
+    addr: Links,
+    /// 长度树中的链接。
+    size: Links,
+}
+
+/// 一棵树中的左右子树和树高。
+#[repr(C)]
+struct Links {
+    l: Link,
+    r: Link,
+    h: usize,
+}
+
+impl Extent {
+    #[inline]
+    const fn new(len: usize) -> Self {
+        const LEAF: Links = Links {
+            l: None,
+            r: None,
+            h: 1,
+        };
+        Self {
+            len,
+            addr: LEAF,
+            size: LEAF,
+        }
+    }
+}
+
+/// 区间的长度。
+#[inline]
+fn len(ext: NonNull<Extent>) -> usize {
+    unsafe { ext.as_ref() }.len
+}
+
+/// 区间结点所在的一棵树。
+trait View {
+    /// 结点在这棵树中的链接。
+    fn links<'a>(ext: NonNull<Extent>) -> &'a mut Links;
+
+    /// 比较两个结点在这棵树中的顺序。
+    fn cmp(a: NonNull<Extent>, b: NonNull<Extent>) -> Ordering;
+}
+
+/// 按地址排序。
+struct ByAddr;
+
+/// 按长度排序，长度相同时按地址排序。
+struct BySize;
+
+impl View for ByAddr {
+    #[inline]
+    fn links<'a>(mut ext: NonNull<Extent>) -> &'a mut Links {
+        unsafe { &mut ext.as_mut().addr }
+    }
+
+    #[inline]
+    fn cmp(a: NonNull<Extent>, b: NonNull<Extent>) -> Ordering {
+        a.cmp(&b)
+    }
+}
+
+impl View for BySize {
+    #[inline]
+    fn links<'a>(mut ext: NonNull<Extent>) -> &'a mut Links {
+        unsafe { &mut ext.as_mut().size }
+    }
+
+    #[inline]
+    fn cmp(a: NonNull<Extent>, b: NonNull<Extent>) -> Ordering {
+        len(a).cmp(&len(b)).then(a.cmp(&b))
+    }
+}
+
+/// 树高。
+#[inline]
+fn height<V: View>(link: Link) -> usize {
+    link.map_or(0, |ext| V::links(ext).h)
+}
+
+/// 更新结点高度，必要时旋转。
+fn rebalance<V: View>(link: &mut Link) {
+    let root = link.unwrap();
+    let links = V::links(root);
+    let (l, r) = (height::<V>(links.l), height::<V>(links.r));
+    if l > r + 1 {
+        let left = V::links(links.l.unwrap());
+        if height::<V>(left.l) < height::<V>(left.r) {
+            rotate_l::<V>(&mut links.l);
+        }
+        rotate_r::<V>(link);
+    } else if r > l + 1 {
+        let right = V::links(links.r.unwrap());
+        if height::<V>(right.r) < height::<V>(right.l) {
+            rotate_r::<V>(&mut links.r);
+        }
+        rotate_l::<V>(link);
+    } else {
+        links.h = l.max(r) + 1;
+    }
+}
+
+/// 更新结点高度。
+#[inline]
+fn update<V: View>(ext: NonNull<Extent>) {
+    let links = V::links(ext);
+    links.h = height::<V>(links.l).max(height::<V>(links.r)) + 1;
+}
+
+/// 右旋。
+fn rotate_r<V: View>(link: &mut Link) {
+    let a = link.unwrap();
+    let b = V::links(a).l.unwrap();
+    V::links(a).l = V::links(b).r;
+    V::links(b).r = Some(a);
+    update::<V>(a);
+    update::<V>(b);
+    *link = Some(b);
+}
+
+/// 左旋。
+fn rotate_l<V: View>(link: &mut Link) {
+    let a = link.unwrap();
+    let b = V::links(a).r.unwrap();
+    V::links(a).r = V::links(b).l;
+    V::links(b).l = Some(a);
+    update::<V>(a);
+    update::<V>(b);
+    *link = Some(b);
+}
+
+/// 插入结点。
+fn insert<V: View>(link: &mut Link, ext: NonNull<Extent>) {
+    match *link {
+        None => {
+            let links = V::links(ext);
+            links.l = None;
+            links.r = None;
+            links.h = 1;
+            *link = Some(ext);
+        }
+        Some(root) => {
+            if V::cmp(ext, root) == Ordering::Less {
+                insert::<V>(&mut V::links(root).l, ext);
+            } else {
+                insert::<V>(&mut V::links(root).r, ext);
+            }
+            rebalance::<V>(link);
+        }
+    }
+}
+
+/// 取下最小的结点。
+fn take_min<V: View>(link: &mut Link) -> Link {
+    let root = (*link)?;
+    let links = V::links(root);
+    if links.l.is_some() {
+        let ans = take_min::<V>(&mut links.l);
+        rebalance::<V>(link);
+        ans
+    } else {
+        *link = links.r;
+        Some(root)
+    }
+}
+
+/// 移除结点。结点必须在树中。
+fn remove<V: View>(link: &mut Link, ext: NonNull<Extent>) {
+    let root = link.expect("extent is not in the tree");
+    let links = V::links(root);
+    match V::cmp(ext, root) {
+        Ordering::Less => remove::<V>(&mut links.l, ext),
+        Ordering::Greater => remove::<V>(&mut links.r, ext),
+        Ordering::Equal => match take_min::<V>(&mut links.r) {
+            // 用右子树中最小的结点替代被移除的结点
+            Some(next) => {
+                let next_links = V::links(next);
+                next_links.l = links.l;
+                next_links.r = links.r;
+                *link = Some(next);
+            }
+            // 没有右子树，左子树至多一个结点
+            None => {
+                *link = links.l;
+                return;
+            }
+        },
+    }
+    rebalance::<V>(link);
+}
+
+/// 在地址树中找到首个块序号不大于 `idx` 的最后一个区间。
+fn floor(mut link: Link, idx: usize, order: &Order) -> Link {
+    let mut ans = None;
+    while let Some(ext) = link {
+        let links = ByAddr::links(ext);
+        if order.ptr_to_idx(ext) <= idx {
+            ans = Some(ext);
+            link = links.r;
+        } else {
+            link = links.l;
+        }
+    }
+    ans
+}
+
+/// 在长度树中按长度从小到大找到第一个放得下的区间。
+///
+/// 长度小于 `count` 的子树直接跳过。返回区间和放置的位置。
+fn find_fit(
+    link: Link,
+    count: usize,
+    fit: &impl Fn(NonNull<Extent>) -> Option<usize>,
+) -> Option<(NonNull<Extent>, usize)> {
+    let ext = link?;
+    let links = BySize::links(ext);
+    if len(ext) < count {
+        find_fit(links.r, count, fit)
+    } else {
+        find_fit(links.l, count, fit)
+            .or_else(|| fit(ext).map(|idx| (ext, idx)))
+            .or_else(|| find_fit(links.r, count, fit))
+    }
+}
+
+/// 在地址树中按地址从高到低找到第一个放得下的区间。返回区间和放置的位置。
+fn find_last(
+    link: Link,
+    fit: &impl Fn(NonNull<Extent>) -> Option<usize>,
+) -> Option<(NonNull<Extent>, usize)> {
+    let ext = link?;
+    let links = ByAddr::links(ext);
+    find_last(links.r, fit)
+        .or_else(|| fit(ext).map(|idx| (ext, idx)))
+        .or_else(|| find_last(links.l, fit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDER: usize = 6;
+
+    #[repr(C, align(4096))]
+    struct Memory([u8; 64 << ORDER]);
+
+    /// 测试内存的块序号范围从 `base` 开始，共 64 个块。
+    fn new_line(memory: &mut Memory) -> (ExtentOligarchy, usize) {
+        let mut line = ExtentOligarchy::EMPTY;
+        line.init(ORDER, 0);
+        (line, memory.0.as_mut_ptr() as usize >> ORDER)
+    }
+
+    /// 检查两棵树有序且平衡，按地址顺序输出所有区间。
+    fn collect(line: &ExtentOligarchy, base: usize) -> ([(usize, usize); 64], usize) {
+        fn check<V: View>(link: Link) -> (usize, usize) {
+            match link {
+                None => (0, 0),
+                Some(ext) => {
+                    let links = V::links(ext);
+                    if let Some(l) = links.l {
+                        assert_eq!(V::cmp(l, ext), Ordering::Less);
+                    }
+                    if let Some(r) = links.r {
+                        assert_eq!(V::cmp(r, ext), Ordering::Greater);
+                    }
+                    let (hl, nl) = check::<V>(links.l);
+                    let (hr, nr) = check::<V>(links.r);
+                    assert!(hl.abs_diff(hr) <= 1);
+                    assert_eq!(links.h, hl.max(hr) + 1);
+                    (links.h, nl + nr + 1)
+                }
+            }
+        }
+        fn dfs(link: Link, base: usize, ans: &mut [(usize, usize); 64], n: &mut usize) {
+            if let Some(ext) = link {
+                let links = ByAddr::links(ext);
+                dfs(links.l, base, ans, n);
+                ans[*n] = ((ext.as_ptr() as usize >> ORDER) - base, len(ext));
+                *n += 1;
+                dfs(links.r, base, ans, n);
+            }
+        }
+
+        let (_, n_addr) = check::<ByAddr>(line.by_addr);
+        let (_, n_size) = check::<BySize>(line.by_size);
+        assert_eq!(n_addr, n_size);
+        let mut ans = [(0, 0); 64];
+        let mut n = 0;
+        dfs(line.by_addr, base, &mut ans, &mut n);
+        assert_eq!(n, n_addr);
+        (ans, n)
+    }
+
+    #[test]
+    fn test_put_coalesce() {
+        let mut memory = Memory([0; 64 << ORDER]);
+        let (mut line, base) = new_line(&mut memory);
+
+        for i in [3, 1, 7, 5, 2, 9, 6] {
+            line.put(base + i);
+        }
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(1, 3), (5, 3), (9, 1)]);
+
+        // 填上中间的空洞，三个区间合并
+        line.put(base + 4);
+        line.put(base + 8);
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(1, 9)]);
+    }
+
+    #[test]
+    fn test_take_any_best_fit() {
+        let mut memory = Memory([0; 64 << ORDER]);
+        let (mut line, base) = new_line(&mut memory);
+
+        // 区间 [0, 8)、[10, 13)、[20, 24)
+        for i in (0..8).chain(10..13).chain(20..24) {
+            line.put(base + i);
+        }
+        // 最佳适配选择长度为 3 的区间
+        assert_eq!(line.take_any(0, 3), Some(base + 10));
+        // 然后是长度为 4 的区间
+        assert_eq!(line.take_any(0, 2), Some(base + 20));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(0, 8), (22, 2)]);
+        // 放不下
+        assert_eq!(line.take_any(0, 9), None);
+        assert_eq!(line.take_any(0, 0), None);
+        assert_eq!(line.take_any(0, 8), Some(base));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(22, 2)]);
+    }
+
+    #[test]
+    fn test_take_any_aligned() {
+        let mut memory = Memory([0; 64 << ORDER]);
+        let (mut line, base) = new_line(&mut memory);
+        // 内存对齐到 4096，即序号对齐到 64
+        assert_eq!(base % 64, 0);
+
+        // 区间 [1, 6)、[9, 20)
+        for i in (1..6).chain(9..20) {
+            line.put(base + i);
+        }
+        // 第一个区间中没有对齐到 4 的 3 个连续块
+        assert_eq!(line.take_any(2, 3), Some(base + 12));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(1, 5), (9, 3), (15, 5)]);
+        assert_eq!(line.take_any(3, 2), Some(base + 16));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(1, 5), (9, 3), (15, 1), (18, 2)]);
+    }
+
+    #[test]
+    fn test_take_any_highest() {
+        let mut memory = Memory([0; 64 << ORDER]);
+        let (mut line, base) = new_line(&mut memory);
+        line.set_policy(TakePolicy::Highest);
+
+        for i in 0..16 {
+            line.put(base + i);
+        }
+        assert_eq!(line.take_any(0, 3), Some(base + 13));
+        assert_eq!(line.take_any(2, 2), Some(base + 8));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(0, 8), (10, 3)]);
+
+        // 不选最短的区间，从地址最高的区间取
+        assert_eq!(line.take_any(0, 1), Some(base + 12));
+        assert_eq!(line.take_any(0, 3), Some(base + 5));
+        assert_eq!(line.take_any(0, 4), Some(base + 1));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(0, 1), (10, 2)]);
+    }
+
+    #[test]
+    fn test_take() {
+        let mut memory = Memory([0; 64 << ORDER]);
+        let (mut line, base) = new_line(&mut memory);
+
+        for i in 0..10 {
+            line.put(base + i);
+        }
+        assert!(line.contains(base + 4) && !line.contains(base + 10));
+        assert!(line.take(base + 4));
+        assert!(!line.contains(base + 4) && line.contains(base + 5));
+        assert!(!line.take(base + 4));
+        assert!(line.take(base));
+        assert!(line.take(base + 9));
+        assert!(!line.take(base + 10));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(1, 3), (5, 4)]);
+    }
+
+    #[test]
+    fn test_range() {
+        let mut memory = Memory([0; 64 << ORDER]);
+        let (mut line, base) = new_line(&mut memory);
+
+        // 整段放入并与两侧的区间合并
+        line.put(base + 2);
+        line.put(base + 9);
+        line.put_range(base + 3, 6);
+        line.put_range(base + 20, 4);
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(2, 8), (20, 4)]);
+
+        // 只能从一个区间中提取
+        assert!(!line.take_range(base + 8, 3));
+        assert!(!line.take_range(base + 22, 3));
+        assert!(line.take_range(base + 4, 3));
+        assert!(line.take_range(base + 20, 4));
+        assert!(line.take_range(base + 20, 0));
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(2, 2), (7, 3)]);
+    }
+
+    #[test]
+    fn test_many_extents_balanced() {
+        let mut memory = Memory([0; 64 << ORDER]);
+        let (mut line, base) = new_line(&mut memory);
+
+        // 32 个独立的块
+        for i in (0..64).step_by(2) {
+            line.put(base + i);
+        }
+        let (_, n) = collect(&line, base);
+        assert_eq!(n, 32);
+        // 逐个填上空洞，最后只剩一个区间
+        for i in (1..63).step_by(2) {
+            line.put(base + i);
+            collect(&line, base);
+        }
+        let (ans, n) = collect(&line, base);
+        assert_eq!(&ans[..n], &[(0, 63)]);
+        // 取出所有块
+        for _ in 0..63 {
+            assert!(line.take_any(0, 1).is_some());
+            collect(&line, base);
+        }
+        assert_eq!(line.take_any(0, 1), None);
+    }
+}
@@ -0,0 +1,488 @@
+use crate::{BuddyError, nonzero};
+use core::{alloc::Layout, fmt, num::NonZeroUsize, ptr::NonNull};
+
+/// 隐式完全二叉树伙伴分配器。
+///
+/// 整个分配器是一棵存放在数组里的完全二叉树，每个节点对应一个块，记录其中最大空闲块的阶数。
+/// 分配和回收都是 O(log n) 的，元数据是调用者提供的一块连续缓冲区，不需要写被管理的内存，
+/// 因此也可以管理不可写的内存，可以代替整个 [`BuddyAllocator`](crate::BuddyAllocator)。
+///
+/// 树的根是 `1 << max_order` 字节的窗口，从 `init` 的基址开始；
+/// 叶子是 `1 << min_order` 字节的页。`max_order = min_order + layers`，
+/// 其中层数由元数据缓冲区的长度决定，参见 [`meta_len`](Self::meta_len)。
+///
+/// 块相对于基址对齐。基址对齐到 `1 << max_order` 时，可以满足任何不超过 `max_order` 阶的对齐。
+pub struct ImplicitBuddyAllocator<'a> {
+    /// 节点数组。`nodes[i - 1]` 是节点 `i` 的值，根是节点 1，节点 `i` 的孩子是 `2i` 和 `2i + 1`。
+    ///
+    /// 节点的值为 0 表示没有空闲块，为 `k + 1` 表示最大空闲块的阶数是 `min_order + k`。
+    nodes: Option<&'a mut [u8]>,
+
+    /// 最小阶数。
+    min_order: usize,
+
+    /// 层数。根的阶数比叶子高这么多。
+    layers: usize,
+
+    /// 窗口的基址。
+    base: usize,
+
+    /// 空闲容量。
+    free: usize,
+
+    /// 总容量。
+    capacity: usize,
+}
+
+impl<'a> ImplicitBuddyAllocator<'a> {
+    /// 构造分配器。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            nodes: None,
+            min_order: 0,
+            layers: 0,
+            base: 0,
+            free: 0,
+            capacity: 0,
+        }
+    }
+
+    /// 管理 `layers` 层需要的元数据长度。
+    #[inline]
+    pub const fn meta_len(layers: usize) -> usize {
+        (2 << layers) - 1
+    }
+
+    /// 运行时初始化。
+    ///
+    /// 设置分配器分配的最小阶数和基址，使用 `nodes` 保存元数据。
+    /// 层数是 `nodes` 能容纳的最大层数，多余的部分不使用。
+    pub fn init<T>(&mut self, min_order: usize, base: NonNull<T>, nodes: &'a mut [u8]) {
+        assert_eq!(
+            0, self.capacity,
+            "init is not allowed after any transfering"
+        );
+        assert!(!nodes.is_empty(), "nodes is too short");
+
+        let layers = ((nodes.len() + 1).ilog2() - 1) as usize;
+        assert!(layers < u8::MAX as usize);
+        assert!(min_order + layers < usize::BITS as usize);
+
+        let base = base.as_ptr() as usize;
+        assert_eq!(
+            0,
+            base & ((1 << min_order) - 1),
+            "base must align to minium order"
+        );
+
+        nodes.fill(0);
+        self.nodes = Some(nodes);
+        self.min_order = min_order;
+        self.layers = layers;
+        self.base = base;
+    }
+
+    /// 返回分配器管理的总容量。
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 返回分配器剩余的空间容量。
+    #[inline]
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// 最大阶数。根的阶数。
+    #[inline]
+    fn max_order(&self) -> usize {
+        self.min_order + self.layers
+    }
+
+    /// 将一个 `ptr` 指向的长度为 `usize` 的内存块转移给分配器。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证：
+    ///
+    /// - 这个内存块没有被其他任何对象引用；
+    /// - 这个内存块和已经托管的内存块不重叠；
+    /// - 这个内存块在分配器的窗口内。
+    #[inline]
+    pub unsafe fn transfer<T>(&mut self, ptr: NonNull<T>, size: usize) {
+        self.capacity += size;
+        self.deallocate(ptr, size)
+    }
+
+    /// 从分配器夺走一个对齐到 `align_order` 阶，长度为 `size` 的内存块。
+    #[inline]
+    pub fn snatch<T>(
+        &mut self,
+        align_order: usize,
+        size: NonZeroUsize,
+    ) -> Result<(NonNull<T>, usize), BuddyError> {
+        let ans = self.allocate(align_order, size);
+        if let Ok((_, size)) = ans {
+            self.capacity -= size;
+        }
+        ans
+    }
+
+    /// 分配可容纳 `T` 对象的内存块。
+    #[inline]
+    pub fn allocate_type<T>(&mut self) -> Result<(NonNull<T>, usize), BuddyError> {
+        self.allocate_layout(Layout::new::<T>())
+    }
+
+    /// 分配符合 `layout` 布局的内存块。
+    #[inline]
+    pub fn allocate_layout<T>(
+        &mut self,
+        layout: Layout,
+    ) -> Result<(NonNull<T>, usize), BuddyError> {
+        if let Some(size) = NonZeroUsize::new(layout.size()) {
+            self.allocate(layout.align().trailing_zeros() as _, size)
+        } else {
+            Ok((NonNull::from(&mut *self).cast(), 0))
+        }
+    }
+
+    /// 分配。
+    ///
+    /// 如果分配成功，返回一个 `(指针, 长度)` 二元组。
+    pub fn allocate<T>(
+        &mut self,
+        align_order: usize,
+        size: NonZeroUsize,
+    ) -> Result<(NonNull<T>, usize), BuddyError> {
+        let max_order = self.max_order();
+
+        // 要分配的容量
+        let page_mask = (1usize << self.min_order) - 1;
+        let ans_size = (size.get() + page_mask) & !page_mask;
+        // 分配的阶数，块相对基址对齐，所以对齐也要求更大的块
+        let size_order = nonzero(ans_size.next_power_of_two()).trailing_zeros() as usize;
+        let order = size_order.max(align_order.min(max_order));
+        if order > max_order || self.nodes.is_none() {
+            Err(BuddyError)?
+        }
+        // 分配
+        let layer = order - self.min_order;
+        let offset = self.take(layer).ok_or(BuddyError)?;
+        let ptr = self.base + offset;
+        if ptr & ((1 << align_order) - 1) != 0 {
+            // 基址的对齐不够
+            self.put(layer, offset);
+            Err(BuddyError)?
+        }
+        self.free -= 1 << order;
+        // 存回多分配的
+        if (1 << order) > ans_size {
+            self.deallocate(
+                unsafe { NonNull::new_unchecked((ptr + ans_size) as *mut u8) },
+                (1 << order) - ans_size,
+            );
+        }
+        Ok((unsafe { NonNull::new_unchecked(ptr as *mut T) }, ans_size))
+    }
+
+    /// 根据布局回收。
+    ///
+    /// # Safety
+    ///
+    /// 这个方法认为 `ptr` 是根据 `layout` 分配出来的，
+    /// 因此长度不小于 `layout.size()` 并且对齐到 `self.min_order`。
+    pub unsafe fn deallocate_layout<T>(&mut self, ptr: NonNull<T>, layout: Layout) {
+        debug_assert!((1 << (ptr.as_ptr() as usize).trailing_zeros()) >= layout.align());
+
+        let mask = (1 << self.min_order) - 1;
+        self.deallocate(ptr, (layout.size() + mask) & !mask)
+    }
+
+    /// 回收。
+    ///
+    /// # Notice
+    ///
+    /// 调用者需要保证 `size` 对齐了分配器的最小阶数。
+    pub fn deallocate<T>(&mut self, ptr: NonNull<T>, size: usize) {
+        debug_assert!(
+            size.trailing_zeros() as usize >= self.min_order,
+            "size must align to minium order"
+        );
+
+        let max_order = self.max_order();
+        let mut offset = (ptr.as_ptr() as usize)
+            .checked_sub(self.base)
+            .expect("block is out of the window");
+        let end = offset + size;
+        assert!(
+            end <= 1 << max_order && self.nodes.is_some(),
+            "block is out of the window"
+        );
+
+        while offset < end {
+            // 剩余长度
+            let len = nonzero(end - offset);
+            // 偏移的对齐决定最大阶数
+            let order_ptr = if offset == 0 {
+                max_order as u32
+            } else {
+                offset.trailing_zeros()
+            };
+            // 长度向下取整也决定最大阶数
+            let order_len = usize::BITS - len.leading_zeros() - 1;
+            // 实际阶数是两个最大阶数中较小的那个
+            let order = order_ptr.min(order_len) as usize;
+            // 释放
+            self.put(order - self.min_order, offset);
+            offset += 1 << order;
+        }
+        self.free += size;
+        assert!(
+            self.free <= self.capacity,
+            "something wrong with the free bytes, it is larger than the capacity: {} > {}",
+            self.free,
+            self.capacity
+        );
+    }
+
+    /// 取出一个 `layer` 层的空闲块，返回它相对基址的偏移。
+    fn take(&mut self, layer: usize) -> Option<usize> {
+        let layers = self.layers;
+        let nodes = self.nodes.as_deref_mut().unwrap();
+        if (nodes[0] as usize) <= layer {
+            return None;
+        }
+        // 找到最左的足够大的块
+        let mut i = 1;
+        for level in (layer + 1..=layers).rev() {
+            push_down(nodes, i, level);
+            i <<= 1;
+            if (nodes[i - 1] as usize) <= layer {
+                i += 1;
+            }
+        }
+        nodes[i - 1] = 0;
+        pull_up(nodes, i, layer);
+        Some((i - (1 << (layers - layer))) << (self.min_order + layer))
+    }
+
+    /// 放回一个 `layer` 层的块，`offset` 是它相对基址的偏移。
+    fn put(&mut self, layer: usize, offset: usize) {
+        let layers = self.layers;
+        let nodes = self.nodes.as_deref_mut().unwrap();
+        let i = (1 << (layers - layer)) + (offset >> (self.min_order + layer));
+        // 把祖先的状态传到路径上
+        for level in (layer + 1..=layers).rev() {
+            push_down(nodes, i >> (level - layer), level);
+        }
+        debug_assert_eq!(0, nodes[i - 1], "double free");
+        nodes[i - 1] = (layer + 1) as _;
+        pull_up(nodes, i, layer);
+    }
+}
+
+impl Default for ImplicitBuddyAllocator<'_> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 整块分配或整块空闲的节点，它的孩子可能没有更新，访问孩子之前要先下传状态。
+#[inline]
+fn push_down(nodes: &mut [u8], i: usize, level: usize) {
+    let val = match nodes[i - 1] as usize {
+        0 => 0,
+        v if v == level + 1 => level as u8,
+        _ => return,
+    };
+    nodes[2 * i - 1] = val;
+    nodes[2 * i] = val;
+}
+
+/// 从 `level` 层的节点 `i` 开始，向上更新所有祖先。
+#[inline]
+fn pull_up(nodes: &mut [u8], mut i: usize, mut level: usize) {
+    while i > 1 {
+        i >>= 1;
+        let l = nodes[2 * i - 1];
+        let r = nodes[2 * i];
+        // 两个孩子都整块空闲时合并
+        nodes[i - 1] = if l as usize == level + 1 && l == r {
+            l + 1
+        } else {
+            l.max(r)
+        };
+        level += 1;
+    }
+}
+
+impl fmt::Debug for ImplicitBuddyAllocator<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "ImplicitBuddyAllocator@{:#018x}",
+            self as *const _ as usize
+        )?;
+        writeln!(f, "---------------------------------")?;
+        writeln!(
+            f,
+            "window: {:#x}..{:#x}",
+            self.base,
+            self.base + (1 << self.max_order())
+        )?;
+        writeln!(f, "free: {:#x}/{:#x}", self.free, self.capacity)?;
+        match self.nodes.as_deref().map(|nodes| nodes[0]) {
+            Some(0) | None => writeln!(f, "largest: -"),
+            Some(v) => writeln!(f, "largest: {}", self.min_order + v as usize - 1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(65536))]
+    struct Block([u8; 65536]);
+
+    /// 4 层，64 KiB 窗口，4 KiB 页。
+    fn allocator(block: &mut Block, nodes: &'static mut [u8]) -> ImplicitBuddyAllocator<'static> {
+        let mut allocator = ImplicitBuddyAllocator::new();
+        let ptr = NonNull::from(block);
+        allocator.init(12, ptr, nodes);
+        assert_eq!(allocator.max_order(), 16);
+        unsafe { allocator.transfer(ptr, 65536) };
+        allocator
+    }
+
+    #[test]
+    fn test_meta_len() {
+        assert_eq!(ImplicitBuddyAllocator::meta_len(0), 1);
+        assert_eq!(ImplicitBuddyAllocator::meta_len(4), 31);
+    }
+
+    #[test]
+    fn test_allocate_all() {
+        static mut BLOCK: Block = Block([0; 65536]);
+        static mut NODES: [u8; 31] = [0; 31];
+        let block = unsafe { &mut *core::ptr::addr_of_mut!(BLOCK) };
+        let nodes = unsafe { &mut *core::ptr::addr_of_mut!(NODES) };
+        let base = block as *mut _ as usize;
+        let mut allocator = allocator(block, nodes);
+
+        // 逐页分配，地址递增
+        let size = NonZeroUsize::new(4096).unwrap();
+        let mut pages = [NonNull::<u8>::dangling(); 16];
+        for (i, p) in pages.iter_mut().enumerate() {
+            *p = allocator.allocate(0, size).unwrap().0;
+            assert_eq!(p.as_ptr() as usize, base + i * 4096);
+        }
+        assert_eq!(allocator.free(), 0);
+        assert!(allocator.allocate::<u8>(0, size).is_err());
+
+        // 交错释放后合并成一个整块
+        for p in pages
+            .iter()
+            .step_by(2)
+            .chain(pages.iter().skip(1).step_by(2))
+        {
+            allocator.deallocate(*p, 4096);
+        }
+        assert_eq!(allocator.free(), 65536);
+        let size = NonZeroUsize::new(65536).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!((p.as_ptr() as usize, s), (base, 65536));
+    }
+
+    #[test]
+    fn test_partial_blocks() {
+        static mut BLOCK: Block = Block([0; 65536]);
+        static mut NODES: [u8; 31] = [0; 31];
+        let block = unsafe { &mut *core::ptr::addr_of_mut!(BLOCK) };
+        let nodes = unsafe { &mut *core::ptr::addr_of_mut!(NODES) };
+        let base = block as *mut _ as usize;
+        let mut allocator = allocator(block, nodes);
+
+        // 3 页占一个 4 页的块，第 4 页存回
+        let size = NonZeroUsize::new(3 * 4096).unwrap();
+        let (p0, s0) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!((p0.as_ptr() as usize, s0), (base, 3 * 4096));
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p1, _) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p1.as_ptr() as usize, base + 3 * 4096);
+
+        // 分两次分配的块一次释放
+        allocator.deallocate(p0, 4 * 4096);
+        assert_eq!(allocator.free(), 65536);
+
+        // 整块分配的块分段释放
+        let size = NonZeroUsize::new(65536).unwrap();
+        let (p, _) = allocator.allocate::<u8>(0, size).unwrap();
+        allocator.deallocate(p, 4096);
+        let size = NonZeroUsize::new(8192).unwrap();
+        assert!(allocator.allocate::<u8>(0, size).is_err());
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p1, _) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p1, p);
+        allocator.deallocate(p, 65536);
+        assert_eq!(allocator.free(), 65536);
+    }
+
+    #[test]
+    fn test_align() {
+        static mut BLOCK: Block = Block([0; 65536]);
+        static mut NODES: [u8; 31] = [0; 31];
+        let block = unsafe { &mut *core::ptr::addr_of_mut!(BLOCK) };
+        let nodes = unsafe { &mut *core::ptr::addr_of_mut!(NODES) };
+        let base = block as *mut _ as usize;
+        let mut allocator = allocator(block, nodes);
+
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p0, _) = allocator.allocate::<u8>(0, size).unwrap();
+        // 对齐到 16 KiB 的页跳过前一个 16 KiB 块，多余的部分存回
+        let (p1, s1) = allocator.allocate::<u8>(14, size).unwrap();
+        assert_eq!((p1.as_ptr() as usize, s1), (base + 16384, 4096));
+        assert_eq!(allocator.free(), 65536 - 2 * 4096);
+        let (p2, _) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p2.as_ptr() as usize, base + 4096);
+        // 超过窗口的对齐只有基址能满足
+        assert!(allocator.allocate::<u8>(20, size).is_err());
+
+        allocator.deallocate(p0, 4096);
+        allocator.deallocate(p1, 4096);
+        allocator.deallocate(p2, 4096);
+        assert_eq!(allocator.free(), 65536);
+    }
+
+    #[test]
+    fn test_partial_transfer() {
+        static mut BLOCK: Block = Block([0; 65536]);
+        static mut NODES: [u8; 63] = [0; 63];
+        let block = unsafe { &mut *core::ptr::addr_of_mut!(BLOCK) };
+        let nodes = unsafe { &mut *core::ptr::addr_of_mut!(NODES) };
+        let ptr = NonNull::from(block).cast::<u8>();
+
+        // 窗口比内存大，只转移中间的一部分
+        let mut allocator = ImplicitBuddyAllocator::new();
+        allocator.init(11, ptr, nodes);
+        assert_eq!(allocator.max_order(), 16);
+        let start = unsafe { ptr.add(2048) };
+        unsafe { allocator.transfer(start, 5 * 2048) };
+        assert_eq!(allocator.capacity(), 5 * 2048);
+
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p0, _) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p0, unsafe { ptr.add(4096) });
+        let (p1, _) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p1, unsafe { ptr.add(8192) });
+        assert!(allocator.allocate::<u8>(0, size).is_err());
+        let size = NonZeroUsize::new(2048).unwrap();
+        let (p2, _) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p2, start);
+        assert_eq!(allocator.free(), 0);
+    }
+}
@@ -1,17 +1,45 @@
 //! 伙伴分配器。
+//!
+//! 启用 `alloc` 特性（或包含它的 `std` 特性）时提供基于堆的 `BTreeBuddy`。
 
 #![no_std]
 #![deny(warnings, unstable_features, missing_docs)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+mod atomic;
 mod avl;
+mod backing;
+mod balloon;
 mod bitmap;
+#[cfg(feature = "alloc")]
+mod btree;
+mod extent;
+mod implicit;
 mod linked_list;
+pub mod memmap;
+mod region;
+mod split;
+mod trace;
 
+pub use atomic::{AtomicBitmapBuddy, ConcurrentBuddyAllocator};
 pub use avl::AvlBuddy;
+pub use backing::Backing;
+#[cfg(all(feature = "std", target_os = "linux"))]
+pub use backing::MmapBacking;
+pub use balloon::Balloon;
 pub use bitmap::UsizeBuddy;
+#[cfg(feature = "alloc")]
+pub use btree::BTreeBuddy;
+pub use extent::ExtentOligarchy;
+pub use implicit::ImplicitBuddyAllocator;
 pub use linked_list::LinkedListBuddy;
+pub use region::RegionTable;
+pub use split::SplitLines;
+pub use trace::{TraceError, TraceReader, TraceRecord, TraceRecorder, TraceSink};
 
-use core::{alloc::Layout, fmt, num::NonZeroUsize, ptr::NonNull};
+use core::{alloc::Layout, fmt, marker::PhantomData, num::NonZeroUsize, ptr::NonNull};
 
 /// 伙伴分配器的一个行。
 pub trait BuddyLine {
@@ -25,11 +53,37 @@ pub trait BuddyLine {
     #[inline]
     fn init(&mut self, _order: usize, _base: usize) {}
 
+    /// 设置提取元素的策略。
+    ///
+    /// 无法区分某种策略的集合可以忽略它。
+    #[inline]
+    fn set_policy(&mut self, _policy: TakePolicy) {}
+
     /// 提取指定位置的元素，返回是否提取到。
+    ///
+    /// [`BuddyAllocator::reclaim_range`] 使用这个方法取出指定的空闲块。
     #[inline]
     fn take(&mut self, _idx: usize) -> bool {
         unimplemented!()
     }
+
+    /// 判断指定位置的元素是否在集合中。
+    ///
+    /// [`BuddyAllocator::memory_map`] 使用这个方法判断一个块是否空闲。
+    /// 默认没有实现，以 [`RegionTable`] 作为观察者并调用 `memory_map` 时所有的行都需要实现它。
+    #[inline]
+    fn contains(&self, _idx: usize) -> bool {
+        unimplemented!()
+    }
+
+    /// 判断序号为 `idx` 的块能否放入集合。
+    ///
+    /// 容量有限的集合（例如位图）只能容纳基序号附近的块，侵入式集合不能容纳地址为 0 的块。
+    /// [`BuddyAllocator::try_transfer`] 使用这个方法在转移前检查。
+    #[inline]
+    fn addressable(&self, _idx: usize) -> bool {
+        true
+    }
 }
 
 /// 寡头集合。伙伴分配器的顶层，不再合并。
@@ -41,6 +95,28 @@ pub trait OligarchyCollection: BuddyLine {
 
     /// 放入一个元素 `idx`。
     fn put(&mut self, idx: usize);
+
+    /// 放入从 `idx` 开始的 `count` 个连续的元素。
+    ///
+    /// 默认逐个放入。转移大块内存时会一次放入大量寡头，实现可以批量设置。
+    #[inline]
+    fn put_range(&mut self, idx: usize, count: usize) {
+        (idx..idx + count).for_each(|idx| self.put(idx));
+    }
+
+    /// 提取从 `idx` 开始的 `count` 个连续的元素。
+    ///
+    /// 只有所有元素都在集合中时才提取并返回 `true`，否则集合不变，返回 `false`。
+    /// 默认逐个用 [`BuddyLine::take`] 提取，遇到不在集合中的元素时放回已经提取的元素。
+    fn take_range(&mut self, idx: usize, count: usize) -> bool {
+        for i in idx..idx + count {
+            if !self.take(i) {
+                self.put_range(idx, i - idx);
+                return false;
+            }
+        }
+        true
+    }
 }
 
 /// 伙伴集合。一组同阶的伙伴。
@@ -57,19 +133,320 @@ pub trait BuddyCollection: BuddyLine {
     fn put(&mut self, idx: usize) -> Option<usize>;
 }
 
+/// 取块策略。
+///
+/// 决定集合中有多个满足要求的元素时优先提取哪一个。
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum TakePolicy {
+    /// 优先提取地址最低的块。
+    #[default]
+    Lowest,
+    /// 优先提取地址最高的块。
+    ///
+    /// 可以把低地址留给只能访问低地址的设备。
+    Highest,
+    /// 优先提取最后放入的块。
+    Lifo,
+}
+
+/// 伙伴行的存储。
+///
+/// 分配器按层访问存储中的行，第 `layer` 层的行管理阶数为 `min_order + layer` 的块。
+/// 各层的行可以是不同的类型，参见 [`SplitLines`]；`B` 是第 0 层的行，决定分配器支持的最小阶数。
+pub trait LineStorage<B> {
+    /// 空存储。用于静态初始化。
+    const EMPTY: Self;
+
+    /// 行数。
+    fn layers(&self) -> usize;
+
+    /// 以最小阶数 `min_order` 和基址 `base` 初始化所有的行。
+    fn init(&mut self, min_order: usize, base: usize);
+
+    /// 设置所有行的取块策略。
+    fn set_policy(&mut self, policy: TakePolicy);
+
+    /// 从第 `layer` 层提取任何一个满足 `align_order` 的块，参见 [`BuddyCollection::take_any`]。
+    fn take_any(&mut self, layer: usize, align_order: usize) -> Option<usize>;
+
+    /// 向第 `layer` 层放入块 `idx`，参见 [`BuddyCollection::put`]。
+    fn put(&mut self, layer: usize, idx: usize) -> Option<usize>;
+
+    /// 从第 `layer` 层提取块 `idx`，参见 [`BuddyLine::take`]。
+    fn take(&mut self, layer: usize, idx: usize) -> bool;
+
+    /// 判断块 `idx` 是否在第 `layer` 层中，参见 [`BuddyLine::contains`]。
+    ///
+    /// 默认没有实现，与 [`BuddyLine::contains`] 相同。
+    #[inline]
+    fn contains(&self, _layer: usize, _idx: usize) -> bool {
+        unimplemented!()
+    }
+
+    /// 判断块 `idx` 能否放入第 `layer` 层，参见 [`BuddyLine::addressable`]。
+    fn addressable(&self, layer: usize, idx: usize) -> bool;
+}
+
+/// 可以逐层输出的伙伴行存储。
+///
+/// 分配器的 [`Debug`](fmt::Debug) 以它逐行输出各层。
+pub trait DebugLines {
+    /// 输出第 `layer` 层的行。
+    fn fmt_line(&self, layer: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+/// 行数在编译时确定的存储。
+impl<B: BuddyCollection, const N: usize> LineStorage<B> for [B; N] {
+    const EMPTY: Self = [B::EMPTY; N];
+
+    #[inline]
+    fn layers(&self) -> usize {
+        N
+    }
+
+    #[inline]
+    fn init(&mut self, min_order: usize, base: usize) {
+        init_lines(self, min_order, base)
+    }
+
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.iter_mut().for_each(|b| b.set_policy(policy));
+    }
+
+    #[inline]
+    fn take_any(&mut self, layer: usize, align_order: usize) -> Option<usize> {
+        self[layer].take_any(align_order)
+    }
+
+    #[inline]
+    fn put(&mut self, layer: usize, idx: usize) -> Option<usize> {
+        self[layer].put(idx)
+    }
+
+    #[inline]
+    fn take(&mut self, layer: usize, idx: usize) -> bool {
+        self[layer].take(idx)
+    }
+
+    #[inline]
+    fn contains(&self, layer: usize, idx: usize) -> bool {
+        self[layer].contains(idx)
+    }
+
+    #[inline]
+    fn addressable(&self, layer: usize, idx: usize) -> bool {
+        self[layer].addressable(idx)
+    }
+}
+
+/// 调用者提供的存储，行数在运行时确定。
+///
+/// 提供存储之前没有任何行。
+impl<B: BuddyCollection> LineStorage<B> for Option<&mut [B]> {
+    const EMPTY: Self = None;
+
+    #[inline]
+    fn layers(&self) -> usize {
+        self.as_deref().map_or(0, <[B]>::len)
+    }
+
+    #[inline]
+    fn init(&mut self, min_order: usize, base: usize) {
+        if let Some(lines) = self {
+            init_lines(lines, min_order, base)
+        }
+    }
+
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        if let Some(lines) = self {
+            lines.iter_mut().for_each(|b| b.set_policy(policy));
+        }
+    }
+
+    #[inline]
+    fn take_any(&mut self, layer: usize, align_order: usize) -> Option<usize> {
+        self.as_deref_mut().unwrap()[layer].take_any(align_order)
+    }
+
+    #[inline]
+    fn put(&mut self, layer: usize, idx: usize) -> Option<usize> {
+        self.as_deref_mut().unwrap()[layer].put(idx)
+    }
+
+    #[inline]
+    fn take(&mut self, layer: usize, idx: usize) -> bool {
+        self.as_deref_mut().unwrap()[layer].take(idx)
+    }
+
+    #[inline]
+    fn contains(&self, layer: usize, idx: usize) -> bool {
+        self.as_deref().unwrap()[layer].contains(idx)
+    }
+
+    #[inline]
+    fn addressable(&self, layer: usize, idx: usize) -> bool {
+        self.as_deref().unwrap()[layer].addressable(idx)
+    }
+}
+
+impl<B: fmt::Debug, const N: usize> DebugLines for [B; N] {
+    #[inline]
+    fn fmt_line(&self, layer: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self[layer].fmt(f)
+    }
+}
+
+impl<B: fmt::Debug> DebugLines for Option<&mut [B]> {
+    #[inline]
+    fn fmt_line(&self, layer: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_deref().unwrap()[layer].fmt(f)
+    }
+}
+
+/// 初始化一组连续的行，`lines[i]` 的阶数是 `min_order + i`。
+#[inline]
+pub(crate) fn init_lines<B: BuddyLine>(lines: &mut [B], min_order: usize, base: usize) {
+    lines.iter_mut().enumerate().for_each(|(i, c)| {
+        let o = min_order + i;
+        c.init(o, base >> o)
+    });
+}
+
+/// 伙伴分配器的观察者。
+///
+/// 分配器在每个公开操作完成后通知观察者，可以用来记录操作序列，参见 [`TraceRecorder`]。
+/// 只有转移在内存块存入行之前通知，观察者可以借此拒绝重叠的转移，参见 [`RegionTable`]。
+/// 操作过程中块的拆分与合并也会通知观察者，可以用来统计各层的活动。
+/// 所有方法默认什么都不做，默认的 [`NoopObserver`] 不产生任何开销。
+pub trait AllocObserver {
+    /// 分配器初始化，`layers` 是伙伴行的层数。
+    #[inline]
+    fn on_init(&mut self, _min_order: usize, _base: usize, _layers: usize) {}
+
+    /// 内存块 `[ptr, ptr + size)` 转移给分配器。
+    ///
+    /// 在内存块存入行之前调用，这里 panic 不会破坏分配器的状态。
+    #[inline]
+    fn on_transfer(&mut self, _ptr: usize, _size: usize) {}
+
+    /// 分配了对齐到 `align_order`、长度为 `size` 的块，`result` 是分配到的 `(地址, 长度)`。
+    ///
+    /// `layer` 是取到块的层，等于层数时表示块来自寡头行；分配失败时是按长度应当取块的层。
+    #[inline]
+    fn on_allocate(
+        &mut self,
+        _align_order: usize,
+        _size: usize,
+        _layer: usize,
+        _result: Option<(usize, usize)>,
+    ) {
+    }
+
+    /// 夺走了对齐到 `align_order`、长度为 `size` 的块，`result` 是夺走的 `(地址, 长度)`。
+    ///
+    /// `layer` 的含义与 [`on_allocate`](Self::on_allocate) 相同。
+    #[inline]
+    fn on_snatch(
+        &mut self,
+        _align_order: usize,
+        _size: usize,
+        _layer: usize,
+        _result: Option<(usize, usize)>,
+    ) {
+    }
+
+    /// 回收了内存块 `[ptr, ptr + size)`。
+    #[inline]
+    fn on_deallocate(&mut self, _ptr: usize, _size: usize) {}
+
+    /// 从范围 `[ptr, ptr + size)` 取回了 `reclaimed` 字节。
+    #[inline]
+    fn on_reclaim(&mut self, _ptr: usize, _size: usize, _reclaimed: usize) {}
+
+    /// 空闲块 `[ptr, ptr + size)` 被取出，不再归分配器管理。
+    ///
+    /// [`reclaim_range`](BuddyAllocator::reclaim_range)、[`split_off`](BuddyAllocator::split_off)
+    /// 和 [`trim`](BuddyAllocator::trim) 对取出的每一块调用，
+    /// 之后再调用 [`on_reclaim`](Self::on_reclaim)。
+    #[inline]
+    fn on_extract(&mut self, _ptr: usize, _size: usize) {}
+
+    /// 第 `layer` 层的块 `[ptr, ptr + size)` 拆分成了第 `layer - 1` 层的两个伙伴。
+    ///
+    /// `layer` 等于层数时表示拆分的是寡头。
+    #[inline]
+    fn on_split(&mut self, _ptr: usize, _size: usize, _layer: usize) {}
+
+    /// 第 `layer - 1` 层的两个伙伴合并成了第 `layer` 层的块 `[ptr, ptr + size)`。
+    ///
+    /// `layer` 等于层数时表示合并成了寡头。
+    #[inline]
+    fn on_merge(&mut self, _ptr: usize, _size: usize, _layer: usize) {}
+}
+
+/// 什么都不做的观察者。分配器默认使用它，不产生任何开销。
+#[derive(Clone, Copy, Default, Debug)]
+pub struct NoopObserver;
+
+impl AllocObserver for NoopObserver {}
+
 /// 伙伴分配器分配失败。
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(transparent)]
 pub struct BuddyError;
 
+/// 转移内存块失败。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TransferError {
+    /// 地址或长度没有对齐到最小阶数。
+    Misaligned,
+    /// 范围超出了地址空间。
+    Overflow,
+    /// 与已托管的内存重叠。
+    Overlap,
+    /// 记录地址范围的表已满。
+    TableFull,
+    /// 第 `layer` 层的行不能容纳范围中的块。`layer` 等于层数时表示寡头行。
+    Unaddressable {
+        /// 不能容纳的行所在的层。
+        layer: usize,
+    },
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Misaligned => f.write_str("range must align to minium order"),
+            Self::Overflow => f.write_str("range exceeds the address space"),
+            Self::Overlap => f.write_str("range overlaps managed memory"),
+            Self::TableFull => f.write_str("region table is full"),
+            Self::Unaddressable { layer } => write!(f, "line {layer} cannot address the range"),
+        }
+    }
+}
+
 /// 伙伴分配器。
-pub struct BuddyAllocator<const N: usize, O: OligarchyCollection, B: BuddyCollection> {
+///
+/// 默认 `N` 个伙伴行保存在分配器内部的数组中。
+/// 也可以由 `S` 指定其他存储，参见 [`SliceBuddyAllocator`]。
+/// 层数总是由存储中的行数决定，存储不是 `[B; N]` 时 `N` 不起作用。
+///
+/// `W` 观察分配器的操作，参见 [`AllocObserver`]。
+pub struct BuddyAllocator<
+    const N: usize,
+    O: OligarchyCollection,
+    B: BuddyCollection,
+    S: LineStorage<B> = [B; N],
+    W: AllocObserver = NoopObserver,
+> {
     /// 寡头集合，管理最大阶数的内存块。
     oligarchy: O,
 
     /// `N` 阶 `B` 型伙伴集合。
     /// `buddies[i]` 管理阶数为 `min_order + i` 的内存块。
-    buddies: [B; N],
+    buddies: S,
 
     /// 最小阶数。
     ///
@@ -81,38 +458,124 @@ pub struct BuddyAllocator<const N: usize, O: OligarchyCollection, B: BuddyCollec
 
     /// 总容量（字节）。
     capacity: usize,
+
+    /// 取块策略。
+    policy: TakePolicy,
+
+    /// 观察者。
+    observer: W,
+
+    _lines: PhantomData<B>,
 }
 
-impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<N, O, B> {
-    /// 最大层数。
-    const MAX_LAYER: usize = N;
+/// 行数在运行时确定的伙伴分配器。
+///
+/// 伙伴行保存在调用者提供的存储中，使用 [`BuddyAllocator::init_with_lines`] 初始化。
+/// 分配和回收的行为与 [`BuddyAllocator`] 相同。
+/// `N` 固定为 0，不表示层数。
+pub type SliceBuddyAllocator<'a, O, B, W = NoopObserver> =
+    BuddyAllocator<0, O, B, Option<&'a mut [B]>, W>;
+
+/// 低 `K` 层使用 `L`、其上 `M` 层使用 `H` 的伙伴分配器，参见 [`SplitLines`]。
+///
+/// `N` 固定为 0，层数是 `K + M`。
+pub type SplitBuddyAllocator<O, L, H, const K: usize, const M: usize, W = NoopObserver> =
+    BuddyAllocator<0, O, L, SplitLines<L, H, K, M>, W>;
+
+impl<const N: usize, O: OligarchyCollection, B: BuddyCollection, S: LineStorage<B>>
+    BuddyAllocator<N, O, B, S, NoopObserver>
+{
+    /// 构造分配器。
+    #[inline]
+    pub const fn new() -> Self {
+        Self::with_observer(NoopObserver)
+    }
+}
+
+impl<
+    const N: usize,
+    O: OligarchyCollection,
+    B: BuddyCollection,
+    S: LineStorage<B>,
+    W: AllocObserver,
+> BuddyAllocator<N, O, B, S, W>
+{
     /// 寡头支持的最小阶数。
     const O_MIN_ORDER: usize = O::INTRUSIVE_META_SIZE.next_power_of_two().trailing_zeros() as _;
     /// 伙伴支持的最小阶数。
     const B_MIN_ORDER: usize = B::INTRUSIVE_META_SIZE.next_power_of_two().trailing_zeros() as _;
 
-    /// 构造分配器。
+    /// 构造带有观察者的分配器。
     #[inline]
-    pub const fn new() -> Self {
+    pub const fn with_observer(observer: W) -> Self {
         Self {
             oligarchy: O::EMPTY,
-            buddies: [B::EMPTY; N],
+            buddies: S::EMPTY,
             min_order: 0,
             free: 0,
             capacity: 0,
+            policy: TakePolicy::Lowest,
+            observer,
+            _lines: PhantomData,
         }
     }
 }
 
-impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> Default
-    for BuddyAllocator<N, O, B>
+impl<
+    const N: usize,
+    O: OligarchyCollection,
+    B: BuddyCollection,
+    S: LineStorage<B>,
+    W: AllocObserver + Default,
+> Default for BuddyAllocator<N, O, B, S, W>
 {
     fn default() -> Self {
-        Self::new()
+        Self::with_observer(W::default())
     }
 }
 
-impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<N, O, B> {
+impl<'a, O: OligarchyCollection, B: BuddyCollection, W: AllocObserver>
+    SliceBuddyAllocator<'a, O, B, W>
+{
+    /// 使用调用者提供的 `lines` 作为伙伴行运行时初始化。
+    ///
+    /// 行数即 `lines` 的长度。`lines` 原有的内容被清空。
+    /// 其他参数与 [`BuddyAllocator::init`] 相同。
+    pub fn init_with_lines<T>(&mut self, min_order: usize, base: NonNull<T>, lines: &'a mut [B]) {
+        assert_eq!(
+            0, self.capacity,
+            "init is not allowed after any transfering"
+        );
+
+        lines.iter_mut().for_each(|b| {
+            *b = B::EMPTY;
+            b.set_policy(self.policy);
+        });
+        self.buddies = Some(lines);
+        self.init(min_order, base);
+    }
+}
+
+impl<
+    const N: usize,
+    O: OligarchyCollection,
+    B: BuddyCollection,
+    S: LineStorage<B>,
+    W: AllocObserver,
+> BuddyAllocator<N, O, B, S, W>
+{
+    /// 返回观察者。
+    #[inline]
+    pub fn observer(&self) -> &W {
+        &self.observer
+    }
+
+    /// 返回观察者。
+    #[inline]
+    pub fn observer_mut(&mut self) -> &mut W {
+        &mut self.observer
+    }
+
     /// 返回分配器管理的总容量。
     #[inline]
     pub fn capacity(&self) -> usize {
@@ -125,10 +588,25 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
         self.free
     }
 
+    /// 伙伴行的层数。
+    #[inline]
+    fn layers(&self) -> usize {
+        self.buddies.layers()
+    }
+
     /// 最大阶数。寡头块的阶数。
     #[inline]
-    const fn max_order(&self) -> usize {
-        self.min_order + Self::MAX_LAYER
+    fn max_order(&self) -> usize {
+        self.min_order + self.layers()
+    }
+
+    /// 长度为 `size` 的分配应当取块的层。
+    #[inline]
+    fn size_layer(&self, size: NonZeroUsize) -> usize {
+        let page_mask = (1usize << self.min_order) - 1;
+        let size = (size.get() + page_mask) & !page_mask;
+        let order = size.next_power_of_two().trailing_zeros() as usize;
+        (order - self.min_order).min(self.layers())
     }
 
     /// 运行时初始化。
@@ -148,11 +626,30 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
         assert!(Self::B_MIN_ORDER <= min_order);
 
         let base = base.as_ptr() as usize;
-        self.buddies.iter_mut().enumerate().for_each(|(i, c)| {
-            let o = self.min_order + i;
-            c.init(o, base >> o)
-        });
+        self.buddies.init(min_order, base);
         self.oligarchy.init(max_order, base >> max_order);
+        self.observer.on_init(min_order, base, self.layers());
+    }
+
+    /// 设置取块策略。
+    ///
+    /// 策略会传递给所有行。分配时拆分出的块也按策略保留高半或低半。
+    #[inline]
+    pub fn set_policy(&mut self, policy: TakePolicy) {
+        assert_eq!(
+            0, self.capacity,
+            "set_policy is not allowed after any transfering"
+        );
+
+        self.policy = policy;
+        self.buddies.set_policy(policy);
+        self.oligarchy.set_policy(policy);
+    }
+
+    /// 返回取块策略。
+    #[inline]
+    pub fn policy(&self) -> TakePolicy {
+        self.policy
     }
 
     /// 将一个 `ptr` 指向的长度为 `usize` 的内存块转移给分配器。
@@ -163,24 +660,347 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
     ///
     /// - 这个内存块没有被其他任何对象引用；
     /// - 这个内存块和已经托管的内存块不重叠。
+    ///
+    /// 以 [`RegionTable`] 作为观察者时，与已经托管的内存块重叠的转移会 panic，
+    /// 也可以使用检查后返回错误的 [`try_transfer`](Self::try_transfer)。
     #[inline]
     pub unsafe fn transfer<T>(&mut self, ptr: NonNull<T>, size: usize) {
+        let ptr = ptr.as_ptr() as usize;
+        self.observer.on_transfer(ptr, size);
         self.capacity += size;
-        self.deallocate(ptr, size)
+        self.put_range(ptr, size);
+    }
+
+    /// 将 `[ptr, ptr + size)` 中除去 `excluded` 的部分转移给分配器，返回因对齐丢弃的字节数。
+    ///
+    /// `excluded` 中的每一项是 `(地址, 长度)`，例如内核镜像、初始内存盘或者固件保留的空洞。
+    /// 这些范围按地址排序（`excluded` 会被原地重排）并裁剪到转移的范围内，可以互相重叠。
+    /// 剩余的每一段向内对齐到最小阶数后转移，对齐丢弃的字节不归分配器管理。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证：
+    ///
+    /// - 除去 `excluded` 的部分没有被其他任何对象引用；
+    /// - 这个内存块和已经托管的内存块不重叠。
+    pub unsafe fn transfer_excluding<T>(
+        &mut self,
+        ptr: NonNull<T>,
+        size: usize,
+        excluded: &mut [(usize, usize)],
+    ) -> usize {
+        let mask = (1usize << self.min_order) - 1;
+        let end = ptr.as_ptr() as usize + size;
+        excluded.sort_unstable_by_key(|&(addr, _)| addr);
+
+        let mut trimmed = 0;
+        let mut cursor = ptr.as_ptr() as usize;
+        // 最后一项哨兵负责转移最后一个排除范围之后的部分
+        let holes = excluded.iter().filter(|&&(_, len)| len != 0);
+        for &(addr, len) in holes.chain([&(end, usize::MAX)]) {
+            let piece = addr.clamp(cursor, end);
+            // 向内对齐。`cursor` 非零，对齐后的起点不会是 0
+            let start = (cursor + mask) & !mask;
+            let stop = piece & !mask;
+            if start < stop {
+                unsafe { self.transfer(NonNull::new_unchecked(start as *mut u8), stop - start) };
+                trimmed += piece - cursor - (stop - start);
+            } else {
+                trimmed += piece - cursor;
+            }
+            cursor = cursor.max(addr.saturating_add(len)).min(end);
+        }
+        trimmed
+    }
+
+    /// 检查 `[ptr, ptr + size)` 对齐到最小阶数，并且分解出的每个块都能放入对应的行。
+    fn check_transfer(&self, ptr: usize, size: usize) -> Result<(), TransferError> {
+        let mask = (1usize << self.min_order) - 1;
+        if ptr & mask != 0 || size & mask != 0 {
+            return Err(TransferError::Misaligned);
+        }
+        let end = ptr.checked_add(size).ok_or(TransferError::Overflow)?;
+
+        // 与回收相同地分解。合并出的块只会落在已经检查过的块的上层，不必检查
+        let max_order = self.max_order();
+        let max_layer = self.layers();
+        let mut ptr = ptr;
+        while ptr < end {
+            let len = nonzero(end - ptr);
+            let order_ptr = ptr.trailing_zeros();
+            let order_len = usize::BITS - len.leading_zeros() - 1;
+            let order = order_ptr.min(order_len) as usize;
+            if order >= max_order {
+                let idx = ptr >> max_order;
+                let count = len.get() >> max_order;
+                if !self.oligarchy.addressable(idx) || !self.oligarchy.addressable(idx + count - 1)
+                {
+                    return Err(TransferError::Unaddressable { layer: max_layer });
+                }
+                ptr += count << max_order;
+            } else {
+                let layer = order - self.min_order;
+                if !self.buddies.addressable(layer, ptr >> order) {
+                    return Err(TransferError::Unaddressable { layer });
+                }
+                ptr += 1 << order;
+            }
+        }
+        Ok(())
     }
 
     /// 从分配器夺走一个对齐到 `align_order` 阶，长度为 `size` 的内存块。
+    ///
+    /// # Notice
+    ///
+    /// 以 [`RegionTable`] 作为观察者时，从表中某个范围的中间夺走块需要多占一项。
+    /// 表已满时 panic，此时块已经夺走，分配器不能继续使用。
     #[inline]
     pub fn snatch<T>(
         &mut self,
         align_order: usize,
         size: NonZeroUsize,
     ) -> Result<(NonNull<T>, usize), BuddyError> {
-        let ans = self.allocate(align_order, size);
+        let (ans, layer) = self.take_range(align_order, size);
         if let Ok((_, size)) = ans {
             self.capacity -= size;
         }
-        ans
+        self.observer
+            .on_snatch(align_order, size.get(), layer, ans.ok());
+        ans.map(|(ptr, size)| (unsafe { NonNull::new_unchecked(ptr as *mut T) }, size))
+    }
+
+    /// 把完全空闲的寡头还给 `backing`，返回归还的字节数。参见 [`trim`](Self::trim)。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证分配器中空闲的寡头都由 `backing` 提供。
+    pub unsafe fn trim_backed(&mut self, keep_bytes: usize, backing: &mut impl Backing) -> usize {
+        self.trim(keep_bytes, |ptr, size| unsafe {
+            backing.release(ptr, size)
+        })
+    }
+
+    /// 取出完全空闲的寡头还给系统，直到再取一个寡头空闲内存就会少于 `keep_bytes` 字节，返回取出的字节数。
+    ///
+    /// 寡头行以 [`TakePolicy::Highest`] 提取寡头，从高地址开始取，
+    /// 相邻的寡头合并成尽量长的段，以 `(地址, 长度)` 传给 `release`。
+    /// 取出的内存不再归分配器管理，容量相应减少，与 [`snatch`](Self::snatch) 相同；
+    /// 观察者收到的通知与用 [`reclaim_range`](Self::reclaim_range) 取回这一段时相同。
+    ///
+    /// 拆分了的寡头即使大部分空闲也不会取出。
+    ///
+    /// # Notice
+    ///
+    /// 取出的顺序取决于寡头行如何实现 [`TakePolicy::Highest`]。
+    /// 例如以 [`TakePolicy::Lifo`] 使用的 [`LinkedListBuddy`] 链表无序，只能从表尾取，
+    /// 可能取出低地址的寡头而留下高地址的寡头。
+    ///
+    /// 以 [`RegionTable`] 作为观察者时，每从表中某个范围的中间取出一段需要多占一项。
+    /// 表已满时 panic，此时这一段已经取出，分配器不能继续使用。
+    pub fn trim(
+        &mut self,
+        keep_bytes: usize,
+        mut release: impl FnMut(NonNull<u8>, usize),
+    ) -> usize {
+        let max_order = self.max_order();
+        let mut flush = |this: &mut Self, (idx, count): (usize, usize)| {
+            let (ptr, size) = (idx << max_order, count << max_order);
+            this.observer.on_extract(ptr, size);
+            this.observer.on_reclaim(ptr, size, size);
+            release(unsafe { NonNull::new_unchecked(ptr as *mut u8) }, size);
+        };
+
+        let mut released = 0;
+        // 当前连续段的首序号和寡头数量
+        let mut run: Option<(usize, usize)> = None;
+        while self.free.saturating_sub(keep_bytes) >> max_order > 0 {
+            // 从高地址开始取，保留低地址；调用 `release` 之前恢复策略
+            self.oligarchy.set_policy(TakePolicy::Highest);
+            let idx = self.oligarchy.take_any(0, 1);
+            self.oligarchy.set_policy(self.policy);
+            let Some(idx) = idx else {
+                break;
+            };
+            self.free -= 1 << max_order;
+            self.capacity -= 1 << max_order;
+            released += 1 << max_order;
+            run = match run {
+                Some((start, count)) if idx + 1 == start => Some((idx, count + 1)),
+                Some((start, count)) if start + count == idx => Some((start, count + 1)),
+                Some(prev) => {
+                    flush(self, prev);
+                    Some((idx, 1))
+                }
+                None => Some((idx, 1)),
+            };
+        }
+        if let Some(run) = run {
+            flush(self, run);
+        }
+        released
+    }
+
+    /// 从分配器取回 `[ptr, ptr + size)` 中所有空闲的内存，返回取回的字节数。
+    ///
+    /// 跨越范围边界的空闲块被拆分，范围外的部分留在分配器中。
+    /// 取回的内存不再归分配器管理，容量相应减少，与 [`snatch`](Self::snatch) 相同。
+    ///
+    /// 范围中不空闲的部分（已分配、已夺走或者从未转移给分配器）按地址顺序合并成尽量长的段，
+    /// 以 `(地址, 长度)` 传给 `busy`。调用者可以迁移这些段上的数据，回收后再次取回。
+    ///
+    /// # Notice
+    ///
+    /// `ptr` 和 `size` 都需要对齐到分配器的最小阶数。所有的行都需要实现 [`BuddyLine::take`]。
+    ///
+    /// 以 [`RegionTable`] 作为观察者时，取回的范围在表中某个范围的中间需要多占一项。
+    /// 表已满时 panic，此时空闲块已经取出，分配器不能继续使用。[`split_off`](Self::split_off) 也是如此。
+    pub fn reclaim_range<T>(
+        &mut self,
+        ptr: NonNull<T>,
+        size: usize,
+        busy: impl FnMut(NonNull<u8>, usize),
+    ) -> usize {
+        let start = ptr.as_ptr() as usize;
+        let reclaimed = self.extract_range(start, size, busy, |_, _| {});
+        self.observer.on_reclaim(start, size, reclaimed);
+        reclaimed
+    }
+
+    /// 从分配器取出 `[start, start + size)` 中所有空闲的块，逐个传给 `taken`，返回取出的字节数。
+    ///
+    /// 不空闲的段传给 `busy`。不通知观察者。
+    fn extract_range(
+        &mut self,
+        start: usize,
+        size: usize,
+        busy: impl FnMut(NonNull<u8>, usize),
+        mut taken: impl FnMut(usize, usize),
+    ) -> usize {
+        let mask = (1usize << self.min_order) - 1;
+        assert!(
+            start & mask == 0 && size & mask == 0,
+            "range must align to minium order"
+        );
+
+        let max_order = self.max_order();
+        let mut busy = Busy { run: None, f: busy };
+        let mut extracted = 0;
+        let mut ptr = start;
+        let end = start + size;
+        while ptr < end {
+            // 与回收相同，按对齐把范围分解成块，但不超过寡头
+            let len = nonzero(end - ptr);
+            let order_ptr = nonzero(ptr).trailing_zeros() as usize;
+            let order_len = (usize::BITS - len.leading_zeros() - 1) as usize;
+            let order = order_ptr.min(order_len).min(max_order);
+            extracted += self.reclaim_block(ptr, order, &mut busy, &mut taken);
+            ptr += 1 << order;
+        }
+        busy.flush();
+
+        self.free -= extracted;
+        self.capacity -= extracted;
+        extracted
+    }
+
+    /// 取回 `order` 阶的块 `ptr` 中空闲的部分。
+    ///
+    /// 先自底向上找包含这个块的空闲块，找到时把空闲块中这个块以外的部分存回；
+    /// 找不到时这个块不完全空闲，拆成两半分别取回。
+    fn reclaim_block<F: FnMut(NonNull<u8>, usize)>(
+        &mut self,
+        ptr: usize,
+        order: usize,
+        busy: &mut Busy<F>,
+        taken: &mut impl FnMut(usize, usize),
+    ) -> usize {
+        let max_order = self.max_order();
+        for o in order..=max_order {
+            let found = if o == max_order {
+                self.oligarchy.take(ptr >> o)
+            } else {
+                self.buddies.take(o - self.min_order, ptr >> o)
+            };
+            if found {
+                // 逐层拆分，存回拆分出的范围外的伙伴
+                for o in (order..o).rev() {
+                    let parent = ptr & !((1 << (o + 1)) - 1);
+                    let layer = o - self.min_order;
+                    self.observer.on_split(parent, 1 << (o + 1), layer + 1);
+                    let buddy = (ptr >> o) ^ 1;
+                    assert!(self.buddies.put(layer, buddy).is_none());
+                }
+                self.observer.on_extract(ptr, 1 << order);
+                taken(ptr, 1 << order);
+                return 1 << order;
+            }
+        }
+        self.reclaim_split(ptr, order, busy, taken)
+    }
+
+    /// 取回已知不包含在更大的空闲块中的 `order` 阶的块 `ptr` 中空闲的部分。
+    fn reclaim_split<F: FnMut(NonNull<u8>, usize)>(
+        &mut self,
+        ptr: usize,
+        order: usize,
+        busy: &mut Busy<F>,
+        taken: &mut impl FnMut(usize, usize),
+    ) -> usize {
+        if order == self.min_order {
+            busy.push(ptr, 1 << order);
+            return 0;
+        }
+        let order = order - 1;
+        let line = order - self.min_order;
+        [ptr, ptr + (1 << order)]
+            .into_iter()
+            .map(|ptr| {
+                if self.buddies.take(line, ptr >> order) {
+                    self.observer.on_extract(ptr, 1 << order);
+                    taken(ptr, 1 << order);
+                    1 << order
+                } else {
+                    self.reclaim_split(ptr, order, busy, taken)
+                }
+            })
+            .sum()
+    }
+
+    /// 把 `other` 管理的内存全部并入这个分配器。
+    ///
+    /// `other` 的空闲块逐个存入这个分配器，与边界另一侧的空闲伙伴合并；
+    /// `other` 的总容量计入这个分配器，`other` 中已分配的块之后回收到这个分配器。
+    /// 观察者把每个并入的空闲块看作一次转移。
+    ///
+    /// # Notice
+    ///
+    /// 两个分配器的最小阶数需要相同，管理的内存不能重叠。
+    pub fn absorb(&mut self, mut other: Self) {
+        assert_eq!(
+            self.min_order, other.min_order,
+            "cannot absorb an allocator with different minium order"
+        );
+
+        // 与转移相同，先通知观察者再存入行
+        for layer in 0..other.layers() {
+            let order = other.min_order + layer;
+            while let Some(idx) = other.buddies.take_any(layer, 0) {
+                self.observer.on_transfer(idx << order, 1 << order);
+                self.capacity += 1 << order;
+                other.capacity -= 1 << order;
+                self.put_range(idx << order, 1 << order);
+            }
+        }
+        let order = other.max_order();
+        while let Some(idx) = other.oligarchy.take_any(0, 1) {
+            self.observer.on_transfer(idx << order, 1 << order);
+            self.capacity += 1 << order;
+            other.capacity -= 1 << order;
+            self.put_range(idx << order, 1 << order);
+        }
+        // 已分配的块之后回收到这个分配器
+        self.capacity += other.capacity;
     }
 
     /// 分配可容纳 `T` 对象的内存块。
@@ -215,11 +1035,60 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
         align_order: usize,
         size: NonZeroUsize,
     ) -> Result<(NonNull<T>, usize), BuddyError> {
-        let max_order = self.max_order();
-        #[inline]
-        const fn allocated<T, U>(ptr: *mut T, size: usize) -> (NonNull<U>, usize) {
-            (unsafe { NonNull::new_unchecked(ptr) }.cast(), size)
+        let (ans, layer) = self.take_range(align_order, size);
+        self.observer
+            .on_allocate(align_order, size.get(), layer, ans.ok());
+        ans.map(|(ptr, size)| (unsafe { NonNull::new_unchecked(ptr as *mut T) }, size))
+    }
+
+    /// 分配。空闲内存不足时向 `backing` 申请一块足够的内存，转移给分配器后再分配一次。
+    ///
+    /// 申请的阶数不小于最小阶数、`align_order` 和容纳 `size` 所需的阶数。
+    /// 申请到的内存需要能放入分配器的行。
+    pub fn allocate_backed<T>(
+        &mut self,
+        align_order: usize,
+        size: NonZeroUsize,
+        backing: &mut impl Backing,
+    ) -> Result<(NonNull<T>, usize), BuddyError> {
+        let (mut ans, mut layer) = self.take_range(align_order, size);
+        if ans.is_err() {
+            let page_mask = (1usize << self.min_order) - 1;
+            let size_order = nonzero(((size.get() + page_mask) & !page_mask).next_power_of_two())
+                .trailing_zeros() as usize;
+            if let Some((ptr, len)) = backing.grow(align_order.max(size_order)) {
+                // 后备内存保证不与已经托管的内存重叠
+                unsafe { self.transfer(ptr, len) };
+                (ans, layer) = self.take_range(align_order, size);
+            }
+        }
+        self.observer
+            .on_allocate(align_order, size.get(), layer, ans.ok());
+        ans.map(|(ptr, size)| (unsafe { NonNull::new_unchecked(ptr as *mut T) }, size))
+    }
+
+    /// 分配，返回 `(地址, 长度)` 和取块的层。不通知观察者。
+    ///
+    /// 分配失败时返回按长度应当取块的层。
+    fn take_range(
+        &mut self,
+        align_order: usize,
+        size: NonZeroUsize,
+    ) -> (Result<(usize, usize), BuddyError>, usize) {
+        match self.take_block(align_order, size) {
+            Ok((ptr, size, layer)) => (Ok((ptr, size)), layer),
+            Err(e) => (Err(e), self.size_layer(size)),
         }
+    }
+
+    /// 分配，返回 `(地址, 长度, 取块的层)`。
+    fn take_block(
+        &mut self,
+        align_order: usize,
+        size: NonZeroUsize,
+    ) -> Result<(usize, usize, usize), BuddyError> {
+        let max_order = self.max_order();
+        let max_layer = self.layers();
 
         // 要分配的容量
         let page_mask = (1usize << self.min_order) - 1;
@@ -227,12 +1096,12 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
         // 分配的阶数
         let size_order = nonzero(ans_size.next_power_of_two()).trailing_zeros() as usize;
         // 分配
-        let (ptr, alloc_size) = if size_order >= max_order {
+        let (ptr, alloc_size, layer) = if size_order >= max_order {
             // 连续分配寡头
-            let count = ((ans_size >> (max_order - 1)) + 1) >> 1;
+            let count = (ans_size + (1 << max_order) - 1) >> max_order;
             let align_offset = align_order.saturating_sub(max_order);
             match self.oligarchy.take_any(align_offset, count) {
-                Some(idx) => (idx << max_order, count << max_order),
+                Some(idx) => (idx << max_order, count << max_order, max_layer),
                 None => Err(BuddyError)?,
             }
         } else {
@@ -241,7 +1110,7 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
             let mut layer = layer0;
             let mut idx = loop {
                 // 从寡头借
-                if layer == Self::MAX_LAYER {
+                if layer == max_layer {
                     let align_offset = align_order.saturating_sub(max_order);
                     match self.oligarchy.take_any(align_offset, 1) {
                         Some(idx) => break idx,
@@ -250,28 +1119,42 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
                 }
                 // 从伙伴借
                 let align_offset = align_order.saturating_sub(self.min_order + layer);
-                match self.buddies[layer].take_any(align_offset) {
+                match self.buddies.take_any(layer, align_offset) {
                     Some(idx) => break idx,
                     None => layer += 1,
                 }
             };
-            // 存回多借用的
-            assert!(self.buddies[layer0..layer].iter_mut().rev().all(|b| {
-                idx <<= 1;
-                b.put(idx + 1).is_none()
+            // 存回多借用的，自顶向下分配时保留高半，但高半不满足对齐时仍保留低半
+            let highest = self.policy == TakePolicy::Highest;
+            let min_order = self.min_order;
+            let observer = &mut self.observer;
+            let buddies = &mut self.buddies;
+            assert!((layer0..layer).rev().all(|l| {
+                let order = min_order + l;
+                observer.on_split(idx << (order + 1), 1 << (order + 1), l + 1);
+                let keep = (highest && order >= align_order) as usize;
+                idx = (idx << 1) | keep;
+                buddies.put(l, idx ^ 1).is_none()
             }));
             // 完成
-            (idx << size_order, 1 << size_order)
+            (idx << size_order, 1 << size_order, layer)
         };
         self.free -= alloc_size;
+        // 自顶向下分配时使用块的末端，但仍要满足对齐
+        let end = ptr + alloc_size;
+        let ans = if self.policy == TakePolicy::Highest {
+            (end - ans_size) & !((1usize << align_order) - 1)
+        } else {
+            ptr
+        };
         // 存回为了对齐而多分配的
-        if alloc_size > ans_size {
-            self.deallocate(
-                unsafe { NonNull::new_unchecked((ptr + ans_size) as *mut u8) },
-                alloc_size - ans_size,
-            );
+        if ans > ptr {
+            self.put_range(ptr, ans - ptr);
         }
-        Ok(allocated(ptr as *mut (), ans_size))
+        if end > ans + ans_size {
+            self.put_range(ans + ans_size, end - ans - ans_size);
+        }
+        Ok((ans, ans_size, layer))
     }
 
     /// 根据布局回收。
@@ -293,14 +1176,21 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
     ///
     /// 调用者需要保证 `size` 对齐了分配器的最小阶数。
     pub fn deallocate<T>(&mut self, ptr: NonNull<T>, size: usize) {
+        let ptr = ptr.as_ptr() as usize;
+        self.put_range(ptr, size);
+        self.observer.on_deallocate(ptr, size);
+    }
+
+    /// 回收 `[ptr, ptr + size)`。不通知观察者。
+    fn put_range(&mut self, mut ptr: usize, size: usize) {
         debug_assert!(
             size.trailing_zeros() as usize >= self.min_order,
             "size must align to minium order"
         );
 
         let max_order = self.max_order();
+        let max_layer = self.layers();
 
-        let mut ptr = ptr.as_ptr() as usize;
         let end = ptr + size;
         while ptr < end {
             // 剩余长度
@@ -320,7 +1210,7 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
                 // 移动指针
                 ptr += count << max_order;
                 // 释放
-                (idx..).take(count).for_each(|idx| self.oligarchy.put(idx));
+                self.oligarchy.put_range(idx, count);
             } else {
                 // 伙伴序号
                 let mut idx = ptr >> order;
@@ -329,13 +1219,17 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
                 // 释放
                 for layer in (order - self.min_order).. {
                     // 释放寡头
-                    if layer == Self::MAX_LAYER {
+                    if layer == max_layer {
                         self.oligarchy.put(idx);
                         break;
                     }
                     // 释放伙伴
-                    match self.buddies[layer].put(idx) {
-                        Some(parent) => idx = parent,
+                    match self.buddies.put(layer, idx) {
+                        Some(parent) => {
+                            idx = parent;
+                            let order = self.min_order + layer + 1;
+                            self.observer.on_merge(idx << order, 1 << order, layer + 1);
+                        }
                         None => break,
                     }
                 }
@@ -351,19 +1245,87 @@ impl<const N: usize, O: OligarchyCollection, B: BuddyCollection> BuddyAllocator<
     }
 }
 
-impl<const N: usize, O: OligarchyCollection + fmt::Debug, B: BuddyCollection + fmt::Debug>
-    fmt::Debug for BuddyAllocator<N, O, B>
+impl<const N: usize, O: OligarchyCollection, B: BuddyCollection, W: AllocObserver + Default>
+    BuddyAllocator<N, O, B, [B; N], W>
+{
+    /// 把 `[ptr, ptr + size)` 中所有空闲的内存拆分到一个新的分配器。
+    ///
+    /// 新的分配器以 `ptr` 为基址，最小阶数和取块策略与这个分配器相同，观察者取默认值。
+    /// 移出的内存从这个分配器的容量中扣除，计入新分配器的容量。
+    /// 范围中已分配的块仍然属于这个分配器，应当回收到这个分配器。
+    ///
+    /// 这个分配器的观察者看到一次 [`reclaim_range`](Self::reclaim_range)，
+    /// 新分配器的观察者把每个移入的块看作一次转移。
+    /// 使用 [`absorb`](Self::absorb) 把新的分配器并回来。
+    ///
+    /// # Notice
+    ///
+    /// `ptr` 和 `size` 都需要对齐到分配器的最小阶数。所有的行都需要实现 [`BuddyLine::take`]。
+    pub fn split_off<T>(&mut self, ptr: NonNull<T>, size: usize) -> Self {
+        let mut other = Self::default();
+        other.set_policy(self.policy);
+        other.init(self.min_order, ptr);
+
+        let start = ptr.as_ptr() as usize;
+        let moved = self.extract_range(
+            start,
+            size,
+            |_, _| {},
+            |ptr, size| {
+                other.observer.on_transfer(ptr, size);
+                other.capacity += size;
+                other.put_range(ptr, size);
+            },
+        );
+        self.observer.on_reclaim(start, size, moved);
+        other
+    }
+}
+
+impl<
+    const N: usize,
+    O: OligarchyCollection + fmt::Debug,
+    B: BuddyCollection,
+    S: LineStorage<B> + DebugLines,
+    W: AllocObserver,
+> fmt::Debug for BuddyAllocator<N, O, B, S, W>
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "BuddyAllocator@{:#018x}", self as *const _ as usize)?;
         writeln!(f, "---------------------------------")?;
-        for (i, line) in self.buddies.iter().enumerate() {
-            writeln!(f, "{:>2}> {line:?}", self.min_order + i)?;
+        for layer in 0..self.layers() {
+            write!(f, "{:>2}> ", self.min_order + layer)?;
+            self.buddies.fmt_line(layer, f)?;
+            writeln!(f)?;
         }
         writeln!(f, "{:>2}> {:?}", self.max_order(), self.oligarchy)
     }
 }
 
+/// 合并相邻的不空闲的段，再交给回调。
+struct Busy<F> {
+    run: Option<(usize, usize)>,
+    f: F,
+}
+
+impl<F: FnMut(NonNull<u8>, usize)> Busy<F> {
+    fn push(&mut self, ptr: usize, size: usize) {
+        match &mut self.run {
+            Some((start, len)) if *start + *len == ptr => *len += size,
+            _ => {
+                self.flush();
+                self.run = Some((ptr, size));
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some((ptr, size)) = self.run.take() {
+            (self.f)(unsafe { NonNull::new_unchecked(ptr as *mut u8) }, size)
+        }
+    }
+}
+
 #[inline]
 const fn nonzero(val: usize) -> NonZeroUsize {
     unsafe { NonZeroUsize::new_unchecked(val) }
@@ -406,6 +1368,22 @@ mod tests {
 
     static mut TEST_MEMORY: [TestPage; 16] = [TestPage([0; 4096]); 16];
 
+    /// 假想的基址。
+    pub(crate) const FAKE_BASE: usize = 0x10_0000;
+
+    /// 构造以 `base` 为基址、最小阶数为 `min_order`、带有观察者 `observer` 的位图分配器。
+    ///
+    /// 位图行不写被管理的内存，可以使用假想的地址。
+    pub(crate) fn fake_allocator<const N: usize, W: AllocObserver>(
+        base: usize,
+        min_order: usize,
+        observer: W,
+    ) -> BuddyAllocator<N, UsizeBuddy, UsizeBuddy, [UsizeBuddy; N], W> {
+        let mut allocator = BuddyAllocator::with_observer(observer);
+        allocator.init(min_order, NonNull::new(base as *mut u8).unwrap());
+        allocator
+    }
+
     #[test]
     fn test_allocator_new() {
         let allocator: TestAllocator<4> = BuddyAllocator::new();
@@ -635,6 +1613,533 @@ mod tests {
         assert_eq!(alloc_size, 0);
     }
 
+    #[test]
+    fn test_allocator_highest_policy() {
+        // 整块对齐，只有一个最大的块
+        #[repr(C, align(65536))]
+        struct Block([u8; 65536]);
+        static mut BLOCK: Block = Block([0; 65536]);
+
+        let mut allocator: TestAllocator<8> = BuddyAllocator::new();
+
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(BLOCK).cast::<u8>()).unwrap();
+        let len = 65536;
+        let end = ptr.as_ptr() as usize + len;
+
+        allocator.init(12, ptr);
+        allocator.set_policy(TakePolicy::Highest);
+        assert_eq!(allocator.policy(), TakePolicy::Highest);
+        unsafe {
+            allocator.transfer(ptr, len);
+        }
+
+        // 自顶向下分配，第一个页来自最高地址
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p0, s0) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p0.as_ptr() as usize, end - 4096);
+        let (p1, s1) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p1.as_ptr() as usize, end - 2 * 4096);
+
+        // 非 2 的幂的大小也放在末端
+        let size = NonZeroUsize::new(3 * 4096).unwrap();
+        let (p2, s2) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p2.as_ptr() as usize + s2, end - 4 * 4096);
+
+        allocator.deallocate(p0, s0);
+        allocator.deallocate(p1, s1);
+        allocator.deallocate(p2, s2);
+        assert_eq!(allocator.free(), len);
+    }
+
+    #[test]
+    fn test_allocator_highest_policy_aligned() {
+        #[repr(C, align(65536))]
+        struct Block([u8; 65536]);
+        static mut BLOCK: Block = Block([0; 65536]);
+
+        let mut allocator: TestAllocator<4> = BuddyAllocator::new();
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(BLOCK).cast::<u8>()).unwrap();
+        let len = 65536;
+        allocator.init(12, ptr);
+        allocator.set_policy(TakePolicy::Highest);
+        unsafe { allocator.transfer(ptr, len) };
+
+        // 对齐超过分配的阶数时，拆分必须保留满足对齐的低半
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p0, s0) = allocator.allocate::<u8>(15, size).unwrap();
+        assert_eq!((p0, s0), (unsafe { ptr.add(32768) }, 4096));
+        assert_eq!(allocator.free(), len - 4096);
+        // 拆分剩下的页紧挨着分配的页
+        let (p1, s1) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p1, unsafe { ptr.add(32768 + 4096) });
+
+        allocator.deallocate(p0, s0);
+        allocator.deallocate(p1, s1);
+        assert_eq!(allocator.free(), len);
+    }
+
+    #[test]
+    fn test_allocator_oligarchy_count() {
+        let mut allocator = BuddyAllocator::<2, UsizeBuddy, UsizeBuddy>::new();
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(TEST_MEMORY).cast::<u8>()).unwrap();
+        let len = core::mem::size_of_val(unsafe { &*core::ptr::addr_of!(TEST_MEMORY) });
+        allocator.init(12, ptr);
+        unsafe { allocator.transfer(ptr, len) };
+
+        // 寡头是 4 页，5 页需要 2 个寡头，多出的 3 页存回
+        let size = NonZeroUsize::new(5 * 4096).unwrap();
+        let (p, s) = allocator.allocate::<u8>(14, size).unwrap();
+        assert_eq!(s, 5 * 4096);
+        assert_eq!(allocator.free(), len - s);
+        allocator.deallocate(p, s);
+        assert_eq!(allocator.free(), len);
+    }
+
+    #[test]
+    fn test_allocator_observer() {
+        #[derive(Default)]
+        struct Events {
+            list: [(char, usize, usize, usize); 16],
+            len: usize,
+        }
+        impl Events {
+            fn push(&mut self, event: (char, usize, usize, usize)) {
+                self.list[self.len] = event;
+                self.len += 1;
+            }
+            fn take(&mut self) -> &[(char, usize, usize, usize)] {
+                let len = core::mem::take(&mut self.len);
+                &self.list[..len]
+            }
+        }
+        impl AllocObserver for Events {
+            fn on_allocate(
+                &mut self,
+                _: usize,
+                size: usize,
+                layer: usize,
+                _: Option<(usize, usize)>,
+            ) {
+                self.push(('a', 0, size, layer));
+            }
+            fn on_split(&mut self, ptr: usize, size: usize, layer: usize) {
+                self.push(('s', ptr, size, layer));
+            }
+            fn on_merge(&mut self, ptr: usize, size: usize, layer: usize) {
+                self.push(('m', ptr, size, layer));
+            }
+        }
+
+        let base = FAKE_BASE;
+        let mut allocator = fake_allocator::<2, _>(base, 12, Events::default());
+        // 逐页转移，相邻的伙伴合并
+        for i in 0..4 {
+            let page = NonNull::new((base + i * 4096) as *mut u8).unwrap();
+            unsafe { allocator.transfer(page, 4096) };
+        }
+        assert_eq!(
+            allocator.observer_mut().take(),
+            [
+                ('m', base, 8192, 1),
+                ('m', base + 8192, 8192, 1),
+                ('m', base, 16384, 2)
+            ]
+        );
+
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(
+            allocator.observer_mut().take(),
+            [
+                ('s', base, 16384, 2),
+                ('s', base, 8192, 1),
+                ('a', 0, 4096, 2)
+            ]
+        );
+
+        allocator.deallocate(p, s);
+        assert_eq!(
+            allocator.observer_mut().take(),
+            [('m', base, 8192, 1), ('m', base, 16384, 2)]
+        );
+
+        // 大于最大阶数的分配来自寡头行
+        let size = NonZeroUsize::new(16384).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(allocator.observer_mut().take(), [('a', 0, 16384, 2)]);
+        allocator.deallocate(p, s);
+        assert_eq!(allocator.observer_mut().take(), []);
+
+        // 取回时拆分空闲块，转移回来时重新合并
+        let page = NonNull::new((base + 4096) as *mut u8).unwrap();
+        assert_eq!(allocator.reclaim_range(page, 4096, |_, _| {}), 4096);
+        assert_eq!(
+            allocator.observer_mut().take(),
+            [('s', base, 16384, 2), ('s', base, 8192, 1)]
+        );
+        unsafe { allocator.transfer(page, 4096) };
+        assert_eq!(
+            allocator.observer_mut().take(),
+            [('m', base, 8192, 1), ('m', base, 16384, 2)]
+        );
+    }
+
+    #[test]
+    fn test_transfer_excluding() {
+        let base = FAKE_BASE;
+        let mut allocator = fake_allocator::<4, _>(base, 12, NoopObserver);
+
+        // 无序、互相重叠、超出范围以及长度为 0 的排除范围
+        let mut excluded = [
+            (base + 0x1_1000, 0x1800),
+            (base - 0x1000, 0x2000),
+            (base + 0x1_0800, 0x1000),
+            (base + 0x1_8000, 0),
+        ];
+        let ptr = NonNull::new(base as *mut u8).unwrap();
+        let trimmed = unsafe { allocator.transfer_excluding(ptr, 0x2_0000, &mut excluded) };
+        // 剩下 [base + 0x1000, base + 0x1_0800) 和 [base + 0x1_2800, base + 0x2_0000)，各丢弃半页
+        assert_eq!(trimmed, 0x1000);
+        assert_eq!(allocator.capacity(), 0x1_c000);
+        assert_eq!(allocator.free(), 0x1_c000);
+        assert_eq!(excluded[0], (base - 0x1000, 0x2000));
+
+        // 所有的页都在排除范围之外
+        let page = NonZeroUsize::new(4096).unwrap();
+        for _ in 0..0x1c {
+            let (ptr, _) = allocator.allocate::<u8>(0, page).unwrap();
+            let addr = ptr.as_ptr() as usize;
+            assert!((base + 0x1000..base + 0x1_0000).contains(&addr) || addr >= base + 0x1_3000);
+        }
+        assert_eq!(allocator.free(), 0);
+    }
+
+    #[test]
+    fn test_reclaim_range() {
+        #[repr(C, align(16384))]
+        struct Region([u8; 32 * 4096]);
+        static mut REGION: Region = Region([0; 32 * 4096]);
+
+        fn check<O: OligarchyCollection, B: BuddyCollection>() {
+            const PAGE: usize = 4096;
+            let base = core::ptr::addr_of_mut!(REGION) as usize;
+            let page = |i: usize| NonNull::new((base + i * PAGE) as *mut u8).unwrap();
+            // 寡头是 4 页
+            let mut allocator = BuddyAllocator::<2, O, B>::new();
+            allocator.init(12, page(0));
+            unsafe { allocator.transfer(page(0), 32 * PAGE) };
+
+            let mut held = [(0, 0); 32];
+            let mut len = 0;
+            for pages in [1, 2, 1, 3, 1] {
+                let size = NonZeroUsize::new(pages * PAGE).unwrap();
+                let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+                held[len] = (p.as_ptr() as usize, s);
+                len += 1;
+            }
+            let in_range = |p: usize| (base + PAGE..base + 9 * PAGE).contains(&p);
+            let busy_pages = held[..len]
+                .iter()
+                .map(|&(p, s)| (p..p + s).step_by(PAGE).filter(|&p| in_range(p)).count())
+                .sum::<usize>();
+
+            // 取回第 1 到 8 页，报告的段互不相邻，覆盖范围内所有被持有的页
+            let mut reported = 0;
+            let mut last_end = 0;
+            let reclaimed = allocator.reclaim_range(page(1), 8 * PAGE, |p, s| {
+                let p = p.as_ptr() as usize;
+                assert!(p > last_end && in_range(p) && in_range(p + s - PAGE));
+                last_end = p + s;
+                reported += s;
+            });
+            assert_eq!(reported, busy_pages * PAGE);
+            assert_eq!(reclaimed, (8 - busy_pages) * PAGE);
+            assert_eq!(allocator.capacity(), 32 * PAGE - reclaimed);
+            assert_eq!(allocator.free(), allocator.capacity() - 8 * PAGE);
+
+            // 取回的页不会再分配出去
+            let size = NonZeroUsize::new(PAGE).unwrap();
+            while let Ok((p, s)) = allocator.allocate::<u8>(0, size) {
+                assert!(!in_range(p.as_ptr() as usize));
+                held[len] = (p.as_ptr() as usize, s);
+                len += 1;
+            }
+            assert_eq!(allocator.free(), 0);
+
+            // 迁移后回收再取回
+            for &(p, s) in &held[..len] {
+                allocator.deallocate(NonNull::new(p as *mut u8).unwrap(), s);
+            }
+            // 已经取回的页不归分配器管理，也报告为不空闲
+            let mut reported = 0;
+            let reclaimed = allocator.reclaim_range(page(1), 8 * PAGE, |_, s| reported += s);
+            assert_eq!(reclaimed, busy_pages * PAGE);
+            assert_eq!(reported, (8 - busy_pages) * PAGE);
+            assert_eq!(allocator.capacity(), 24 * PAGE);
+            assert_eq!(allocator.free(), 24 * PAGE);
+
+            // 范围外的内存报告为不空闲
+            let mut reported = 0;
+            let reclaimed = allocator.reclaim_range(page(0), 2 * PAGE, |p, s| {
+                assert_eq!((p, s), (page(1), PAGE));
+                reported += 1;
+            });
+            assert_eq!((reclaimed, reported), (PAGE, 1));
+            assert_eq!(allocator.free(), 23 * PAGE);
+        }
+
+        check::<UsizeBuddy, UsizeBuddy>();
+        check::<LinkedListBuddy, LinkedListBuddy>();
+        check::<crate::AvlBuddy, crate::AvlBuddy>();
+        check::<crate::ExtentOligarchy, LinkedListBuddy>();
+    }
+
+    #[test]
+    fn test_trim() {
+        const BASE: usize = FAKE_BASE;
+        let mut allocator = fake_allocator::<4, _>(BASE, 12, RegionTable::<4>::new());
+        let ptr = NonNull::new(BASE as *mut u8).unwrap();
+        // 8 个 64 KiB 的寡头
+        unsafe { allocator.transfer(ptr, 0x8_0000) };
+        allocator
+            .allocate::<u8>(16, NonZeroUsize::new(0x1_0000).unwrap())
+            .unwrap();
+        allocator
+            .allocate::<u8>(0, NonZeroUsize::new(0x1000).unwrap())
+            .unwrap();
+        assert_eq!(allocator.free(), 0x6_f000);
+
+        // 保留 128 KiB 空闲内存，相邻的寡头合并成一段
+        let mut released = (0, 0);
+        let mut count = 0;
+        let size = allocator.trim(0x2_0000, |ptr, size| {
+            released = (ptr.as_ptr() as usize, size);
+            count += 1;
+        });
+        assert_eq!(size, 0x4_0000);
+        assert_eq!((count, released), (1, (BASE + 0x4_0000, 0x4_0000)));
+        assert_eq!(allocator.capacity(), 0x4_0000);
+        assert_eq!(allocator.free(), 0x2_f000);
+        assert!(allocator.observer().iter().eq([(BASE, 0x4_0000)]));
+
+        // 拆分了的寡头不会取出
+        assert_eq!(allocator.trim(0, |_, _| {}), 0x2_0000);
+        assert_eq!(allocator.trim(0, |_, _| unreachable!()), 0);
+        assert_eq!(allocator.capacity(), 0x2_0000);
+        assert_eq!(allocator.free(), 0xf000);
+        assert!(allocator.observer().iter().eq([(BASE, 0x2_0000)]));
+    }
+
+    #[test]
+    fn test_trim_extent_oligarchy() {
+        #[repr(C, align(16384))]
+        struct Memory([u8; 4 << 14]);
+        static mut MEMORY: Memory = Memory([0; 4 << 14]);
+
+        let mut allocator = BuddyAllocator::<2, ExtentOligarchy, LinkedListBuddy>::new();
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(MEMORY).cast::<u8>()).unwrap();
+        let base = ptr.as_ptr() as usize;
+        // 最大阶数为 14，4 个寡头
+        allocator.init(12, ptr);
+        unsafe { allocator.transfer(ptr, 4 << 14) };
+        let size = NonZeroUsize::new(1 << 14).unwrap();
+        let blocks = [(); 4].map(|_| allocator.allocate::<u8>(14, size).unwrap().0);
+        // 空闲的区间是 [0, 1) 和 [2, 4)
+        allocator.deallocate(blocks[0], 1 << 14);
+        allocator.deallocate(blocks[2], 1 << 14);
+        allocator.deallocate(blocks[3], 1 << 14);
+
+        // 不按最佳适配取最短的区间，仍然从高地址开始取
+        let mut released = (0, 0);
+        let size = allocator.trim(1 << 14, |ptr, size| {
+            released = (ptr.as_ptr() as usize, size)
+        });
+        assert_eq!(size, 2 << 14);
+        assert_eq!(released, (base + (2 << 14), 2 << 14));
+        assert_eq!(allocator.capacity(), 2 << 14);
+        allocator.deallocate(blocks[1], 1 << 14);
+        assert_eq!(allocator.free(), 2 << 14);
+    }
+
+    #[test]
+    fn test_trim_lifo_linked_list() {
+        #[repr(C, align(16384))]
+        struct Memory([u8; 4 << 14]);
+        static mut MEMORY: Memory = Memory([0; 4 << 14]);
+
+        let mut allocator = BuddyAllocator::<2, LinkedListBuddy, LinkedListBuddy>::new();
+        allocator.set_policy(TakePolicy::Lifo);
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(MEMORY).cast::<u8>()).unwrap();
+        let base = ptr.as_ptr() as usize;
+        // 最大阶数为 14，4 个寡头
+        allocator.init(12, ptr);
+        unsafe { allocator.transfer(ptr, 4 << 14) };
+        let size = NonZeroUsize::new(1 << 14).unwrap();
+        let blocks = [(); 4].map(|_| allocator.allocate::<u8>(14, size).unwrap().0);
+        // 按地址顺序回收，最低的寡头在表尾
+        for block in blocks {
+            allocator.deallocate(block, 1 << 14);
+        }
+
+        // 无序的链表从表尾取，先取出最低的寡头
+        let mut released = (0, 0);
+        let size = allocator.trim(3 << 14, |ptr, size| {
+            released = (ptr.as_ptr() as usize, size)
+        });
+        assert_eq!(size, 1 << 14);
+        assert_eq!(released, (base, 1 << 14));
+        // 寡头行的策略已经恢复，取最后放入的寡头
+        let size = NonZeroUsize::new(1 << 14).unwrap();
+        let (p, _) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(p.as_ptr() as usize, base + (3 << 14));
+    }
+
+    #[test]
+    fn test_split_off_absorb() {
+        #[repr(C, align(16384))]
+        struct Region([u8; 32 * 4096]);
+        static mut REGION: Region = Region([0; 32 * 4096]);
+
+        fn check<O: OligarchyCollection, B: BuddyCollection>() {
+            const PAGE: usize = 4096;
+            let base = core::ptr::addr_of_mut!(REGION) as usize;
+            let page = |i: usize| NonNull::new((base + i * PAGE) as *mut u8).unwrap();
+            // 寡头是 4 页
+            let mut allocator = BuddyAllocator::<2, O, B>::new();
+            allocator.init(12, page(0));
+            unsafe { allocator.transfer(page(0), 32 * PAGE) };
+
+            // 第 0 到 2 页已分配，拆分第 2 到 13 页
+            let size = NonZeroUsize::new(3 * PAGE).unwrap();
+            let (p0, s0) = allocator.allocate::<u8>(0, size).unwrap();
+            assert_eq!((p0, s0), (page(0), 3 * PAGE));
+            let in_range =
+                |p: usize| (page(2).as_ptr() as usize..page(14).as_ptr() as usize).contains(&p);
+            let mut other = allocator.split_off(page(2), 12 * PAGE);
+            assert_eq!(other.capacity(), 11 * PAGE);
+            assert_eq!(other.free(), 11 * PAGE);
+            assert_eq!(allocator.capacity(), 21 * PAGE);
+            assert_eq!(allocator.free(), 18 * PAGE);
+
+            // 两个分配器分别只分配范围内和范围外的页
+            let size = NonZeroUsize::new(PAGE).unwrap();
+            let mut held = [(page(0), 0); 32];
+            let mut len = 0;
+            while let Ok((p, s)) = other.allocate::<u8>(0, size) {
+                assert!(in_range(p.as_ptr() as usize));
+                held[len] = (p, s);
+                len += 1;
+            }
+            assert_eq!(len, 11);
+            held[..len]
+                .iter()
+                .for_each(|&(p, s)| other.deallocate(p, s));
+            while let Ok((p, s)) = allocator.allocate::<u8>(0, size) {
+                assert!(!in_range(p.as_ptr() as usize));
+                held[len] = (p, s);
+                len += 1;
+            }
+            assert_eq!(len, 11 + 18);
+            held[11..len]
+                .iter()
+                .for_each(|&(p, s)| allocator.deallocate(p, s));
+
+            // 并回后跨越边界合并，可以再次分配整个区域
+            allocator.deallocate(p0, s0);
+            allocator.absorb(other);
+            assert_eq!(allocator.capacity(), 32 * PAGE);
+            assert_eq!(allocator.free(), 32 * PAGE);
+            let size = NonZeroUsize::new(32 * PAGE).unwrap();
+            let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+            assert_eq!((p, s), (page(0), 32 * PAGE));
+            allocator.deallocate(p, s);
+        }
+
+        check::<UsizeBuddy, UsizeBuddy>();
+        check::<LinkedListBuddy, LinkedListBuddy>();
+        check::<crate::AvlBuddy, crate::AvlBuddy>();
+        check::<crate::ExtentOligarchy, LinkedListBuddy>();
+    }
+
+    #[test]
+    fn test_allocator_linked_list_oligarchy() {
+        let mut allocator = BuddyAllocator::<2, LinkedListBuddy, LinkedListBuddy>::new();
+
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(TEST_MEMORY).cast::<u8>()).unwrap();
+        let len = core::mem::size_of_val(unsafe { &*core::ptr::addr_of!(TEST_MEMORY) });
+
+        // 最大阶数为 14，64 KiB 中至少有 3 个连续的寡头
+        allocator.init(12, ptr);
+        unsafe {
+            allocator.transfer(ptr, len);
+        }
+
+        // 跨越多个寡头的分配
+        let size = NonZeroUsize::new(2 << 14).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(s, 2 << 14);
+        assert_eq!(allocator.free(), len - s);
+
+        allocator.deallocate(p, s);
+        assert_eq!(allocator.free(), len);
+    }
+
+    #[test]
+    fn test_allocator_extent_oligarchy() {
+        let mut allocator = BuddyAllocator::<2, ExtentOligarchy, LinkedListBuddy>::new();
+
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(TEST_MEMORY).cast::<u8>()).unwrap();
+        let len = core::mem::size_of_val(unsafe { &*core::ptr::addr_of!(TEST_MEMORY) });
+
+        // 最大阶数为 14，64 KiB 中至少有 3 个连续的寡头
+        allocator.init(12, ptr);
+        unsafe {
+            allocator.transfer(ptr, len);
+        }
+
+        // 跨越多个寡头的分配
+        let size = NonZeroUsize::new(3 << 14).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(s, 3 << 14);
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p1, s1) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(allocator.free(), len - s - s1);
+
+        allocator.deallocate(p, s);
+        allocator.deallocate(p1, s1);
+        assert_eq!(allocator.free(), len);
+        // 释放后重新合并成连续的区间
+        let size = NonZeroUsize::new(3 << 14).unwrap();
+        assert!(allocator.allocate::<u8>(0, size).is_ok());
+    }
+
+    #[test]
+    fn test_slice_allocator() {
+        static mut LINES: [LinkedListBuddy; 8] = [LinkedListBuddy::EMPTY; 8];
+
+        let mut allocator = SliceBuddyAllocator::<UsizeBuddy, LinkedListBuddy>::new();
+        assert_eq!(allocator.layers(), 0);
+
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(TEST_MEMORY).cast::<u8>()).unwrap();
+        let len = core::mem::size_of_val(unsafe { &*core::ptr::addr_of!(TEST_MEMORY) });
+
+        // 层数由运行时提供的存储决定
+        let lines = unsafe { &mut *core::ptr::addr_of_mut!(LINES) };
+        allocator.init_with_lines(12, ptr, &mut lines[..6]);
+        assert_eq!(allocator.layers(), 6);
+        assert_eq!(allocator.max_order(), 18);
+        unsafe { allocator.transfer(ptr, len) };
+
+        let size = NonZeroUsize::new(4096).unwrap();
+        let (p0, s0) = allocator.allocate::<u8>(0, size).unwrap();
+        let size = NonZeroUsize::new(3 * 4096).unwrap();
+        let (p1, s1) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!(allocator.free(), len - s0 - s1);
+        allocator.deallocate(p0, s0);
+        allocator.deallocate(p1, s1);
+        assert_eq!(allocator.free(), len);
+    }
+
     #[test]
     fn test_max_order() {
         let mut allocator: TestAllocator<4> = BuddyAllocator::new();
@@ -643,7 +2148,7 @@ mod tests {
 
         allocator.init(3, ptr);
 
-        // max_order = min_order + MAX_LAYER = 3 + 4 = 7
+        // max_order = min_order + N = 3 + 4 = 7
         assert_eq!(allocator.max_order(), 7);
     }
 
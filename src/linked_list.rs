@@ -1,26 +1,36 @@
-use crate::{BuddyCollection, BuddyLine, OligarchyCollection, Order};
+use crate::{BuddyCollection, BuddyLine, OligarchyCollection, Order, TakePolicy};
 use core::{fmt, ptr::NonNull};
 
 /// 侵入式链表伙伴行。
 ///
 /// 使用单向链表管理空闲内存块，适合管理小块内存。
-/// 不支持对齐分配，时间复杂度为 O(n)。
+/// 时间复杂度为 O(n)。
+///
+/// 除 [`TakePolicy::Lifo`] 外，链表按地址升序排列。
+/// 无序时作为寡头行查找连续的块需要多次遍历链表。
+/// 对齐的提取最多检查有限个结点，找不到时由分配器拆分更高阶的块。
 pub struct LinkedListBuddy {
     /// 空闲链表头节点。
     free_list: Node,
     /// 当前阶数，用于指针和索引的转换。
     order: Order,
+    /// 取块策略。
+    policy: TakePolicy,
 }
 
 /// 必须实现 [`Send`] 才能加锁。
 unsafe impl Send for LinkedListBuddy {}
 
+/// 对齐的提取从链表头开始最多检查的结点数。
+const ALIGNED_SCAN_LIMIT: usize = 64;
+
 impl BuddyLine for LinkedListBuddy {
     const INTRUSIVE_META_SIZE: usize = core::mem::size_of::<Node>();
 
     const EMPTY: Self = Self {
         free_list: Node { next: None },
         order: Order::new(0),
+        policy: TakePolicy::Lowest,
     };
 
     #[inline]
@@ -28,44 +38,106 @@ impl BuddyLine for LinkedListBuddy {
         self.order = Order::new(order);
     }
 
-    fn take(&mut self, _idx: usize) -> bool {
-        todo!("not efficient")
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.policy = policy;
+    }
+
+    /// 遍历链表查找目标结点，时间复杂度为 O(n)。
+    fn take(&mut self, idx: usize) -> bool {
+        self.order
+            .idx_to_ptr(idx)
+            .is_some_and(|ptr| self.free_list.remove(ptr))
+    }
+
+    /// 遍历链表查找目标结点，时间复杂度为 O(n)。
+    fn contains(&self, idx: usize) -> bool {
+        self.order
+            .idx_to_ptr(idx)
+            .is_some_and(|ptr| self.free_list.contains(ptr))
+    }
+
+    #[inline]
+    fn addressable(&self, idx: usize) -> bool {
+        self.order.idx_to_ptr::<Node>(idx).is_some()
     }
 }
 
 impl OligarchyCollection for LinkedListBuddy {
     #[inline]
     fn take_any(&mut self, align_order: usize, count: usize) -> Option<usize> {
-        if count > 1 || align_order > 0 {
-            // TODO 不支持
-            None
-        } else {
-            // 直接从中删除一个节点
-            self.free_list
-                .take_any()
-                .map(|ptr| self.order.ptr_to_idx(ptr))
+        match count {
+            0 => None,
+            1 => self.take_by_policy(align_order),
+            _ => self.take_run(align_order, count),
         }
     }
 
-    // 向头结点处插入一个节点
+    // 按策略插入一个节点
     #[inline]
     fn put(&mut self, idx: usize) {
         let ptr = self.order.idx_to_ptr(idx).expect("block address is null");
-        self.free_list.insert_unordered(ptr);
+        if self.policy == TakePolicy::Lifo {
+            self.free_list.insert_unordered(ptr);
+        } else {
+            self.free_list.insert_sorted(ptr);
+        }
+    }
+
+    // 只查找一次插入位置，时间复杂度为 O(n + count)
+    fn put_range(&mut self, idx: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let first = self.order.idx_to_ptr(idx).expect("block address is null");
+        // 无序时插在链表头
+        let cursor = if self.policy == TakePolicy::Lifo {
+            &mut self.free_list
+        } else {
+            self.free_list.seek(first)
+        };
+        // 从后往前插在同一个位置，这一段按地址升序
+        for idx in (idx..idx + count).rev() {
+            let mut node: NonNull<Node> = self.order.idx_to_ptr(idx).unwrap();
+            unsafe { node.as_mut() }.next = cursor.next.replace(node);
+        }
+    }
+
+    // 找到第一个结点后检查之后的结点是否连续，时间复杂度为 O(n + count)
+    fn take_range(&mut self, idx: usize, count: usize) -> bool {
+        if count == 0 {
+            return true;
+        }
+        let Some(first) = self.order.idx_to_ptr::<Node>(idx) else {
+            return false;
+        };
+        if self.policy == TakePolicy::Lifo {
+            return self.take_scattered(idx, count);
+        }
+        let mut pred = &mut self.free_list;
+        while let Some(mut next) = pred.next
+            && next < first
+        {
+            pred = unsafe { next.as_mut() };
+        }
+        let mut cursor = pred.next;
+        for idx in idx..idx + count {
+            match cursor {
+                Some(node) if self.order.idx_to_ptr(idx) == Some(node) => {
+                    cursor = unsafe { node.as_ref() }.next;
+                }
+                _ => return false,
+            }
+        }
+        pred.next = cursor;
+        true
     }
 }
 
 impl BuddyCollection for LinkedListBuddy {
     #[inline]
     fn take_any(&mut self, align_order: usize) -> Option<usize> {
-        if align_order != 0 {
-            // TODO 需要支持对齐吗？没效率，似乎没必要
-            None
-        } else {
-            self.free_list
-                .take_any()
-                .map(|ptr| self.order.ptr_to_idx(ptr))
-        }
+        self.take_by_policy(align_order)
     }
 
     // 向对应位置插入一个新的元素
@@ -77,7 +149,15 @@ impl BuddyCollection for LinkedListBuddy {
             self.free_list.insert_unordered(node);
             return None;
         };
-        if self.free_list.insert(node, buddy) {
+        if self.policy == TakePolicy::Lifo {
+            // 链表无序，需要找遍整个链表
+            if self.free_list.remove(buddy) {
+                Some(idx >> 1)
+            } else {
+                self.free_list.insert_unordered(node);
+                None
+            }
+        } else if self.free_list.insert(node, buddy) {
             None
         } else {
             // 插入失败说明伙伴已碰头
@@ -86,6 +166,98 @@ impl BuddyCollection for LinkedListBuddy {
     }
 }
 
+impl LinkedListBuddy {
+    /// 按策略取下一个序号对齐到 `align_order` 的节点。
+    #[inline]
+    fn take_by_policy(&mut self, align_order: usize) -> Option<usize> {
+        let mask = (1usize << align_order) - 1;
+        let order = &self.order;
+        let aligned = |ptr| order.ptr_to_idx(ptr) & mask == 0;
+        let ptr = if self.policy == TakePolicy::Highest {
+            self.free_list.take_last(aligned)
+        } else if align_order == 0 {
+            // 有序时头结点地址最低，无序时头结点最后放入
+            self.free_list.take_any()
+        } else {
+            self.free_list.take_first(ALIGNED_SCAN_LIMIT, aligned)
+        };
+        ptr.map(|ptr| self.order.ptr_to_idx(ptr))
+    }
+}
+
+impl LinkedListBuddy {
+    /// 按策略取下 `count` 个序号连续的节点，第一个序号对齐到 `align_order`。
+    fn take_run(&mut self, align_order: usize, count: usize) -> Option<usize> {
+        let mask = (1usize << align_order) - 1;
+        if self.policy == TakePolicy::Lifo {
+            // 无序时从最后放入的结点开始，逐个检查以它开头的连续段是否都在链表中
+            let mut cursor = self.free_list.next;
+            while let Some(node) = cursor {
+                let idx = self.order.ptr_to_idx(node);
+                if idx & mask == 0 && self.take_scattered(idx, count) {
+                    return Some(idx);
+                }
+                cursor = unsafe { node.as_ref() }.next;
+            }
+            return None;
+        }
+        let highest = self.policy == TakePolicy::Highest;
+        // 当前连续段首结点的前驱和首序号
+        let mut stretch: Option<(NonNull<Node>, usize)> = None;
+        // 找到的连续段首结点的前驱、连续段的首序号和要取的首序号
+        let mut found = None;
+        let mut last = 0;
+        let mut cursor = NonNull::from(&mut self.free_list);
+        while let Some(next) = unsafe { cursor.as_ref() }.next {
+            let idx = self.order.ptr_to_idx(next);
+            let (pred, first) = match stretch {
+                Some(s) if idx == last + 1 => s,
+                _ => *stretch.insert((cursor, idx)),
+            };
+            // 以当前结点结尾的 `count` 个结点
+            if let Some(start) = (idx + 1).checked_sub(count)
+                && start >= first
+                && start & mask == 0
+            {
+                found = Some((pred, first, start));
+                if !highest {
+                    break;
+                }
+            }
+            last = idx;
+            cursor = next;
+        }
+        let (mut pred, first, start) = found?;
+        // 找到要取的第一个结点的前驱，再跳过要取的结点
+        for _ in first..start {
+            pred = unsafe { pred.as_ref() }.next.unwrap();
+        }
+        let mut end = pred;
+        for _ in 0..count {
+            end = unsafe { end.as_ref() }.next.unwrap();
+        }
+        unsafe { pred.as_mut() }.next = unsafe { end.as_ref() }.next;
+        Some(start)
+    }
+
+    /// 在无序的链表中取下序号为 `idx..idx + count` 的结点，只有全部存在时才取下。
+    ///
+    /// 时间复杂度为 O(n * count)。
+    fn take_scattered(&mut self, idx: usize, count: usize) -> bool {
+        let ptrs = (idx..idx + count).map(|idx| self.order.idx_to_ptr::<Node>(idx));
+        if !ptrs
+            .clone()
+            .all(|ptr| ptr.is_some_and(|ptr| self.free_list.contains(ptr)))
+        {
+            return false;
+        }
+        for ptr in ptrs {
+            self.free_list.remove(ptr.unwrap());
+        }
+        true
+    }
+}
+
 impl fmt::Debug for LinkedListBuddy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "[")?;
@@ -145,6 +317,53 @@ impl Node {
         }
     }
 
+    /// 按地址顺序插入结点。
+    #[inline]
+    fn insert_sorted(&mut self, mut node: NonNull<Node>) {
+        let cursor = self.seek(node);
+        unsafe { node.as_mut() }.next = cursor.next.replace(node);
+    }
+
+    /// 按地址顺序找到结点 `node` 应该插在其后的结点。
+    #[inline]
+    fn seek(&mut self, node: NonNull<Node>) -> &mut Node {
+        let mut cursor = self;
+        while let Some(mut next) = cursor.next {
+            if next > node {
+                break;
+            }
+            cursor = unsafe { next.as_mut() };
+        }
+        cursor
+    }
+
+    /// 移除目标结点，如果目标结点存在返回 `true`。
+    #[inline]
+    fn remove(&mut self, target: NonNull<Node>) -> bool {
+        let mut cursor = self;
+        while let Some(mut next) = cursor.next {
+            if next == target {
+                cursor.next = unsafe { next.as_ref().next };
+                return true;
+            }
+            cursor = unsafe { next.as_mut() };
+        }
+        false
+    }
+
+    /// 判断目标结点是否在链表中。
+    #[inline]
+    fn contains(&self, target: NonNull<Node>) -> bool {
+        let mut cursor = self.next;
+        while let Some(next) = cursor {
+            if next == target {
+                return true;
+            }
+            cursor = unsafe { next.as_ref() }.next;
+        }
+        false
+    }
+
     /// 直接在头结点插入。
     #[inline]
     fn insert_unordered(&mut self, mut node: NonNull<Node>) {
@@ -158,6 +377,43 @@ impl Node {
         self.next = root.and_then(|node| unsafe { node.as_ref().next });
         root
     }
+
+    /// 取下前 `limit` 个结点中第一个满足条件的结点。
+    #[inline]
+    fn take_first(
+        &mut self,
+        limit: usize,
+        f: impl Fn(NonNull<Node>) -> bool,
+    ) -> Option<NonNull<Node>> {
+        let mut cursor = self;
+        for _ in 0..limit {
+            let mut next = cursor.next?;
+            if f(next) {
+                cursor.next = unsafe { next.as_ref().next };
+                return Some(next);
+            }
+            cursor = unsafe { next.as_mut() };
+        }
+        None
+    }
+
+    /// 取下最后一个满足条件的结点。
+    #[inline]
+    fn take_last(&mut self, f: impl Fn(NonNull<Node>) -> bool) -> Option<NonNull<Node>> {
+        // 记录最后一个满足条件的结点的前驱
+        let mut prev = None;
+        let mut cursor = NonNull::from(self);
+        while let Some(next) = unsafe { cursor.as_ref() }.next {
+            if f(next) {
+                prev = Some(cursor);
+            }
+            cursor = next;
+        }
+        let prev = unsafe { prev?.as_mut() };
+        let ans = prev.next?;
+        prev.next = unsafe { ans.as_ref().next };
+        Some(ans)
+    }
 }
 
 #[cfg(test)]
@@ -165,7 +421,7 @@ mod tests {
     use super::*;
 
     // 为测试分配一些内存作为节点存储
-    #[repr(C, align(16))]
+    #[repr(C, align(32))]
     struct TestMemory {
         data: [u8; 256],
     }
@@ -213,10 +469,12 @@ mod tests {
         // 将指针转换为索引，然后 put
         let idx = list.order.ptr_to_idx(node_ptr);
         OligarchyCollection::put(&mut list, idx);
+        assert!(list.contains(idx) && !list.contains(idx + 1));
 
         // 可以成功取出
         let taken_idx = OligarchyCollection::take_any(&mut list, 0, 1);
         assert_eq!(taken_idx, Some(idx));
+        assert!(!list.contains(idx));
     }
 
     #[test]
@@ -252,22 +510,134 @@ mod tests {
 
         // 空链表应该返回 None
         assert_eq!(BuddyCollection::take_any(&mut list, 0), None);
-
-        // 不支持对齐
         assert_eq!(BuddyCollection::take_any(&mut list, 1), None);
     }
 
+    #[test]
+    fn test_take_any_with_align() {
+        let mut memory = TestMemory { data: [0; 256] };
+        // base 对齐到 32，即阶数 4 下的序号对齐到 2
+        let base = memory.data.as_mut_ptr() as usize >> 4;
+
+        for (policy, expect) in [
+            (TakePolicy::Lowest, [2, 6]),
+            (TakePolicy::Highest, [6, 2]),
+            (TakePolicy::Lifo, [2, 6]),
+        ] {
+            let mut list = LinkedListBuddy::EMPTY;
+            list.init(4, 0);
+            list.set_policy(policy);
+            // 序号 base + 1、base + 3 对齐到 1，base + 2、base + 6 对齐到 2
+            for i in [6, 3, 2, 1] {
+                OligarchyCollection::put(&mut list, base + i);
+            }
+            for i in expect {
+                assert_eq!(BuddyCollection::take_any(&mut list, 1), Some(base + i));
+            }
+            assert_eq!(BuddyCollection::take_any(&mut list, 1), None);
+            assert!(OligarchyCollection::take_any(&mut list, 0, 1).is_some());
+        }
+    }
+
     #[test]
     fn test_oligarchy_collection_take_any() {
         let mut list = LinkedListBuddy::EMPTY;
         list.init(0, 0);
 
-        // 不支持多个块
+        // 空链表
         assert_eq!(OligarchyCollection::take_any(&mut list, 0, 2), None);
-        // 不支持对齐
+        // 空链表
         assert_eq!(OligarchyCollection::take_any(&mut list, 1, 1), None);
     }
 
+    #[test]
+    fn test_oligarchy_take_run() {
+        let mut memory = TestMemory { data: [0; 256] };
+        // base 对齐到 32，即阶数 4 下的序号对齐到 2
+        let base = memory.data.as_mut_ptr() as usize >> 4;
+
+        for (policy, expect) in [
+            (TakePolicy::Lowest, [2, 8]),
+            (TakePolicy::Highest, [10, 2]),
+            (TakePolicy::Lifo, [8, 2]),
+        ] {
+            let mut list = LinkedListBuddy::EMPTY;
+            list.init(4, 0);
+            list.set_policy(policy);
+            // 乱序放入，除 Lifo 外链表仍然有序
+            for i in [12, 1, 5, 2, 11, 3, 9, 4, 10, 8] {
+                OligarchyCollection::put(&mut list, base + i);
+            }
+            // 连续段为 1..=5 和 8..=12
+            for i in expect {
+                assert_eq!(
+                    OligarchyCollection::take_any(&mut list, 1, 3),
+                    Some(base + i)
+                );
+            }
+            assert_eq!(OligarchyCollection::take_any(&mut list, 1, 3), None);
+            assert_eq!(OligarchyCollection::take_any(&mut list, 0, 0), None);
+            // 剩下的块保持原来的顺序
+            let mut rest = [0; 4];
+            for r in rest.iter_mut() {
+                *r = OligarchyCollection::take_any(&mut list, 0, 1).unwrap() - base;
+            }
+            match policy {
+                TakePolicy::Lowest => assert_eq!(rest, [1, 5, 11, 12]),
+                TakePolicy::Highest => assert_eq!(rest, [9, 8, 5, 1]),
+                TakePolicy::Lifo => assert_eq!(rest, [11, 5, 1, 12]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_oligarchy_range() {
+        let mut memory = TestMemory { data: [0; 256] };
+        let base = memory.data.as_mut_ptr() as usize >> 4;
+
+        let mut list = LinkedListBuddy::EMPTY;
+        list.init(4, 0);
+        for i in [1, 12] {
+            OligarchyCollection::put(&mut list, base + i);
+        }
+        list.put_range(base + 4, 5);
+        list.put_range(base + 2, 0);
+        // 连续段不完整时不提取
+        assert!(!list.take_range(base + 7, 3));
+        assert!(!list.take_range(base, 2));
+        assert!(list.take_range(base + 5, 3));
+        assert!(list.take_range(base + 5, 0));
+        // 剩下的块仍然有序
+        let mut rest = [0; 4];
+        for r in rest.iter_mut() {
+            *r = OligarchyCollection::take_any(&mut list, 0, 1).unwrap() - base;
+        }
+        assert_eq!(rest, [1, 4, 8, 12]);
+        assert_eq!(OligarchyCollection::take_any(&mut list, 0, 1), None);
+    }
+
+    #[test]
+    fn test_lifo_oligarchy_range() {
+        let mut memory = TestMemory { data: [0; 256] };
+        let base = memory.data.as_mut_ptr() as usize >> 4;
+
+        let mut list = LinkedListBuddy::EMPTY;
+        list.init(4, 0);
+        list.set_policy(TakePolicy::Lifo);
+        OligarchyCollection::put(&mut list, base + 12);
+        list.put_range(base + 4, 5);
+        OligarchyCollection::put(&mut list, base + 1);
+        assert!(!list.take_range(base + 7, 3));
+        assert!(list.take_range(base + 5, 3));
+        // 后放入的先取出，同一段按地址升序
+        let mut rest = [0; 4];
+        for r in rest.iter_mut() {
+            *r = OligarchyCollection::take_any(&mut list, 0, 1).unwrap() - base;
+        }
+        assert_eq!(rest, [1, 4, 8, 12]);
+        assert_eq!(OligarchyCollection::take_any(&mut list, 0, 1), None);
+    }
+
     #[test]
     fn test_node_insert() {
         // 测试 Node::insert 方法
@@ -290,6 +660,49 @@ mod tests {
         assert!(head.insert(ptr3, buddy));
     }
 
+    #[test]
+    fn test_take_by_policy() {
+        let mut memory = TestMemory { data: [0; 256] };
+        let base = memory.data.as_mut_ptr() as usize >> 4;
+
+        for (policy, expect) in [
+            (TakePolicy::Lowest, [0, 2, 4]),
+            (TakePolicy::Highest, [4, 2, 0]),
+            (TakePolicy::Lifo, [2, 0, 4]),
+        ] {
+            let mut list = LinkedListBuddy::EMPTY;
+            list.init(4, 0);
+            list.set_policy(policy);
+            // 乱序放入互不为伙伴的块
+            for i in [4, 0, 2] {
+                OligarchyCollection::put(&mut list, base + i);
+            }
+            for i in expect {
+                assert_eq!(BuddyCollection::take_any(&mut list, 0), Some(base + i));
+            }
+            assert_eq!(BuddyCollection::take_any(&mut list, 0), None);
+        }
+    }
+
+    #[test]
+    fn test_lifo_buddy_merge() {
+        let mut memory = TestMemory { data: [0; 256] };
+        let idx0 = memory.data.as_mut_ptr() as usize >> 4;
+
+        let mut list = LinkedListBuddy::EMPTY;
+        list.init(4, 0);
+        list.set_policy(TakePolicy::Lifo);
+        assert_eq!(BuddyCollection::put(&mut list, idx0 + 2), None);
+        assert_eq!(BuddyCollection::put(&mut list, idx0), None);
+        // 伙伴不在链表头也能找到
+        assert_eq!(
+            BuddyCollection::put(&mut list, idx0 + 3),
+            Some((idx0 + 3) >> 1)
+        );
+        assert_eq!(BuddyCollection::take_any(&mut list, 0), Some(idx0));
+        assert_eq!(BuddyCollection::take_any(&mut list, 0), None);
+    }
+
     #[test]
     fn test_node_take_any() {
         let mut head = Node { next: None };
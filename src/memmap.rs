@@ -0,0 +1,1003 @@
+//! 解析固件提供的内存布局。
+//!
+//! 支持 e820 表、multiboot2 的内存映射标签、UEFI 内存描述符数组和扁平设备树的
+//! `/memory` 与 `/reserved-memory` 节点。每种解析器都产生 [`MemoryRegion`]，
+//! 收集到 [`MemoryMap`] 中合并、扣除保留区域并按页对齐后转移给分配器：
+//!
+//! ```
+//! use customizable_buddy::memmap::{E820, MemoryMap};
+//!
+//! // 一项 e820 表：[0x10_0000, 0x20_0000) 可用
+//! let mut table = [0u8; 20];
+//! table[..8].copy_from_slice(&0x10_0000u64.to_le_bytes());
+//! table[8..16].copy_from_slice(&0x10_0000u64.to_le_bytes());
+//! table[16..].copy_from_slice(&1u32.to_le_bytes());
+//!
+//! let mut map = MemoryMap::<8>::new();
+//! map.extend(E820::new(&table, E820::ENTRY_SIZE).unwrap()).unwrap();
+//! // 扣除内核镜像
+//! map.reserve(0x10_0000, 0x3_2000).unwrap();
+//! assert!(map.ranges(12).eq([(0x13_2000, 0xc_e000)]));
+//! ```
+
+use crate::{AllocObserver, BuddyAllocator, BuddyCollection, LineStorage, OligarchyCollection};
+use core::{fmt, ptr::NonNull, slice::ChunksExact};
+
+/// 内存区域的类型。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemoryKind {
+    /// 可以交给分配器的内存。
+    Usable,
+    /// 不能交给分配器的内存。
+    Reserved,
+}
+
+/// 固件报告的一段物理内存。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemoryRegion {
+    /// 起始地址。
+    pub start: u64,
+    /// 长度。
+    pub size: u64,
+    /// 类型。
+    pub kind: MemoryKind,
+}
+
+/// 解析或整理内存布局失败。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemmapError {
+    /// 数据格式错误。
+    Malformed,
+    /// 没有找到内存布局。
+    NotFound,
+    /// 区域数量超出了 [`MemoryMap`] 的容量。
+    Full,
+}
+
+impl fmt::Display for MemmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => f.write_str("malformed memory map"),
+            Self::NotFound => f.write_str("memory map not found"),
+            Self::Full => f.write_str("memory map is full"),
+        }
+    }
+}
+
+/// e820 表。
+///
+/// 类型 1 是可用内存，其他类型（包括 ACPI 可回收内存）都视为保留。
+/// 表项带有 ACPI 3.0 扩展属性时，属性第 0 位为 0 的表项被忽略。
+pub struct E820<'a> {
+    entries: ChunksExact<'a, u8>,
+}
+
+impl<'a> E820<'a> {
+    /// 不带扩展属性的表项长度。
+    pub const ENTRY_SIZE: usize = 20;
+
+    /// 解析每项 `entry_size` 字节的表。
+    pub fn new(table: &'a [u8], entry_size: usize) -> Result<Self, MemmapError> {
+        if entry_size < Self::ENTRY_SIZE || !table.len().is_multiple_of(entry_size) {
+            return Err(MemmapError::Malformed);
+        }
+        Ok(Self {
+            entries: table.chunks_exact(entry_size),
+        })
+    }
+}
+
+impl Iterator for E820<'_> {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = self.entries.next()?;
+            if entry.len() >= 24 && le_u32(entry, 20).ok()? & 1 == 0 {
+                continue;
+            }
+            return Some(MemoryRegion {
+                start: le_u64(entry, 0).ok()?,
+                size: le_u64(entry, 8).ok()?,
+                kind: match le_u32(entry, 16).ok()? {
+                    1 => MemoryKind::Usable,
+                    _ => MemoryKind::Reserved,
+                },
+            });
+        }
+    }
+}
+
+/// multiboot2 启动信息中的内存映射标签。
+///
+/// 类型 1 是可用内存，其他类型都视为保留。
+pub struct Multiboot2<'a> {
+    entries: ChunksExact<'a, u8>,
+}
+
+impl<'a> Multiboot2<'a> {
+    /// 内存映射标签的类型。
+    const TAG_MMAP: u32 = 6;
+    /// 结束标签的类型。
+    const TAG_END: u32 = 0;
+
+    /// 在启动信息 `info` 中查找内存映射标签。
+    pub fn new(info: &'a [u8]) -> Result<Self, MemmapError> {
+        let total = le_u32(info, 0)? as usize;
+        let info = info.get(..total).ok_or(MemmapError::Malformed)?;
+        // 标签从第 8 字节开始，每个标签对齐到 8 字节
+        let mut pos = 8;
+        loop {
+            let ty = le_u32(info, pos)?;
+            let size = le_u32(info, pos + 4)? as usize;
+            let tag = info
+                .get(pos..pos + size)
+                .filter(|_| size >= 8)
+                .ok_or(MemmapError::Malformed)?;
+            match ty {
+                Self::TAG_END => return Err(MemmapError::NotFound),
+                Self::TAG_MMAP => {
+                    let entry_size = le_u32(tag, 8)? as usize;
+                    if entry_size < 24 {
+                        return Err(MemmapError::Malformed);
+                    }
+                    let entries = tag.get(16..).ok_or(MemmapError::Malformed)?;
+                    return Ok(Self {
+                        entries: entries.chunks_exact(entry_size),
+                    });
+                }
+                _ => pos = (pos + size).next_multiple_of(8),
+            }
+        }
+    }
+}
+
+impl Iterator for Multiboot2<'_> {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.entries.next()?;
+        Some(MemoryRegion {
+            start: le_u64(entry, 0).ok()?,
+            size: le_u64(entry, 8).ok()?,
+            kind: match le_u32(entry, 16).ok()? {
+                1 => MemoryKind::Usable,
+                _ => MemoryKind::Reserved,
+            },
+        })
+    }
+}
+
+/// `GetMemoryMap` 返回的 UEFI 内存描述符数组。
+///
+/// 空闲内存和启动服务的代码与数据在退出启动服务后可用；
+/// 加载器的代码与数据通常包含内核镜像和启动参数，与其他类型一样视为保留。
+pub struct Uefi<'a> {
+    descriptors: ChunksExact<'a, u8>,
+}
+
+impl<'a> Uefi<'a> {
+    /// 描述符的最小长度。固件报告的描述符长度可能更大。
+    pub const DESCRIPTOR_SIZE: usize = 40;
+    /// UEFI 页的阶数。
+    const PAGE_ORDER: u32 = 12;
+
+    /// 解析每项 `descriptor_size` 字节的数组。
+    pub fn new(map: &'a [u8], descriptor_size: usize) -> Result<Self, MemmapError> {
+        if descriptor_size < Self::DESCRIPTOR_SIZE || !map.len().is_multiple_of(descriptor_size) {
+            return Err(MemmapError::Malformed);
+        }
+        Ok(Self {
+            descriptors: map.chunks_exact(descriptor_size),
+        })
+    }
+}
+
+impl Iterator for Uefi<'_> {
+    type Item = MemoryRegion;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let desc = self.descriptors.next()?;
+        Some(MemoryRegion {
+            start: le_u64(desc, 8).ok()?,
+            size: le_u64(desc, 24)
+                .ok()?
+                .checked_shl(Self::PAGE_ORDER)
+                .unwrap_or(u64::MAX),
+            kind: match le_u32(desc, 0).ok()? {
+                // EfiBootServicesCode、EfiBootServicesData、EfiConventionalMemory
+                3 | 4 | 7 => MemoryKind::Usable,
+                _ => MemoryKind::Reserved,
+            },
+        })
+    }
+}
+
+/// 扁平设备树。
+///
+/// 根节点下名为 `memory` 或者 `device_type` 为 `"memory"` 的节点的 `reg` 是可用内存；
+/// 内存保留块和 `/reserved-memory` 的子节点的 `reg` 是保留内存。
+/// 构造时检查整个结构块，迭代不会遇到格式错误。
+#[derive(Clone)]
+pub struct Fdt<'a> {
+    /// 尚未读完的内存保留块。
+    rsvmap: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+    pos: usize,
+    /// 当前节点的深度，根节点为 1。
+    depth: usize,
+    /// 根节点的 `#address-cells` 和 `#size-cells`。
+    root_cells: (usize, usize),
+    /// `/reserved-memory` 的 `#address-cells` 和 `#size-cells`。
+    reserved_cells: (usize, usize),
+    /// 当前根节点的子节点的类型。
+    node: FdtNode,
+    /// 当前节点的 `reg`。
+    reg: &'a [u8],
+    /// 待产生的区域。
+    pending: (&'a [u8], (usize, usize), MemoryKind),
+}
+
+/// 根节点的子节点的类型。
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FdtNode {
+    Other,
+    Memory,
+    ReservedMemory,
+}
+
+impl<'a> Fdt<'a> {
+    const MAGIC: u32 = 0xd00d_feed;
+    const BEGIN_NODE: u32 = 1;
+    const END_NODE: u32 = 2;
+    const PROP: u32 = 3;
+    const NOP: u32 = 4;
+    const END: u32 = 9;
+    /// `#address-cells` 和 `#size-cells` 的默认值。
+    const DEFAULT_CELLS: (usize, usize) = (2, 1);
+
+    /// 解析设备树 `blob`。
+    pub fn new(blob: &'a [u8]) -> Result<Self, MemmapError> {
+        if be_u32(blob, 0)? != Self::MAGIC {
+            return Err(MemmapError::Malformed);
+        }
+        let total = be_u32(blob, 4)? as usize;
+        let blob = blob.get(..total).ok_or(MemmapError::Malformed)?;
+        let off_struct = be_u32(blob, 8)? as usize;
+        let off_strings = be_u32(blob, 12)? as usize;
+        let off_rsvmap = be_u32(blob, 16)? as usize;
+        let version = be_u32(blob, 20)?;
+        let size_strings = be_u32(blob, 32)? as usize;
+        // 第 17 版之前没有结构块长度
+        let structs = if version >= 17 {
+            let size_struct = be_u32(blob, 36)? as usize;
+            blob.get(off_struct..off_struct + size_struct)
+        } else {
+            blob.get(off_struct..)
+        };
+        let fdt = Self {
+            rsvmap: blob.get(off_rsvmap..).ok_or(MemmapError::Malformed)?,
+            structs: structs.ok_or(MemmapError::Malformed)?,
+            strings: blob
+                .get(off_strings..off_strings + size_strings)
+                .ok_or(MemmapError::Malformed)?,
+            pos: 0,
+            depth: 0,
+            root_cells: Self::DEFAULT_CELLS,
+            reserved_cells: Self::DEFAULT_CELLS,
+            node: FdtNode::Other,
+            reg: &[],
+            pending: (&[], Self::DEFAULT_CELLS, MemoryKind::Usable),
+        };
+        // 预先走一遍，之后的迭代不会出错
+        let mut check = fdt.clone();
+        while check.try_next()?.is_some() {}
+        Ok(fdt)
+    }
+
+    fn try_next(&mut self) -> Result<Option<MemoryRegion>, MemmapError> {
+        // 内存保留块以一对 0 结束
+        while !self.rsvmap.is_empty() {
+            let start = be_u64(self.rsvmap, 0)?;
+            let size = be_u64(self.rsvmap, 8)?;
+            if start == 0 && size == 0 {
+                self.rsvmap = &[];
+            } else {
+                self.rsvmap = &self.rsvmap[16..];
+                return Ok(Some(MemoryRegion {
+                    start,
+                    size,
+                    kind: MemoryKind::Reserved,
+                }));
+            }
+        }
+        loop {
+            let (reg, (address_cells, size_cells), kind) = self.pending;
+            if !reg.is_empty() {
+                let start = be_cells(reg, 0, address_cells)?;
+                let size = be_cells(reg, address_cells, size_cells)?;
+                self.pending.0 = &reg[(address_cells + size_cells) * 4..];
+                return Ok(Some(MemoryRegion { start, size, kind }));
+            }
+            if !self.walk()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// 读一个标记。结构块结束时返回 `false`。
+    fn walk(&mut self) -> Result<bool, MemmapError> {
+        let token = be_u32(self.structs, self.pos)?;
+        self.pos += 4;
+        match token {
+            Self::BEGIN_NODE => {
+                let name = cstr(self.structs, self.pos)?;
+                self.pos = (self.pos + name.len() + 1).next_multiple_of(4);
+                self.depth += 1;
+                match self.depth {
+                    2 => {
+                        self.node = if name == b"memory" || name.starts_with(b"memory@") {
+                            FdtNode::Memory
+                        } else if name == b"reserved-memory" {
+                            self.reserved_cells = Self::DEFAULT_CELLS;
+                            FdtNode::ReservedMemory
+                        } else {
+                            FdtNode::Other
+                        };
+                        self.reg = &[];
+                    }
+                    3 if self.node == FdtNode::ReservedMemory => self.reg = &[],
+                    _ => {}
+                }
+            }
+            Self::END_NODE => {
+                match (self.depth, self.node) {
+                    (2, FdtNode::Memory) => {
+                        self.pending = (self.reg, self.root_cells, MemoryKind::Usable)
+                    }
+                    (3, FdtNode::ReservedMemory) => {
+                        self.pending = (self.reg, self.reserved_cells, MemoryKind::Reserved)
+                    }
+                    (0, _) => return Err(MemmapError::Malformed),
+                    _ => {}
+                }
+                let (reg, (address_cells, size_cells), _) = self.pending;
+                if !reg.len().is_multiple_of((address_cells + size_cells) * 4) {
+                    return Err(MemmapError::Malformed);
+                }
+                self.reg = &[];
+                self.depth -= 1;
+            }
+            Self::PROP => {
+                let len = be_u32(self.structs, self.pos)? as usize;
+                let name = cstr(self.strings, be_u32(self.structs, self.pos + 4)? as usize)?;
+                let value = self
+                    .structs
+                    .get(self.pos + 8..self.pos + 8 + len)
+                    .ok_or(MemmapError::Malformed)?;
+                self.pos = (self.pos + 8 + len).next_multiple_of(4);
+                let cells = || match be_u32(value, 0)? {
+                    n @ 1..=2 => Ok(n as usize),
+                    _ => Err(MemmapError::Malformed),
+                };
+                match (self.depth, self.node, name) {
+                    (1, _, b"#address-cells") => self.root_cells.0 = cells()?,
+                    (1, _, b"#size-cells") => self.root_cells.1 = cells()?,
+                    (2, FdtNode::ReservedMemory, b"#address-cells") => {
+                        self.reserved_cells.0 = cells()?
+                    }
+                    (2, FdtNode::ReservedMemory, b"#size-cells") => {
+                        self.reserved_cells.1 = cells()?
+                    }
+                    (2, FdtNode::Other, b"device_type") if value == b"memory\0" => {
+                        self.node = FdtNode::Memory
+                    }
+                    (2, FdtNode::Memory | FdtNode::Other, b"reg")
+                    | (3, FdtNode::ReservedMemory, b"reg") => self.reg = value,
+                    _ => {}
+                }
+            }
+            Self::NOP => {}
+            Self::END if self.depth == 0 => return Ok(false),
+            _ => return Err(MemmapError::Malformed),
+        }
+        Ok(true)
+    }
+}
+
+impl Iterator for Fdt<'_> {
+    type Item = MemoryRegion;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.try_next().ok().flatten()
+    }
+}
+
+/// 整理后的内存布局，可用和保留的区域各最多 `M` 段。
+///
+/// 可用区域和保留区域分别合并成互不重叠的段，迭代时从可用区域中扣除保留区域，
+/// 因此加入的先后次序不影响结果，固件报告的区域重叠时保留优先。
+pub struct MemoryMap<const M: usize> {
+    usable: RangeSet<M>,
+    reserved: RangeSet<M>,
+}
+
+impl<const M: usize> MemoryMap<M> {
+    /// 空的内存布局。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            usable: RangeSet::new(),
+            reserved: RangeSet::new(),
+        }
+    }
+
+    /// 加入一段区域。
+    pub fn add(&mut self, region: MemoryRegion) -> Result<(), MemmapError> {
+        let end = region.start.saturating_add(region.size);
+        match region.kind {
+            MemoryKind::Usable => self.usable.insert(region.start, end),
+            MemoryKind::Reserved => self.reserved.insert(region.start, end),
+        }
+    }
+
+    /// 加入解析器产生的所有区域。
+    pub fn extend(
+        &mut self,
+        regions: impl IntoIterator<Item = MemoryRegion>,
+    ) -> Result<(), MemmapError> {
+        regions.into_iter().try_for_each(|region| self.add(region))
+    }
+
+    /// 保留 `[start, start + size)`，例如内核镜像、初始内存盘或者设备树本身。
+    #[inline]
+    pub fn reserve(&mut self, start: u64, size: u64) -> Result<(), MemmapError> {
+        self.add(MemoryRegion {
+            start,
+            size,
+            kind: MemoryKind::Reserved,
+        })
+    }
+
+    /// 按地址顺序迭代扣除保留区域后的可用内存，产生 `(地址, 长度)`。
+    ///
+    /// 每段向内对齐到 `align_order` 阶，对齐后为空的段被丢弃。
+    /// 地址 0 所在的块和超出 `usize` 的部分不会产生。
+    #[inline]
+    pub fn ranges(&self, align_order: usize) -> Ranges<'_, M> {
+        Ranges {
+            map: self,
+            align: 1 << align_order,
+            usable: 0,
+            reserved: 0,
+            cursor: 0,
+        }
+    }
+
+    /// 把所有可用内存转移给 `allocator`，返回转移的字节数。
+    ///
+    /// 每段向内对齐到分配器的最小阶数，地址直接用作指针。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证：
+    ///
+    /// - 物理地址与虚拟地址恒等映射；
+    /// - 内存布局如实描述了物理内存，可用的内存没有被其他任何对象引用；
+    /// - 可用的内存和已经托管的内存块不重叠。
+    pub unsafe fn transfer<
+        const N: usize,
+        O: OligarchyCollection,
+        B: BuddyCollection,
+        S: LineStorage<B>,
+        W: AllocObserver,
+    >(
+        &self,
+        allocator: &mut BuddyAllocator<N, O, B, S, W>,
+    ) -> usize {
+        self.ranges(allocator.min_order)
+            .map(|(ptr, size)| {
+                let ptr = unsafe { NonNull::new_unchecked(ptr as *mut u8) };
+                unsafe { allocator.transfer(ptr, size) };
+                size
+            })
+            .sum()
+    }
+}
+
+impl<const M: usize> Default for MemoryMap<M> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const M: usize> fmt::Debug for MemoryMap<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryMap")
+            .field("usable", &self.usable)
+            .field("reserved", &self.reserved)
+            .finish()
+    }
+}
+
+/// [`MemoryMap::ranges`] 的迭代器。
+pub struct Ranges<'a, const M: usize> {
+    map: &'a MemoryMap<M>,
+    align: u64,
+    usable: usize,
+    reserved: usize,
+    /// 已经产生到的地址。
+    cursor: u64,
+}
+
+impl<const M: usize> Iterator for Ranges<'_, M> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let usable = self.map.usable.as_slice();
+        let reserved = self.map.reserved.as_slice();
+        loop {
+            let &(s, e) = usable.get(self.usable)?;
+            let start = self.cursor.max(s);
+            if start >= e {
+                self.usable += 1;
+                continue;
+            }
+            while reserved
+                .get(self.reserved)
+                .is_some_and(|&(_, re)| re <= start)
+            {
+                self.reserved += 1;
+            }
+            let end = match reserved.get(self.reserved) {
+                // 起点落在保留区域中，跳过这个保留区域
+                Some(&(rs, re)) if rs <= start => {
+                    self.cursor = re;
+                    continue;
+                }
+                Some(&(rs, _)) => rs.min(e),
+                None => e,
+            };
+            self.cursor = end;
+            // 向内对齐，跳过地址 0 所在的块
+            let mask = self.align - 1;
+            let start = start.checked_add(mask).map_or(u64::MAX, |s| s & !mask);
+            let start = start.max(self.align);
+            let end = end.min(usize::MAX as u64) & !mask;
+            if start < end {
+                return Some((start as usize, (end - start) as usize));
+            }
+        }
+    }
+}
+
+/// 按地址排序的互不相邻的 `[起始, 结束)`，最多 `M` 段。
+struct RangeSet<const M: usize> {
+    ranges: [(u64, u64); M],
+    len: usize,
+}
+
+impl<const M: usize> RangeSet<M> {
+    const fn new() -> Self {
+        Self {
+            ranges: [(0, 0); M],
+            len: 0,
+        }
+    }
+
+    #[inline]
+    fn as_slice(&self) -> &[(u64, u64)] {
+        &self.ranges[..self.len]
+    }
+
+    /// 加入 `[start, end)`，与重叠或相邻的段合并。
+    fn insert(&mut self, start: u64, end: u64) -> Result<(), MemmapError> {
+        if start >= end {
+            return Ok(());
+        }
+        let i = self.as_slice().partition_point(|&(_, e)| e < start);
+        let j = i + self.as_slice()[i..].partition_point(|&(s, _)| s <= end);
+        if i == j {
+            if self.len == M {
+                return Err(MemmapError::Full);
+            }
+            self.ranges.copy_within(i..self.len, i + 1);
+            self.ranges[i] = (start, end);
+            self.len += 1;
+        } else {
+            self.ranges[i] = (start.min(self.ranges[i].0), end.max(self.ranges[j - 1].1));
+            self.ranges.copy_within(j..self.len, i + 1);
+            self.len -= j - i - 1;
+        }
+        Ok(())
+    }
+}
+
+impl<const M: usize> fmt::Debug for RangeSet<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.as_slice().iter().map(|&(s, e)| s..e))
+            .finish()
+    }
+}
+
+#[inline]
+fn bytes<const L: usize>(buf: &[u8], pos: usize) -> Result<[u8; L], MemmapError> {
+    buf.get(pos..pos + L)
+        .and_then(|b| b.try_into().ok())
+        .ok_or(MemmapError::Malformed)
+}
+
+#[inline]
+fn le_u32(buf: &[u8], pos: usize) -> Result<u32, MemmapError> {
+    bytes(buf, pos).map(u32::from_le_bytes)
+}
+
+#[inline]
+fn le_u64(buf: &[u8], pos: usize) -> Result<u64, MemmapError> {
+    bytes(buf, pos).map(u64::from_le_bytes)
+}
+
+#[inline]
+fn be_u32(buf: &[u8], pos: usize) -> Result<u32, MemmapError> {
+    bytes(buf, pos).map(u32::from_be_bytes)
+}
+
+#[inline]
+fn be_u64(buf: &[u8], pos: usize) -> Result<u64, MemmapError> {
+    bytes(buf, pos).map(u64::from_be_bytes)
+}
+
+/// 从第 `cell` 个单元起读 `n` 个大端单元组成的数。
+#[inline]
+fn be_cells(buf: &[u8], cell: usize, n: usize) -> Result<u64, MemmapError> {
+    (cell..cell + n).try_fold(0, |val, i| Ok(val << 32 | be_u32(buf, i * 4)? as u64))
+}
+
+/// 从 `pos` 起以 0 结尾的字符串，不含结尾的 0。
+#[inline]
+fn cstr(buf: &[u8], pos: usize) -> Result<&[u8], MemmapError> {
+    let buf = buf.get(pos..).ok_or(MemmapError::Malformed)?;
+    let len = buf
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(MemmapError::Malformed)?;
+    Ok(&buf[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 拼接测试用的二进制数据。
+    struct Fixture {
+        buf: [u8; 1024],
+        len: usize,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            Self {
+                buf: [0; 1024],
+                len: 0,
+            }
+        }
+
+        fn bytes(&mut self, bytes: &[u8]) -> &mut Self {
+            self.buf[self.len..][..bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+            self
+        }
+
+        fn le32(&mut self, val: u32) -> &mut Self {
+            self.bytes(&val.to_le_bytes())
+        }
+
+        fn le64(&mut self, val: u64) -> &mut Self {
+            self.bytes(&val.to_le_bytes())
+        }
+
+        fn be32(&mut self, val: u32) -> &mut Self {
+            self.bytes(&val.to_be_bytes())
+        }
+
+        fn be64(&mut self, val: u64) -> &mut Self {
+            self.bytes(&val.to_be_bytes())
+        }
+
+        fn align(&mut self, align: usize) -> &mut Self {
+            self.len = self.len.next_multiple_of(align);
+            self
+        }
+
+        fn as_slice(&self) -> &[u8] {
+            &self.buf[..self.len]
+        }
+    }
+
+    const fn usable(start: u64, size: u64) -> MemoryRegion {
+        MemoryRegion {
+            start,
+            size,
+            kind: MemoryKind::Usable,
+        }
+    }
+
+    const fn reserved(start: u64, size: u64) -> MemoryRegion {
+        MemoryRegion {
+            start,
+            size,
+            kind: MemoryKind::Reserved,
+        }
+    }
+
+    #[test]
+    fn test_e820() {
+        let mut table = Fixture::new();
+        table.le64(0).le64(0x9_fc00).le32(1);
+        table.le64(0xf_0000).le64(0x1_0000).le32(2);
+        table.le64(0x10_0000).le64(0x7ee_0000).le32(1);
+        table.le64(0x7fe_0000).le64(0x2_0000).le32(3);
+        let e820 = E820::new(table.as_slice(), E820::ENTRY_SIZE).unwrap();
+        assert!(e820.eq([
+            usable(0, 0x9_fc00),
+            reserved(0xf_0000, 0x1_0000),
+            usable(0x10_0000, 0x7ee_0000),
+            reserved(0x7fe_0000, 0x2_0000),
+        ]));
+
+        // 扩展属性第 0 位为 0 的表项被忽略
+        let mut table = Fixture::new();
+        table.le64(0x10_0000).le64(0x1000).le32(1).le32(1);
+        table.le64(0x20_0000).le64(0x1000).le32(1).le32(0);
+        let e820 = E820::new(table.as_slice(), 24).unwrap();
+        assert!(e820.eq([usable(0x10_0000, 0x1000)]));
+
+        assert!(matches!(
+            E820::new(&table.as_slice()[1..], 24),
+            Err(MemmapError::Malformed)
+        ));
+        assert!(matches!(
+            E820::new(table.as_slice(), 16),
+            Err(MemmapError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn test_multiboot2() {
+        let mut info = Fixture::new();
+        info.le32(0).le32(0);
+        // 命令行标签，长度不是 8 的倍数
+        info.le32(1).le32(8 + 3).bytes(b"ab\0").align(8);
+        // 内存映射标签
+        info.le32(6).le32(16 + 2 * 24).le32(24).le32(0);
+        info.le64(0).le64(0x9_fc00).le32(1).le32(0);
+        info.le64(0x10_0000).le64(0x7ef_0000).le32(3).le32(0);
+        // 结束标签
+        info.le32(0).le32(8);
+        let total = info.len as u32;
+        info.buf[..4].copy_from_slice(&total.to_le_bytes());
+
+        let mb2 = Multiboot2::new(info.as_slice()).unwrap();
+        assert!(mb2.eq([usable(0, 0x9_fc00), reserved(0x10_0000, 0x7ef_0000)]));
+
+        // 没有内存映射标签
+        let mut info = Fixture::new();
+        info.le32(16).le32(0).le32(0).le32(8);
+        assert!(matches!(
+            Multiboot2::new(info.as_slice()),
+            Err(MemmapError::NotFound)
+        ));
+        // 标签超出启动信息
+        let mut info = Fixture::new();
+        info.le32(16).le32(0).le32(6).le32(64);
+        assert!(matches!(
+            Multiboot2::new(info.as_slice()),
+            Err(MemmapError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn test_uefi() {
+        // 固件报告的描述符比结构体长
+        const SIZE: usize = 48;
+        let mut map = Fixture::new();
+        for (ty, start, pages) in [
+            (7, 0x10_0000, 0x100),
+            (2, 0x20_0000, 0x10),
+            (4, 0x21_0000, 1),
+            (0, 0, 1),
+        ] {
+            map.le32(ty)
+                .le32(0)
+                .le64(start)
+                .le64(0)
+                .le64(pages)
+                .le64(0xf);
+            map.align(SIZE);
+        }
+        let uefi = Uefi::new(map.as_slice(), SIZE).unwrap();
+        assert!(uefi.eq([
+            usable(0x10_0000, 0x10_0000),
+            reserved(0x20_0000, 0x1_0000),
+            usable(0x21_0000, 0x1000),
+            reserved(0, 0x1000),
+        ]));
+        assert!(matches!(
+            Uefi::new(map.as_slice(), 32),
+            Err(MemmapError::Malformed)
+        ));
+    }
+
+    /// 拼接一棵设备树。
+    fn fdt(blob: &mut Fixture) {
+        const STRINGS: &[u8] = b"#address-cells\0#size-cells\0device_type\0reg\0";
+        const ADDRESS_CELLS: u32 = 0;
+        const SIZE_CELLS: u32 = 15;
+        const DEVICE_TYPE: u32 = 27;
+        const REG: u32 = 39;
+
+        let mut s = Fixture::new();
+        let begin = |s: &mut Fixture, name: &[u8]| {
+            s.be32(1).bytes(name).bytes(b"\0").align(4);
+        };
+        let prop = |s: &mut Fixture, name: u32, value: &[u8]| {
+            s.be32(3)
+                .be32(value.len() as u32)
+                .be32(name)
+                .bytes(value)
+                .align(4);
+        };
+        let end = |s: &mut Fixture| {
+            s.be32(2);
+        };
+        let mut reg = Fixture::new();
+
+        begin(&mut s, b"");
+        prop(&mut s, ADDRESS_CELLS, &2u32.to_be_bytes());
+        prop(&mut s, SIZE_CELLS, &2u32.to_be_bytes());
+        // 两段内存
+        begin(&mut s, b"memory@80000000");
+        prop(&mut s, DEVICE_TYPE, b"memory\0");
+        reg.be64(0x8000_0000).be64(0x800_0000);
+        reg.be64(0x1_0000_0000).be64(0x1000_0000);
+        prop(&mut s, REG, reg.as_slice());
+        end(&mut s);
+        // 名字不是 memory，`reg` 在 `device_type` 之前
+        begin(&mut s, b"ram");
+        s.be32(4);
+        prop(
+            &mut s,
+            REG,
+            Fixture::new().be64(0x2_0000_0000).be64(0x1000).as_slice(),
+        );
+        prop(&mut s, DEVICE_TYPE, b"memory\0");
+        end(&mut s);
+        // 不是内存的节点
+        begin(&mut s, b"uart@10000000");
+        prop(
+            &mut s,
+            REG,
+            Fixture::new().be64(0x1000_0000).be64(0x100).as_slice(),
+        );
+        end(&mut s);
+        begin(&mut s, b"reserved-memory");
+        prop(&mut s, ADDRESS_CELLS, &2u32.to_be_bytes());
+        prop(&mut s, SIZE_CELLS, &1u32.to_be_bytes());
+        begin(&mut s, b"sbi@80000000");
+        prop(
+            &mut s,
+            REG,
+            Fixture::new().be64(0x8000_0000).be32(0x8_0000).as_slice(),
+        );
+        end(&mut s);
+        end(&mut s);
+        end(&mut s);
+        s.be32(9);
+
+        // 头 40 字节，之后是内存保留块、结构块和字符串块
+        let off_rsvmap = 40;
+        let off_struct = off_rsvmap + 2 * 16;
+        let off_strings = off_struct + s.len;
+        let total = off_strings + STRINGS.len();
+        blob.be32(Fdt::MAGIC)
+            .be32(total as u32)
+            .be32(off_struct as u32)
+            .be32(off_strings as u32)
+            .be32(off_rsvmap as u32)
+            .be32(17)
+            .be32(16)
+            .be32(0)
+            .be32(STRINGS.len() as u32)
+            .be32(s.len as u32);
+        blob.be64(0x8700_0000).be64(0x1000).be64(0).be64(0);
+        blob.bytes(s.as_slice()).bytes(STRINGS);
+    }
+
+    #[test]
+    fn test_fdt() {
+        let mut blob = Fixture::new();
+        fdt(&mut blob);
+        let fdt = Fdt::new(blob.as_slice()).unwrap();
+        assert!(fdt.eq([
+            reserved(0x8700_0000, 0x1000),
+            usable(0x8000_0000, 0x800_0000),
+            usable(0x1_0000_0000, 0x1000_0000),
+            usable(0x2_0000_0000, 0x1000),
+            reserved(0x8000_0000, 0x8_0000),
+        ]));
+
+        // 魔数错误或者结构块被截断
+        let mut bad = Fixture::new();
+        bad.bytes(blob.as_slice());
+        bad.buf[0] = 0;
+        assert!(matches!(
+            Fdt::new(bad.as_slice()),
+            Err(MemmapError::Malformed)
+        ));
+        bad.buf[0] = 0xd0;
+        bad.buf[36..40].copy_from_slice(&8u32.to_be_bytes());
+        assert!(matches!(
+            Fdt::new(bad.as_slice()),
+            Err(MemmapError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn test_memory_map() {
+        let mut map = MemoryMap::<4>::new();
+        // 次序无关，保留优先，重叠和相邻的可用区域合并
+        map.extend([
+            reserved(0x20_3000, 0x1000),
+            usable(0x20_0000, 0x8000),
+            usable(0x20_6000, 0x2_a000),
+            usable(0, 0x9_fc00),
+            reserved(0x22_0000, 0x10_0000),
+        ])
+        .unwrap();
+        map.reserve(0x20_7800, 0x800).unwrap();
+        assert!(map.ranges(12).eq([
+            // 跳过第 0 页，末尾不足一页的部分丢弃
+            (0x1000, 0x9_e000),
+            (0x20_0000, 0x3000),
+            (0x20_4000, 0x3000),
+            (0x20_8000, 0x1_8000),
+        ]));
+        // 按更大的阶数对齐
+        assert!(
+            map.ranges(16)
+                .eq([(0x1_0000, 0x8_0000), (0x21_0000, 0x1_0000)])
+        );
+
+        // 容量不足
+        let mut map = MemoryMap::<1>::new();
+        map.add(usable(0x1000, 0x1000)).unwrap();
+        map.add(usable(0x2000, 0x1000)).unwrap();
+        assert_eq!(map.add(usable(0x4000, 0x1000)), Err(MemmapError::Full));
+        // 填上空隙后合并
+        map.add(usable(0x3000, 0x1000)).unwrap();
+        map.add(usable(0x4000, 0x1000)).unwrap();
+        assert!(map.ranges(12).eq([(0x1000, 0x4000)]));
+    }
+
+    #[test]
+    fn test_transfer() {
+        use crate::tests::fake_allocator;
+
+        let mut blob = Fixture::new();
+        fdt(&mut blob);
+        let mut map = MemoryMap::<8>::new();
+        map.extend(Fdt::new(blob.as_slice()).unwrap()).unwrap();
+        // 只保留第一段内存中的 1 MiB
+        map.reserve(0x8010_0000, 0x1_0000_0000).unwrap();
+        map.reserve(0x1_0000_0000, 0x2_0000_0000).unwrap();
+
+        let mut allocator = fake_allocator::<4, _>(0x8000_0000, 16, crate::NoopObserver);
+        let size = unsafe { map.transfer(&mut allocator) };
+        assert_eq!(size, 0x8_0000);
+        assert_eq!(allocator.capacity(), 0x8_0000);
+    }
+}
@@ -0,0 +1,452 @@
+use crate::{
+    AllocObserver, BuddyAllocator, BuddyCollection, LineStorage, OligarchyCollection, TransferError,
+};
+use core::{fmt, ptr::NonNull};
+
+/// 记录分配器管理的地址范围的表，最多容纳 `M` 段互不相邻的范围。
+///
+/// 作为观察者挂在分配器上：转移的范围加入表，夺走和取回的块移出表，相邻的范围合并。
+/// 转移与表中的范围重叠时 panic，此时内存块还没有存入行，分配器的状态不受影响。
+/// 表满时同样 panic。
+///
+/// 夺走或取出的块在表中某个范围的中间时，这个范围拆成两段，多占一项。
+/// 这时表满也会 panic，但块已经从行中取出，分配器的状态与表不再一致，不能继续使用。
+/// 会夺走或取出内存的分配器应当为每次可能的拆分预留表项，
+/// 参见 [`snatch`](BuddyAllocator::snatch)、[`reclaim_range`](BuddyAllocator::reclaim_range)
+/// 和 [`trim`](BuddyAllocator::trim)。
+///
+/// 挂上这个表的分配器可以用 [`contains`](BuddyAllocator::contains) 判断地址是否归分配器管理，
+/// 用 [`memory_map`](BuddyAllocator::memory_map) 列出空闲和已分配的范围。
+///
+/// [`absorb`](BuddyAllocator::absorb) 只把并入的空闲块通知观察者，
+/// 被并入的分配器中已分配的块不会加入表。
+pub struct RegionTable<const M: usize> {
+    /// 按地址排序的 `(起始, 结束)`。
+    regions: [(usize, usize); M],
+    len: usize,
+}
+
+impl<const M: usize> RegionTable<M> {
+    /// 空表。
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            regions: [(0, 0); M],
+            len: 0,
+        }
+    }
+
+    /// 表中范围的数量。
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// 表是否为空。
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// 按地址顺序迭代表中的范围，产生 `(地址, 长度)`。
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.regions[..self.len]
+            .iter()
+            .map(|&(start, end)| (start, end - start))
+    }
+
+    /// 判断 `ptr` 是否在表中的某个范围内。
+    pub fn contains(&self, ptr: usize) -> bool {
+        let i = self.regions[..self.len].partition_point(|&(_, end)| end <= ptr);
+        i < self.len && self.regions[i].0 <= ptr
+    }
+
+    /// 检查能否加入非空的范围 `[start, end)`，返回插入位置以及是否与前后的范围相邻。
+    fn locate(&self, start: usize, end: usize) -> Result<(usize, bool, bool), TransferError> {
+        let i = self.regions[..self.len].partition_point(|&(s, _)| s < start);
+        if (i > 0 && self.regions[i - 1].1 > start) || (i < self.len && self.regions[i].0 < end) {
+            return Err(TransferError::Overlap);
+        }
+        let merge_prev = i > 0 && self.regions[i - 1].1 == start;
+        let merge_next = i < self.len && self.regions[i].0 == end;
+        if !merge_prev && !merge_next && self.len == M {
+            return Err(TransferError::TableFull);
+        }
+        Ok((i, merge_prev, merge_next))
+    }
+
+    /// 加入范围 `[start, end)`。
+    fn insert(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        let (i, merge_prev, merge_next) = self
+            .locate(start, end)
+            .unwrap_or_else(|e| panic!("cannot transfer [{start:#x}, {end:#x}): {e}"));
+        match (merge_prev, merge_next) {
+            (true, true) => {
+                self.regions[i - 1].1 = self.regions[i].1;
+                self.remove_at(i);
+            }
+            (true, false) => self.regions[i - 1].1 = end,
+            (false, true) => self.regions[i].0 = start,
+            (false, false) => self.insert_at(i, (start, end)),
+        }
+    }
+
+    /// 移出范围 `[start, end)`，不在表中的部分忽略。
+    fn remove(&mut self, start: usize, end: usize) {
+        let mut i = self.regions[..self.len].partition_point(|&(_, e)| e <= start);
+        while i < self.len && self.regions[i].0 < end {
+            let (s, e) = self.regions[i];
+            if s < start && end < e {
+                // 从中间挖去，拆成两段
+                self.regions[i].1 = start;
+                self.insert_at(i + 1, (end, e));
+                break;
+            } else if s < start {
+                self.regions[i].1 = start;
+                i += 1;
+            } else if end < e {
+                self.regions[i].0 = end;
+                break;
+            } else {
+                self.remove_at(i);
+            }
+        }
+    }
+
+    fn insert_at(&mut self, i: usize, region: (usize, usize)) {
+        assert!(self.len < M, "{}", TransferError::TableFull);
+        self.regions.copy_within(i..self.len, i + 1);
+        self.regions[i] = region;
+        self.len += 1;
+    }
+
+    fn remove_at(&mut self, i: usize) {
+        self.regions.copy_within(i + 1..self.len, i);
+        self.len -= 1;
+    }
+}
+
+impl<const M: usize> Default for RegionTable<M> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const M: usize> fmt::Debug for RegionTable<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.regions[..self.len].iter().map(|&(s, e)| s..e))
+            .finish()
+    }
+}
+
+impl<const M: usize> AllocObserver for RegionTable<M> {
+    #[inline]
+    fn on_transfer(&mut self, ptr: usize, size: usize) {
+        self.insert(ptr, ptr + size)
+    }
+
+    #[inline]
+    fn on_snatch(
+        &mut self,
+        _align_order: usize,
+        _size: usize,
+        _layer: usize,
+        result: Option<(usize, usize)>,
+    ) {
+        if let Some((ptr, size)) = result {
+            self.remove(ptr, ptr + size)
+        }
+    }
+
+    #[inline]
+    fn on_extract(&mut self, ptr: usize, size: usize) {
+        self.remove(ptr, ptr + size)
+    }
+}
+
+impl<const N: usize, O: OligarchyCollection, B: BuddyCollection, const M: usize, S: LineStorage<B>>
+    BuddyAllocator<N, O, B, S, RegionTable<M>>
+{
+    /// 判断 `ptr` 是否归分配器管理，即转移给分配器后没有被夺走或取回。
+    #[inline]
+    pub fn contains<T>(&self, ptr: NonNull<T>) -> bool {
+        self.observer.contains(ptr.as_ptr() as usize)
+    }
+
+    /// 检查后将 `ptr` 指向的长度为 `size` 的内存块转移给分配器。
+    ///
+    /// 与 [`transfer`](BuddyAllocator::transfer) 相同，但先检查地址和长度对齐到最小阶数、
+    /// 范围不超出地址空间、不与已托管的内存重叠、表中还有位置，
+    /// 并且范围分解出的每个块都能放入对应的行（参见 [`BuddyLine::addressable`](crate::BuddyLine::addressable)）。
+    /// 检查不通过时返回错误，分配器不变。
+    ///
+    /// # Safety
+    ///
+    /// 调用者需要保证这个内存块没有被其他任何对象引用。
+    pub unsafe fn try_transfer<T>(
+        &mut self,
+        ptr: NonNull<T>,
+        size: usize,
+    ) -> Result<(), TransferError> {
+        let start = ptr.as_ptr() as usize;
+        self.check_transfer(start, size)?;
+        if size != 0 {
+            self.observer.locate(start, start + size)?;
+        }
+        unsafe { self.transfer(ptr, size) };
+        Ok(())
+    }
+
+    /// 按地址顺序列出分配器管理的内存，以 `(地址, 长度, 是否空闲)` 传给 `f`。
+    ///
+    /// 相邻的同类范围合并成一段。逐页查询所有的行，只适合调试。
+    ///
+    /// # Notice
+    ///
+    /// 所有的行都需要实现 [`BuddyLine::contains`](crate::BuddyLine::contains)。
+    pub fn memory_map(&self, mut f: impl FnMut(NonNull<u8>, usize, bool)) {
+        let max_order = self.max_order();
+        let free = |ptr: usize| {
+            (self.min_order..=max_order).find(|&o| {
+                if o == max_order {
+                    self.oligarchy.contains(ptr >> o)
+                } else {
+                    self.buddies.contains(o - self.min_order, ptr >> o)
+                }
+            })
+        };
+        let mut emit =
+            |(ptr, size, free)| f(unsafe { NonNull::new_unchecked(ptr as _) }, size, free);
+
+        let mut run: Option<(usize, usize, bool)> = None;
+        for (start, size) in self.observer.iter() {
+            let end = start + size;
+            let mut ptr = start;
+            while ptr < end {
+                let (next, is_free) = match free(ptr) {
+                    Some(o) => ((((ptr >> o) + 1) << o).min(end), true),
+                    None => ((ptr + (1 << self.min_order)).min(end), false),
+                };
+                match &mut run {
+                    Some((s, len, free)) if *s + *len == ptr && *free == is_free => {
+                        *len = next - *s
+                    }
+                    _ => {
+                        if let Some(run) = run.take() {
+                            emit(run);
+                        }
+                        run = Some((ptr, next - ptr, is_free));
+                    }
+                }
+                ptr = next;
+            }
+        }
+        if let Some(run) = run {
+            emit(run);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_merge() {
+        let mut table = RegionTable::<4>::new();
+        table.on_transfer(0x3000, 0x1000);
+        table.on_transfer(0x1000, 0x1000);
+        table.on_transfer(0x8000, 0x1000);
+        assert_eq!(table.len(), 3);
+        // 填上空隙，与两侧合并
+        table.on_transfer(0x2000, 0x1000);
+        assert!(table.iter().eq([(0x1000, 0x3000), (0x8000, 0x1000)]));
+        assert!(table.contains(0x1000));
+        assert!(table.contains(0x3fff));
+        assert!(!table.contains(0x4000));
+        assert!(!table.contains(0xfff));
+    }
+
+    #[test]
+    fn test_remove_split() {
+        let mut table = RegionTable::<4>::new();
+        table.on_transfer(0x1000, 0x8000);
+        // 从中间挖去
+        table.on_extract(0x3000, 0x1000);
+        assert!(table.iter().eq([(0x1000, 0x2000), (0x4000, 0x5000)]));
+        // 跨越两段
+        table.on_snatch(0, 0, 0, Some((0x2000, 0x3000)));
+        assert!(table.iter().eq([(0x1000, 0x1000), (0x5000, 0x4000)]));
+        // 失败的夺取不改变表
+        table.on_snatch(0, 0, 0, None);
+        assert_eq!(table.len(), 2);
+        // 整段移出
+        table.on_extract(0x1000, 0x1000);
+        assert!(table.iter().eq([(0x5000, 0x4000)]));
+        // 不在表中的部分忽略
+        table.on_extract(0, 0x6000);
+        assert!(table.iter().eq([(0x6000, 0x3000)]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_remove_split_full() {
+        let mut table = RegionTable::<1>::new();
+        table.on_transfer(0x1000, 0x8000);
+        // 拆成两段需要多占一项
+        table.on_extract(0x3000, 0x1000);
+    }
+
+    #[test]
+    fn test_allocator_memory_map() {
+        use crate::{
+            UsizeBuddy,
+            tests::{FAKE_BASE, fake_allocator},
+        };
+        use core::num::NonZeroUsize;
+
+        const PAGE: usize = 4096;
+        let base = FAKE_BASE;
+        let page = |i: usize| NonNull::new((base + i * PAGE) as *mut u8).unwrap();
+        let map = |allocator: &BuddyAllocator<
+            2,
+            UsizeBuddy,
+            UsizeBuddy,
+            [UsizeBuddy; 2],
+            RegionTable<4>,
+        >| {
+            let mut list = [(0, 0, false); 8];
+            let mut len = 0;
+            allocator.memory_map(|ptr, size, free| {
+                list[len] = ((ptr.as_ptr() as usize - base) / PAGE, size / PAGE, free);
+                len += 1;
+            });
+            (list, len)
+        };
+
+        // 寡头是 4 页
+        let mut allocator = fake_allocator::<2, _>(base, 12, RegionTable::<4>::new());
+        unsafe {
+            allocator.transfer(page(0), 8 * PAGE);
+            allocator.transfer(page(12), 4 * PAGE);
+        }
+        let size = NonZeroUsize::new(PAGE).unwrap();
+        allocator.allocate::<u8>(0, size).unwrap();
+        let (list, len) = map(&allocator);
+        assert_eq!(list[..len], [(0, 1, false), (1, 7, true), (12, 4, true)]);
+        assert!(allocator.contains(page(7)));
+        assert!(!allocator.contains(page(8)));
+
+        // 夺走的寡头和取回的页移出表
+        let size = NonZeroUsize::new(4 * PAGE).unwrap();
+        let (p, _) = allocator.snatch::<u8>(0, size).unwrap();
+        assert_eq!(p, page(4));
+        allocator.reclaim_range(page(13), PAGE, |_, _| {});
+        assert!(!allocator.contains(page(4)));
+        assert!(!allocator.contains(page(13)));
+        let (list, len) = map(&allocator);
+        assert_eq!(
+            list[..len],
+            [(0, 1, false), (1, 3, true), (12, 1, true), (14, 2, true)]
+        );
+        assert_eq!(allocator.observer().len(), 3);
+    }
+
+    #[test]
+    fn test_try_transfer() {
+        use crate::tests::{FAKE_BASE, fake_allocator};
+
+        let base = FAKE_BASE;
+        let ptr = |addr: usize| NonNull::new(addr as *mut u8).unwrap();
+        // 寡头是 4 页，每行容纳 64 个块
+        let mut allocator = fake_allocator::<2, _>(base, 12, RegionTable::<2>::new());
+
+        let mut transfer =
+            |addr: usize, size: usize| unsafe { allocator.try_transfer(ptr(addr), size) };
+        assert_eq!(
+            transfer(base + 0x800, 0x1000),
+            Err(TransferError::Misaligned)
+        );
+        assert_eq!(transfer(base, 0x1800), Err(TransferError::Misaligned));
+        assert_eq!(
+            transfer(usize::MAX & !0xfff, 0x2000),
+            Err(TransferError::Overflow)
+        );
+        // 低于基址，或者超出寡头行的容量
+        assert_eq!(
+            transfer(base - 0x1000, 0x1000),
+            Err(TransferError::Unaddressable { layer: 0 })
+        );
+        assert_eq!(
+            transfer(base + (64 << 14), 0x4000),
+            Err(TransferError::Unaddressable { layer: 2 })
+        );
+
+        assert_eq!(transfer(base, 0x8000), Ok(()));
+        assert_eq!(transfer(base + 0x4000, 0x1000), Err(TransferError::Overlap));
+        assert_eq!(
+            transfer(base - 0x4000, 0x8000),
+            Err(TransferError::Unaddressable { layer: 2 })
+        );
+        assert_eq!(transfer(base + 0x10000, 0x4000), Ok(()));
+        assert_eq!(
+            transfer(base + 0x20000, 0x4000),
+            Err(TransferError::TableFull)
+        );
+        // 与已有的范围相邻时合并，不占用新的位置
+        assert_eq!(transfer(base + 0x8000, 0x8000), Ok(()));
+        assert_eq!(transfer(base + 0x20000, 0x4000), Ok(()));
+        assert_eq!(allocator.capacity(), 0x18000);
+        assert_eq!(allocator.free(), 0x18000);
+    }
+
+    #[test]
+    #[should_panic(expected = "overlaps managed memory")]
+    fn test_overlap() {
+        let mut table = RegionTable::<4>::new();
+        table.on_transfer(0x1000, 0x2000);
+        table.on_transfer(0x2000, 0x2000);
+    }
+
+    #[test]
+    #[should_panic(expected = "region table is full")]
+    fn test_full() {
+        let mut table = RegionTable::<2>::new();
+        table.on_transfer(0x1000, 0x1000);
+        table.on_transfer(0x3000, 0x1000);
+        table.on_transfer(0x5000, 0x1000);
+    }
+
+    #[test]
+    fn test_absorb_overlap() {
+        extern crate std;
+        use crate::tests::{FAKE_BASE, fake_allocator};
+        use core::num::NonZeroUsize;
+        use std::panic::{AssertUnwindSafe, catch_unwind};
+
+        const PAGE: usize = 4096;
+        let page = |i: usize| NonNull::new((FAKE_BASE + i * PAGE) as *mut u8).unwrap();
+        // 寡头是 4 页
+        let mut allocator = fake_allocator::<2, _>(FAKE_BASE, 12, RegionTable::<4>::new());
+        unsafe { allocator.transfer(page(0), 4 * PAGE) };
+        let mut other = fake_allocator::<2, _>(FAKE_BASE, 12, RegionTable::<4>::new());
+        unsafe { other.transfer(page(2), 4 * PAGE) };
+
+        // 观察者在块存入行之前 panic
+        let absorb = catch_unwind(AssertUnwindSafe(|| allocator.absorb(other)));
+        assert!(absorb.is_err());
+        assert_eq!(allocator.capacity(), 4 * PAGE);
+        assert_eq!(allocator.free(), 4 * PAGE);
+        // 行中只有原来的寡头
+        let size = NonZeroUsize::new(4 * PAGE).unwrap();
+        assert_eq!(allocator.allocate::<u8>(0, size).unwrap().0, page(0));
+        let size = NonZeroUsize::new(PAGE).unwrap();
+        assert!(allocator.allocate::<u8>(0, size).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{BuddyCollection, DebugLines, LineStorage, TakePolicy, init_lines};
+use core::fmt;
+
+/// 按层数分成两段的伙伴行存储。
+///
+/// 低 `K` 层的行使用 `L` 实现，其上的 `M` 层使用 `H` 实现。
+/// 例如低阶的行块多而密集，适合用位图；高阶的行块少而稀疏，适合用链表或树。
+///
+/// 两段分别保存在各自类型的数组中，每一层只占用它使用的实现的空间。
+/// 分配器按层号访问时比较层号与 `K`，再静态分派到对应段的实现。
+/// 使用这种存储的分配器参见 [`SplitBuddyAllocator`](crate::SplitBuddyAllocator)。
+/// 使用 `H` 的行在初始化时检查阶数是否足够存放 `H` 的元数据。
+pub struct SplitLines<L, H, const K: usize, const M: usize> {
+    low: [L; K],
+    high: [H; M],
+}
+
+impl<L, H, const K: usize, const M: usize> LineStorage<L> for SplitLines<L, H, K, M>
+where
+    L: BuddyCollection,
+    H: BuddyCollection,
+{
+    const EMPTY: Self = Self {
+        low: [L::EMPTY; K],
+        high: [H::EMPTY; M],
+    };
+
+    #[inline]
+    fn layers(&self) -> usize {
+        K + M
+    }
+
+    #[inline]
+    fn init(&mut self, min_order: usize, base: usize) {
+        if M > 0 {
+            let order = min_order + K;
+            assert!(
+                H::INTRUSIVE_META_SIZE <= 1 << order,
+                "order {order} is too small for the intrusive meta of high lines"
+            );
+        }
+        init_lines(&mut self.low, min_order, base);
+        init_lines(&mut self.high, min_order + K, base);
+    }
+
+    #[inline]
+    fn set_policy(&mut self, policy: TakePolicy) {
+        self.low.set_policy(policy);
+        self.high.set_policy(policy);
+    }
+
+    #[inline]
+    fn take_any(&mut self, layer: usize, align_order: usize) -> Option<usize> {
+        if layer < K {
+            self.low.take_any(layer, align_order)
+        } else {
+            self.high.take_any(layer - K, align_order)
+        }
+    }
+
+    #[inline]
+    fn put(&mut self, layer: usize, idx: usize) -> Option<usize> {
+        if layer < K {
+            self.low.put(layer, idx)
+        } else {
+            self.high.put(layer - K, idx)
+        }
+    }
+
+    #[inline]
+    fn take(&mut self, layer: usize, idx: usize) -> bool {
+        if layer < K {
+            self.low.take(layer, idx)
+        } else {
+            self.high.take(layer - K, idx)
+        }
+    }
+
+    #[inline]
+    fn contains(&self, layer: usize, idx: usize) -> bool {
+        if layer < K {
+            self.low.contains(layer, idx)
+        } else {
+            self.high.contains(layer - K, idx)
+        }
+    }
+
+    #[inline]
+    fn addressable(&self, layer: usize, idx: usize) -> bool {
+        if layer < K {
+            self.low.addressable(layer, idx)
+        } else {
+            self.high.addressable(layer - K, idx)
+        }
+    }
+}
+
+impl<L: fmt::Debug, H: fmt::Debug, const K: usize, const M: usize> DebugLines
+    for SplitLines<L, H, K, M>
+{
+    #[inline]
+    fn fmt_line(&self, layer: usize, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if layer < K {
+            self.low.fmt_line(layer, f)
+        } else {
+            self.high.fmt_line(layer - K, f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AvlBuddy, LinkedListBuddy, SplitBuddyAllocator, UsizeBuddy};
+    use core::{mem::size_of, num::NonZeroUsize, ptr::NonNull};
+
+    #[test]
+    fn test_layers_select_line() {
+        type Lines = SplitLines<UsizeBuddy, LinkedListBuddy, 2, 3>;
+        // 每一层只占用选定实现的空间
+        assert_eq!(
+            size_of::<Lines>(),
+            2 * size_of::<UsizeBuddy>() + 3 * size_of::<LinkedListBuddy>()
+        );
+
+        let mut lines = Lines::EMPTY;
+        lines.init(12, 0);
+        assert_eq!(lines.layers(), 5);
+        // 低两层是位图，从基序号开始编址；高层是链表，不能容纳地址为 0 的块
+        assert!(lines.addressable(1, 0));
+        assert!(!lines.addressable(2, 0));
+        assert_eq!(lines.put(0, 3), None);
+        assert!(lines.contains(0, 3));
+        assert!(!lines.contains(2, 3));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_high_meta_too_large() {
+        let mut lines = SplitLines::<UsizeBuddy, AvlBuddy, 2, 1>::EMPTY;
+        lines.init(1, 0);
+    }
+
+    #[test]
+    fn test_allocator_with_split_lines() {
+        #[repr(C, align(65536))]
+        struct Block([u8; 65536]);
+        static mut BLOCK: Block = Block([0; 65536]);
+
+        // 阶数 12、13 用位图，更高的阶数用链表
+        let mut allocator =
+            SplitBuddyAllocator::<UsizeBuddy, UsizeBuddy, LinkedListBuddy, 2, 6>::new();
+
+        let ptr = NonNull::new(core::ptr::addr_of_mut!(BLOCK).cast::<u8>()).unwrap();
+        let len = 65536;
+        allocator.init(12, ptr);
+        unsafe { allocator.transfer(ptr, len) };
+
+        // 分配所有页，再全部释放
+        let size = NonZeroUsize::new(4096).unwrap();
+        let mut pages = [NonNull::<u8>::dangling(); 16];
+        for p in pages.iter_mut() {
+            *p = allocator.allocate(0, size).unwrap().0;
+        }
+        assert_eq!(allocator.free(), 0);
+        assert!(allocator.allocate::<u8>(0, size).is_err());
+        for p in pages
+            .iter()
+            .step_by(2)
+            .chain(pages.iter().skip(1).step_by(2))
+        {
+            allocator.deallocate(*p, 4096);
+        }
+        assert_eq!(allocator.free(), len);
+
+        // 释放后重新合并成一个整块
+        let size = NonZeroUsize::new(len).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        assert_eq!((p, s), (ptr, len));
+    }
+}
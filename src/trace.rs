@@ -0,0 +1,521 @@
+use crate::AllocObserver;
+use core::fmt;
+
+/// 分配器操作的记录。
+///
+/// 地址都是绝对地址。编码时相对初始化记录中的基址保存，长度和阶数用变长整数保存。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TraceRecord {
+    /// 初始化。
+    Init {
+        /// 最小阶数。
+        min_order: usize,
+        /// 基址。
+        base: usize,
+        /// 伙伴行的层数。
+        layers: usize,
+    },
+    /// 转移。
+    Transfer {
+        /// 地址。
+        ptr: usize,
+        /// 长度。
+        size: usize,
+    },
+    /// 分配。
+    Allocate {
+        /// 对齐阶数。
+        align_order: usize,
+        /// 请求的长度。
+        size: usize,
+        /// 分配到的 `(地址, 长度)`。
+        result: Option<(usize, usize)>,
+    },
+    /// 夺走。
+    Snatch {
+        /// 对齐阶数。
+        align_order: usize,
+        /// 请求的长度。
+        size: usize,
+        /// 夺走的 `(地址, 长度)`。
+        result: Option<(usize, usize)>,
+    },
+    /// 回收。
+    Deallocate {
+        /// 地址。
+        ptr: usize,
+        /// 长度。
+        size: usize,
+    },
+    /// 取回指定范围。
+    Reclaim {
+        /// 地址。
+        ptr: usize,
+        /// 长度。
+        size: usize,
+        /// 取回的字节数。
+        reclaimed: usize,
+    },
+}
+
+/// 操作记录格式错误。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TraceError;
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("malformed trace")
+    }
+}
+
+/// 记录流开头的魔数和版本。
+const MAGIC: [u8; 4] = *b"BDT\x01";
+
+const INIT: u8 = 0;
+const TRANSFER: u8 = 1;
+const ALLOCATE: u8 = 2;
+const ALLOCATE_FAILED: u8 = 3;
+const SNATCH: u8 = 4;
+const SNATCH_FAILED: u8 = 5;
+const DEALLOCATE: u8 = 6;
+const RECLAIM: u8 = 7;
+
+impl TraceRecord {
+    /// 一条记录编码后的最大长度。
+    pub const MAX_LEN: usize = 1 + 4 * 10;
+
+    /// 编码到 `buf`，返回编码的长度。地址相对 `base` 保存。
+    pub fn encode(&self, base: usize, buf: &mut [u8; Self::MAX_LEN]) -> usize {
+        let mut w = Writer { buf, len: 1 };
+        let addr = |ptr: usize| zigzag(ptr.wrapping_sub(base) as isize);
+        let tag = match *self {
+            Self::Init {
+                min_order,
+                base,
+                layers,
+            } => {
+                w.varint(min_order);
+                w.varint(base);
+                w.varint(layers);
+                INIT
+            }
+            Self::Transfer { ptr, size } => {
+                w.varint(addr(ptr));
+                w.varint(size);
+                TRANSFER
+            }
+            Self::Deallocate { ptr, size } => {
+                w.varint(addr(ptr));
+                w.varint(size);
+                DEALLOCATE
+            }
+            Self::Reclaim {
+                ptr,
+                size,
+                reclaimed,
+            } => {
+                w.varint(addr(ptr));
+                w.varint(size);
+                w.varint(reclaimed);
+                RECLAIM
+            }
+            Self::Allocate {
+                align_order,
+                size,
+                result,
+            }
+            | Self::Snatch {
+                align_order,
+                size,
+                result,
+            } => {
+                let snatch = matches!(self, Self::Snatch { .. });
+                w.varint(align_order);
+                w.varint(size);
+                match result {
+                    Some((ptr, len)) => {
+                        w.varint(addr(ptr));
+                        w.varint(len);
+                        if snatch { SNATCH } else { ALLOCATE }
+                    }
+                    None if snatch => SNATCH_FAILED,
+                    None => ALLOCATE_FAILED,
+                }
+            }
+        };
+        w.buf[0] = tag;
+        w.len
+    }
+
+    /// 从 `bytes` 开头解码一条记录，返回记录和它的长度。地址相对 `base` 恢复。
+    pub fn decode(base: usize, bytes: &[u8]) -> Result<(Self, usize), TraceError> {
+        let (&tag, _) = bytes.split_first().ok_or(TraceError)?;
+        let mut r = Reader { bytes, pos: 1 };
+        let addr = |v: usize| base.wrapping_add(unzigzag(v) as usize);
+        let record = match tag {
+            INIT => Self::Init {
+                min_order: r.varint()?,
+                base: r.varint()?,
+                layers: r.varint()?,
+            },
+            TRANSFER => Self::Transfer {
+                ptr: addr(r.varint()?),
+                size: r.varint()?,
+            },
+            DEALLOCATE => Self::Deallocate {
+                ptr: addr(r.varint()?),
+                size: r.varint()?,
+            },
+            RECLAIM => Self::Reclaim {
+                ptr: addr(r.varint()?),
+                size: r.varint()?,
+                reclaimed: r.varint()?,
+            },
+            ALLOCATE..=SNATCH_FAILED => {
+                let align_order = r.varint()?;
+                let size = r.varint()?;
+                let result = if tag == ALLOCATE || tag == SNATCH {
+                    Some((addr(r.varint()?), r.varint()?))
+                } else {
+                    None
+                };
+                if tag <= ALLOCATE_FAILED {
+                    Self::Allocate {
+                        align_order,
+                        size,
+                        result,
+                    }
+                } else {
+                    Self::Snatch {
+                        align_order,
+                        size,
+                        result,
+                    }
+                }
+            }
+            _ => Err(TraceError)?,
+        };
+        Ok((record, r.pos))
+    }
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8; TraceRecord::MAX_LEN],
+    len: usize,
+}
+
+impl Writer<'_> {
+    /// LEB128 编码。
+    fn varint(&mut self, mut val: usize) {
+        loop {
+            let byte = (val & 0x7f) as u8;
+            val >>= 7;
+            if val == 0 {
+                self.buf[self.len] = byte;
+                self.len += 1;
+                break;
+            }
+            self.buf[self.len] = byte | 0x80;
+            self.len += 1;
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    /// LEB128 解码。
+    fn varint(&mut self) -> Result<usize, TraceError> {
+        let mut val = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = *self.bytes.get(self.pos).ok_or(TraceError)?;
+            self.pos += 1;
+            val |= ((byte & 0x7f) as usize)
+                .checked_shl(shift)
+                .ok_or(TraceError)?;
+            if byte & 0x80 == 0 {
+                return Ok(val);
+            }
+        }
+        Err(TraceError)
+    }
+}
+
+#[inline]
+const fn zigzag(val: isize) -> usize {
+    ((val << 1) ^ (val >> (isize::BITS - 1))) as usize
+}
+
+#[inline]
+const fn unzigzag(val: usize) -> isize {
+    ((val >> 1) as isize) ^ -((val & 1) as isize)
+}
+
+/// 记录的输出。
+pub trait TraceSink {
+    /// 写入一段编码后的记录。
+    fn write(&mut self, bytes: &[u8]);
+}
+
+#[cfg(feature = "alloc")]
+impl TraceSink for alloc::vec::Vec<u8> {
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes)
+    }
+}
+
+/// 把分配器的操作编码成紧凑的二进制记录的观察者。
+///
+/// 输出以魔数开头，随后是逐条编码的 [`TraceRecord`]，可以用 [`TraceReader`] 读回。
+pub struct TraceRecorder<S: TraceSink> {
+    sink: S,
+    base: usize,
+}
+
+impl<S: TraceSink> TraceRecorder<S> {
+    /// 创建观察者，立即向 `sink` 写入魔数。
+    pub fn new(mut sink: S) -> Self {
+        sink.write(&MAGIC);
+        Self { sink, base: 0 }
+    }
+
+    /// 返回输出。
+    #[inline]
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// 取出输出。
+    #[inline]
+    pub fn into_sink(self) -> S {
+        self.sink
+    }
+
+    fn record(&mut self, record: TraceRecord) {
+        let mut buf = [0; TraceRecord::MAX_LEN];
+        let len = record.encode(self.base, &mut buf);
+        self.sink.write(&buf[..len]);
+    }
+}
+
+impl<S: TraceSink> AllocObserver for TraceRecorder<S> {
+    fn on_init(&mut self, min_order: usize, base: usize, layers: usize) {
+        self.base = 0;
+        self.record(TraceRecord::Init {
+            min_order,
+            base,
+            layers,
+        });
+        self.base = base;
+    }
+
+    fn on_transfer(&mut self, ptr: usize, size: usize) {
+        self.record(TraceRecord::Transfer { ptr, size })
+    }
+
+    fn on_allocate(
+        &mut self,
+        align_order: usize,
+        size: usize,
+        _layer: usize,
+        result: Option<(usize, usize)>,
+    ) {
+        self.record(TraceRecord::Allocate {
+            align_order,
+            size,
+            result,
+        })
+    }
+
+    fn on_snatch(
+        &mut self,
+        align_order: usize,
+        size: usize,
+        _layer: usize,
+        result: Option<(usize, usize)>,
+    ) {
+        self.record(TraceRecord::Snatch {
+            align_order,
+            size,
+            result,
+        })
+    }
+
+    fn on_deallocate(&mut self, ptr: usize, size: usize) {
+        self.record(TraceRecord::Deallocate { ptr, size })
+    }
+
+    fn on_reclaim(&mut self, ptr: usize, size: usize, reclaimed: usize) {
+        self.record(TraceRecord::Reclaim {
+            ptr,
+            size,
+            reclaimed,
+        })
+    }
+}
+
+/// 从 [`TraceRecorder`] 的输出中逐条读出记录。
+pub struct TraceReader<'a> {
+    bytes: &'a [u8],
+    base: usize,
+}
+
+impl<'a> TraceReader<'a> {
+    /// 检查魔数，创建读取器。
+    pub fn new(bytes: &'a [u8]) -> Result<Self, TraceError> {
+        match bytes.strip_prefix(&MAGIC) {
+            Some(bytes) => Ok(Self { bytes, base: 0 }),
+            None => Err(TraceError),
+        }
+    }
+}
+
+impl Iterator for TraceReader<'_> {
+    type Item = Result<TraceRecord, TraceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        match TraceRecord::decode(self.base, self.bytes) {
+            Ok((record, len)) => {
+                self.bytes = &self.bytes[len..];
+                if let TraceRecord::Init { base, .. } = record {
+                    self.base = base;
+                }
+                Some(Ok(record))
+            }
+            Err(e) => {
+                // 格式错误之后的内容无法解析
+                self.bytes = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{FAKE_BASE, fake_allocator};
+    use core::{num::NonZeroUsize, ptr::NonNull};
+
+    /// 固定容量的输出。
+    struct Buf {
+        bytes: [u8; 1024],
+        len: usize,
+    }
+
+    impl TraceSink for Buf {
+        fn write(&mut self, bytes: &[u8]) {
+            self.bytes[self.len..][..bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let base = 0x8000_0000;
+        let records = [
+            TraceRecord::Init {
+                min_order: 12,
+                base,
+                layers: 16,
+            },
+            TraceRecord::Transfer {
+                ptr: base - 0x1000,
+                size: 0x10_0000,
+            },
+            TraceRecord::Allocate {
+                align_order: 21,
+                size: 3,
+                result: Some((base + 0x20_0000, 0x1000)),
+            },
+            TraceRecord::Allocate {
+                align_order: 0,
+                size: usize::MAX,
+                result: None,
+            },
+            TraceRecord::Snatch {
+                align_order: 12,
+                size: 0x3000,
+                result: Some((usize::MAX & !0xfff, 0x3000)),
+            },
+            TraceRecord::Snatch {
+                align_order: 12,
+                size: 0x3000,
+                result: None,
+            },
+            TraceRecord::Deallocate { ptr: 0, size: 0 },
+            TraceRecord::Reclaim {
+                ptr: base + 0x4000,
+                size: 0x8000,
+                reclaimed: 0x3000,
+            },
+        ];
+        for record in records {
+            let mut buf = [0; TraceRecord::MAX_LEN];
+            let len = record.encode(base, &mut buf);
+            assert_eq!(TraceRecord::decode(base, &buf[..len]), Ok((record, len)));
+            // 截断的记录无法解码
+            assert_eq!(TraceRecord::decode(base, &buf[..len - 1]), Err(TraceError));
+        }
+        assert_eq!(TraceRecord::decode(0, &[0xff]), Err(TraceError));
+    }
+
+    #[test]
+    fn test_record_allocator() {
+        let base = FAKE_BASE;
+        let mut allocator = fake_allocator::<4, _>(
+            base,
+            12,
+            TraceRecorder::new(Buf {
+                bytes: [0; 1024],
+                len: 0,
+            }),
+        );
+        let ptr = NonNull::new(base as *mut u8).unwrap();
+        unsafe { allocator.transfer(ptr, 0x4_0000) };
+        let size = NonZeroUsize::new(0x1800).unwrap();
+        let (p, s) = allocator.allocate::<u8>(0, size).unwrap();
+        let huge = NonZeroUsize::new(0x10_0000).unwrap();
+        assert!(allocator.snatch::<u8>(0, huge).is_err());
+        allocator.deallocate(p, s);
+
+        let sink = allocator.observer().sink();
+        let records = TraceReader::new(&sink.bytes[..sink.len])
+            .unwrap()
+            .map(Result::unwrap);
+        let expect = [
+            TraceRecord::Init {
+                min_order: 12,
+                base,
+                layers: 4,
+            },
+            TraceRecord::Transfer {
+                ptr: base,
+                size: 0x4_0000,
+            },
+            TraceRecord::Allocate {
+                align_order: 0,
+                size: 0x1800,
+                result: Some((base, 0x2000)),
+            },
+            TraceRecord::Snatch {
+                align_order: 0,
+                size: 0x10_0000,
+                result: None,
+            },
+            TraceRecord::Deallocate {
+                ptr: base,
+                size: 0x2000,
+            },
+        ];
+        assert!(records.eq(expect));
+        assert!(TraceReader::new(b"BDT").is_err());
+    }
+}
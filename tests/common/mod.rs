@@ -0,0 +1,441 @@
+//! 差分测试的公共部分。
+//!
+//! 把同一串操作同时施加到各种 `BuddyAllocator<N, O, B>` 组合和一个参照模型上，
+//! 检查空闲容量、分配到的地址范围和不重叠。
+//! 模糊测试和确定性的属性测试共用这些代码。
+
+#![allow(dead_code)]
+
+use customizable_buddy::{
+    AvlBuddy, BTreeBuddy, BuddyAllocator, BuddyCollection, ExtentOligarchy, LinkedListBuddy,
+    OligarchyCollection, TakePolicy, UsizeBuddy,
+};
+use std::{
+    alloc::{Layout, alloc_zeroed, dealloc},
+    boxed::Box,
+    num::NonZeroUsize,
+    ptr::NonNull,
+    vec::Vec,
+};
+
+/// 最小阶数。
+pub const MIN_ORDER: usize = 12;
+/// 伙伴行的层数。寡头是 8 页。
+pub const LAYERS: usize = 3;
+/// 测试区域的页数。位图行最多容纳 64 个块。
+pub const PAGES: usize = 64;
+
+const PAGE: usize = 1 << MIN_ORDER;
+const REGION: usize = PAGES * PAGE;
+
+/// 一次操作。
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// 分配 `pages` 页，对齐到 `align_order`。
+    Allocate { align_order: usize, pages: usize },
+    /// 夺走 `pages` 页，对齐到 `align_order`。
+    Snatch { align_order: usize, pages: usize },
+    /// 回收第 `slot` 个持有的块。`keep` 不为 0 时只回收前面的部分，保留最后 `keep` 页。
+    Deallocate { slot: usize, keep: usize },
+    /// 转移第 `slot` 个还没有托管的内存段。
+    Transfer { slot: usize },
+    /// 取回从第 `page` 页开始的 `pages` 页。
+    Reclaim { page: usize, pages: usize },
+}
+
+impl Op {
+    /// 从 3 个字节解码一个操作。
+    pub fn decode(bytes: [u8; 3]) -> Self {
+        const ALIGNS: [usize; 7] = [0, 12, 12, 13, 14, 15, 17];
+        let [kind, a, b] = bytes.map(|b| b as usize);
+        match kind % 8 {
+            0..=2 => Self::Allocate {
+                align_order: ALIGNS[a % ALIGNS.len()],
+                pages: b % 20 + 1,
+            },
+            3 => Self::Snatch {
+                align_order: ALIGNS[a % ALIGNS.len()],
+                pages: b % 4 + 1,
+            },
+            4..=5 => Self::Deallocate {
+                slot: a,
+                keep: if b % 4 == 0 { b / 4 } else { 0 },
+            },
+            6 => Self::Reclaim {
+                page: a % PAGES,
+                pages: b % 16 + 1,
+            },
+            _ => Self::Transfer { slot: a },
+        }
+    }
+
+    /// 把一串字节解码成操作序列。
+    pub fn decode_all(data: &[u8]) -> Vec<Self> {
+        data.chunks_exact(3)
+            .map(|c| Self::decode([c[0], c[1], c[2]]))
+            .collect()
+    }
+}
+
+/// 被测试的分配器，地址都用相对区域起点的偏移表示。
+pub trait Subject {
+    /// 组合的名字。
+    fn name(&self) -> &'static str;
+    /// 是否应该和参照分配器给出完全相同的结果。
+    fn deterministic(&self) -> bool;
+    /// 偏移对应的地址。
+    fn addr(&self, offset: usize) -> usize;
+    fn allocate(&mut self, align_order: usize, size: usize) -> Option<(usize, usize)>;
+    fn snatch(&mut self, align_order: usize, size: usize) -> Option<(usize, usize)>;
+    fn deallocate(&mut self, offset: usize, size: usize);
+    fn transfer(&mut self, offset: usize, size: usize);
+    /// 取回范围，返回取回的字节数和报告的不空闲的段。
+    fn reclaim(&mut self, offset: usize, size: usize) -> (usize, Vec<(usize, usize)>);
+    fn free(&self) -> usize;
+    fn capacity(&self) -> usize;
+}
+
+/// 对齐到区域大小的内存，区域放在其中偏移 `skew` 页处。
+struct Memory {
+    ptr: NonNull<u8>,
+    region: usize,
+}
+
+impl Memory {
+    const LAYOUT: Layout = match Layout::from_size_align(2 * REGION, REGION) {
+        Ok(layout) => layout,
+        Err(_) => panic!(),
+    };
+
+    fn new(skew: usize) -> Self {
+        let ptr = NonNull::new(unsafe { alloc_zeroed(Self::LAYOUT) }).unwrap();
+        let region = ptr.as_ptr() as usize + skew % PAGES * PAGE;
+        Self { ptr, region }
+    }
+
+    fn ptr(&self, offset: usize) -> NonNull<u8> {
+        assert!(offset < REGION);
+        NonNull::new((self.region + offset) as *mut u8).unwrap()
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.ptr.as_ptr(), Self::LAYOUT) }
+    }
+}
+
+struct Buddy<O: OligarchyCollection, B: BuddyCollection> {
+    name: &'static str,
+    deterministic: bool,
+    allocator: BuddyAllocator<LAYERS, O, B>,
+    memory: Memory,
+}
+
+impl<O: OligarchyCollection, B: BuddyCollection> Subject for Buddy<O, B> {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    fn addr(&self, offset: usize) -> usize {
+        self.memory.region + offset
+    }
+
+    fn allocate(&mut self, align_order: usize, size: usize) -> Option<(usize, usize)> {
+        let size = NonZeroUsize::new(size).unwrap();
+        let (ptr, size) = self.allocator.allocate::<u8>(align_order, size).ok()?;
+        Some((ptr.as_ptr() as usize - self.memory.region, size))
+    }
+
+    fn snatch(&mut self, align_order: usize, size: usize) -> Option<(usize, usize)> {
+        let size = NonZeroUsize::new(size).unwrap();
+        let (ptr, size) = self.allocator.snatch::<u8>(align_order, size).ok()?;
+        Some((ptr.as_ptr() as usize - self.memory.region, size))
+    }
+
+    fn deallocate(&mut self, offset: usize, size: usize) {
+        self.allocator.deallocate(self.memory.ptr(offset), size)
+    }
+
+    fn transfer(&mut self, offset: usize, size: usize) {
+        unsafe { self.allocator.transfer(self.memory.ptr(offset), size) }
+    }
+
+    fn reclaim(&mut self, offset: usize, size: usize) -> (usize, Vec<(usize, usize)>) {
+        let region = self.memory.region;
+        let mut busy = Vec::new();
+        let reclaimed = self
+            .allocator
+            .reclaim_range(self.memory.ptr(offset), size, |ptr, len| {
+                busy.push((ptr.as_ptr() as usize - region, len))
+            });
+        (reclaimed, busy)
+    }
+
+    fn free(&self) -> usize {
+        self.allocator.free()
+    }
+
+    fn capacity(&self) -> usize {
+        self.allocator.capacity()
+    }
+}
+
+fn buddy<O, B>(
+    name: &'static str,
+    deterministic: bool,
+    policy: TakePolicy,
+    skew: usize,
+) -> Box<dyn Subject>
+where
+    O: OligarchyCollection + 'static,
+    B: BuddyCollection + 'static,
+{
+    let memory = Memory::new(skew);
+    let mut allocator = BuddyAllocator::<LAYERS, O, B>::new();
+    allocator.set_policy(policy);
+    allocator.init(MIN_ORDER, memory.ptr(0));
+    Box::new(Buddy {
+        name,
+        deterministic,
+        allocator,
+        memory,
+    })
+}
+
+fn with_lines<O: OligarchyCollection + 'static>(
+    o: &'static str,
+    deterministic: bool,
+    policy: TakePolicy,
+    skew: usize,
+    subjects: &mut Vec<Box<dyn Subject>>,
+) {
+    macro_rules! push {
+        ($($b:ty: $name:literal),*) => {
+            $(subjects.push(buddy::<O, $b>(
+                Box::leak(format!("{o}/{}", $name).into_boxed_str()),
+                deterministic,
+                policy,
+                skew,
+            ));)*
+        };
+    }
+    push!(
+        UsizeBuddy: "bitmap",
+        LinkedListBuddy: "linked_list",
+        AvlBuddy: "avl",
+        BTreeBuddy: "btree"
+    );
+}
+
+/// 所有的组合。第一个是参照分配器。
+pub fn subjects(policy: TakePolicy, skew: usize) -> Vec<Box<dyn Subject>> {
+    // 后进先出的链表按放入次序取块，区间树寡头按最佳适配取块，它们只和参照模型比较
+    let exact = policy != TakePolicy::Lifo;
+    let mut subjects = Vec::new();
+    with_lines::<BTreeBuddy>("btree", exact, policy, skew, &mut subjects);
+    subjects.rotate_right(1);
+    with_lines::<UsizeBuddy>("bitmap", exact, policy, skew, &mut subjects);
+    with_lines::<LinkedListBuddy>("linked_list", exact, policy, skew, &mut subjects);
+    with_lines::<AvlBuddy>("avl", exact, policy, skew, &mut subjects);
+    with_lines::<ExtentOligarchy>("extent", false, policy, skew, &mut subjects);
+    subjects
+}
+
+/// 页的状态。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Page {
+    /// 不归分配器管理。
+    Outside,
+    /// 空闲。
+    Free,
+    /// 已分配。
+    Held,
+}
+
+/// 参照模型，逐页记录状态。
+struct Model {
+    pages: [Page; PAGES],
+    /// 持有的块 `(偏移, 长度)`。
+    held: Vec<(usize, usize)>,
+    /// 还没有托管的内存段 `(偏移, 长度)`。
+    pending: Vec<(usize, usize)>,
+}
+
+impl Model {
+    fn new(seed: u64) -> Self {
+        // 把区域切成几段，逐段转移
+        let mut pending = Vec::new();
+        let mut start = 0;
+        let mut seed = seed;
+        while start < PAGES {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            let len = ((seed >> 40) as usize % 24 + 1).min(PAGES - start);
+            pending.push((start * PAGE, len * PAGE));
+            start += len;
+        }
+        Self {
+            pages: [Page::Outside; PAGES],
+            held: Vec::new(),
+            pending,
+        }
+    }
+
+    fn set(&mut self, offset: usize, size: usize, from: Page, to: Page) {
+        for page in &mut self.pages[offset / PAGE..][..size / PAGE] {
+            assert_eq!(*page, from, "page state mismatch");
+            *page = to;
+        }
+    }
+
+    fn count(&self, state: Page) -> usize {
+        self.pages.iter().filter(|&&p| p == state).count() * PAGE
+    }
+}
+
+/// 把 `ops` 施加到所有组合上并检查。
+pub fn run(policy: TakePolicy, skew: usize, ops: &[Op]) {
+    let mut subjects = subjects(policy, skew);
+    let mut models = (0..subjects.len())
+        .map(|_| Model::new(skew as u64))
+        .collect::<Vec<_>>();
+
+    for (step, op) in ops.iter().enumerate() {
+        let mut oracle = None;
+        for (subject, model) in subjects.iter_mut().zip(&mut models) {
+            let name = subject.name();
+            let ctx = || format!("{name} at step {step}: {op:?}");
+            let ans = apply(subject.as_mut(), model, *op);
+            if subject.deterministic() {
+                match oracle {
+                    None => oracle = Some(ans),
+                    Some(expect) => assert_eq!(ans, expect, "{}", ctx()),
+                }
+            }
+            check(subject.as_ref(), model, ctx);
+        }
+    }
+
+    // 归还所有内存，每一页都能再被分配出来，并且只分配一次
+    for (subject, model) in subjects.iter_mut().zip(&mut models) {
+        for (offset, size) in core::mem::take(&mut model.held) {
+            subject.deallocate(offset, size);
+            model.set(offset, size, Page::Held, Page::Free);
+        }
+        for (offset, size) in core::mem::take(&mut model.pending) {
+            subject.transfer(offset, size);
+            model.set(offset, size, Page::Outside, Page::Free);
+        }
+        let name = subject.name();
+        assert_eq!(subject.free(), REGION, "{name} at the end");
+        assert_eq!(subject.capacity(), REGION, "{name} at the end");
+        while let Some((offset, size)) = subject.allocate(0, PAGE) {
+            assert_eq!(size, PAGE, "{name} at the end");
+            model.set(offset, size, Page::Free, Page::Held);
+        }
+        assert_eq!(subject.free(), 0, "{name} at the end");
+    }
+}
+
+fn apply(subject: &mut dyn Subject, model: &mut Model, op: Op) -> Option<(usize, usize)> {
+    match op {
+        Op::Allocate { align_order, pages } | Op::Snatch { align_order, pages } => {
+            let snatch = matches!(op, Op::Snatch { .. });
+            let size = pages * PAGE - PAGE / 2;
+            let ans = if snatch {
+                subject.snatch(align_order, size)
+            } else {
+                subject.allocate(align_order, size)
+            };
+            let (offset, len) = ans?;
+            let name = subject.name();
+            assert_eq!(len, pages * PAGE, "{name}: wrong size of {op:?}");
+            assert_eq!(
+                subject.addr(offset) & ((1 << align_order) - 1),
+                0,
+                "{name}: misaligned {op:?}"
+            );
+            assert!(offset + len <= REGION, "{name}: out of region");
+            // 分配到的每一页都必须是空闲的
+            model.set(offset, len, Page::Free, Page::Held);
+            if snatch {
+                model.set(offset, len, Page::Held, Page::Outside);
+                model.pending.push((offset, len));
+            } else {
+                model.held.push((offset, len));
+            }
+            Some((offset, len))
+        }
+        Op::Deallocate { slot, keep } => {
+            if model.held.is_empty() {
+                return None;
+            }
+            let i = slot % model.held.len();
+            let (offset, len) = model.held[i];
+            let keep = keep * PAGE;
+            let freed = if keep > 0 && keep < len {
+                model.held[i] = (offset + len - keep, keep);
+                len - keep
+            } else {
+                model.held.swap_remove(i);
+                len
+            };
+            subject.deallocate(offset, freed);
+            model.set(offset, freed, Page::Held, Page::Free);
+            Some((offset, freed))
+        }
+        Op::Reclaim { page, pages } => {
+            let offset = page * PAGE;
+            let size = pages.min(PAGES - page) * PAGE;
+            let (reclaimed, busy) = subject.reclaim(offset, size);
+            // 空闲的页全部取回，其他页按连续段报告
+            let mut expect = Vec::<(usize, usize)>::new();
+            let mut freed = 0;
+            let first = model.pending.len();
+            for i in page..page + size / PAGE {
+                if model.pages[i] == Page::Free {
+                    model.pages[i] = Page::Outside;
+                    freed += PAGE;
+                    match model.pending[first..].last_mut() {
+                        Some((start, len)) if *start + *len == i * PAGE => *len += PAGE,
+                        _ => model.pending.push((i * PAGE, PAGE)),
+                    }
+                } else {
+                    match expect.last_mut() {
+                        Some((start, len)) if *start + *len == i * PAGE => *len += PAGE,
+                        _ => expect.push((i * PAGE, PAGE)),
+                    }
+                }
+            }
+            let name = subject.name();
+            assert_eq!(reclaimed, freed, "{name}: reclaimed bytes of {op:?}");
+            assert_eq!(busy, expect, "{name}: busy ranges of {op:?}");
+            Some((offset, reclaimed))
+        }
+        Op::Transfer { slot } => {
+            if model.pending.is_empty() {
+                return None;
+            }
+            let (offset, len) = model.pending.swap_remove(slot % model.pending.len());
+            subject.transfer(offset, len);
+            model.set(offset, len, Page::Outside, Page::Free);
+            Some((offset, len))
+        }
+    }
+}
+
+fn check(subject: &dyn Subject, model: &Model, ctx: impl Fn() -> String) {
+    assert_eq!(subject.free(), model.count(Page::Free), "{}: free", ctx());
+    assert_eq!(
+        subject.capacity(),
+        model.count(Page::Free) + model.count(Page::Held),
+        "{}: capacity",
+        ctx()
+    );
+}
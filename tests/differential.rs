@@ -0,0 +1,53 @@
+//! 确定性的差分属性测试。
+//!
+//! 用固定种子的伪随机数生成操作序列，施加到所有组合上。
+//! 失败时打印种子，可以用同样的种子重现。
+
+mod common;
+
+use common::{Op, run};
+use customizable_buddy::TakePolicy;
+
+/// 线性同余伪随机数发生器。
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> [u8; 3] {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let [_, _, _, _, a, b, c, _] = self.0.to_le_bytes();
+        [a, b, c]
+    }
+}
+
+fn ops(seed: u64, len: usize) -> Vec<Op> {
+    let mut rng = Rng(seed);
+    (0..len).map(|_| Op::decode(rng.next())).collect()
+}
+
+fn check_policy(policy: TakePolicy) {
+    for seed in 0..64 {
+        let result = std::panic::catch_unwind(|| run(policy, seed as usize, &ops(seed, 400)));
+        if let Err(e) = result {
+            eprintln!("failed with {policy:?} seed = {seed}");
+            std::panic::resume_unwind(e);
+        }
+    }
+}
+
+#[test]
+fn differential_lowest() {
+    check_policy(TakePolicy::Lowest);
+}
+
+#[test]
+fn differential_highest() {
+    check_policy(TakePolicy::Highest);
+}
+
+#[test]
+fn differential_lifo() {
+    check_policy(TakePolicy::Lifo);
+}